@@ -0,0 +1,75 @@
+use candid::{CandidType, Deserialize};
+
+// The major version goes up when an endpoint changes shape; the old name then stays
+// behind as a shim listed in DEPRECATED until the next major release. Additions only
+// bump the minor version.
+const MAJOR: u16 = 5;
+const MINOR: u16 = 11;
+
+// (deprecated method, method to call instead)
+const DEPRECATED: &[(&str, &str)] = &[
+    ("create_proposal", "create_proposal_v3"),
+    ("create_proposal_v2", "create_proposal_v3"),
+    ("edit_proposal", "edit_proposal_v2"),
+    ("end_proposal", "end_proposal_v2"),
+    ("get_comments", "get_comments_v2"),
+    ("get_constitution_history", "get_constitution_history_v2"),
+    ("get_events", "get_events_v2"),
+    ("get_events_for_proposal", "get_events_for_proposal_v2"),
+    ("get_followed", "get_followed_v2"),
+    ("get_inbox", "get_inbox_v2"),
+    ("get_moderation_queue", "get_moderation_queue_v2"),
+    ("get_my_votes", "get_my_votes_v2"),
+    ("get_proposal", "get_proposal_v2"),
+    ("get_voters", "get_voters_v2"),
+    ("list_active_proposals", "list_active_proposals_v2"),
+    ("list_archived", "list_archived_v2"),
+    ("list_dead_letters", "list_dead_letters_v2"),
+    ("list_proposals", "list_proposals_v2"),
+    ("list_proposals_by_tag", "list_proposals_by_tag_v2"),
+    ("list_tags", "list_tags_v2"),
+    ("search_proposals", "search_proposals_v2"),
+    ("vote", "vote_v2"),
+];
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DeprecatedMethod {
+    pub method: String,
+    pub replacement: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ApiVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub deprecated: Vec<DeprecatedMethod>,
+}
+
+// Prefixes the generated interface with the version and deprecation notes, so they end
+// up in the .did file and the `candid:service` metadata alongside the methods.
+pub fn annotate(interface: String) -> String {
+    let mut annotated = format!("// API version {}.{}\n", MAJOR, MINOR);
+    for (method, replacement) in DEPRECATED {
+        annotated.push_str(&format!(
+            "// deprecated: {} (use {})\n",
+            method, replacement
+        ));
+    }
+    annotated + &interface
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_api_version() -> ApiVersion {
+    ApiVersion {
+        major: MAJOR,
+        minor: MINOR,
+        deprecated: DEPRECATED
+            .iter()
+            .map(|(method, replacement)| DeprecatedMethod {
+                method: method.to_string(),
+                replacement: replacement.to_string(),
+            })
+            .collect(),
+    }
+}
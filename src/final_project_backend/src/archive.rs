@@ -0,0 +1,202 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::ops::Bound;
+
+use crate::reactions::{self, ReactionTarget};
+use crate::roles::{self, Role};
+use crate::weights::WeightedTally;
+use crate::{
+    ballots, certification, challenges, comments, config, eligibility, env, events, execution,
+    history, limits, load_proposal, memory, metrics, options, seconding, secret, slugs, tags,
+    weights, ErrorCode, Memory, Proposal, ProposalResult, VoteError, ARCHIVE_MEMORY_ID,
+    PROPOSAL_MAP,
+};
+
+// What is left of a closed proposal once it is archived: the outcome, not the ballots.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchivedProposal {
+    pub key: u64,
+    pub title: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub description: String,
+    pub owner: Principal,
+    pub approve: u32,
+    pub reject: u32,
+    pub pass: u32,
+    pub weights: Option<WeightedTally>,
+    pub options: Option<Vec<String>>,
+    pub option_tallies: Option<Vec<u32>>,
+    pub winner: Option<u8>,
+    pub result: ProposalResult,
+    pub created_at: Option<u64>,
+    pub closed_at: Option<u64>,
+    pub archived_at: u64,
+}
+
+impl Default for ArchivedProposal {
+    fn default() -> Self {
+        ArchivedProposal {
+            key: 0,
+            title: None,
+            tags: None,
+            description: String::new(),
+            owner: Principal::anonymous(),
+            approve: 0,
+            reject: 0,
+            pass: 0,
+            weights: None,
+            options: None,
+            option_tallies: None,
+            winner: None,
+            result: ProposalResult::default(),
+            created_at: None,
+            closed_at: None,
+            archived_at: 0,
+        }
+    }
+}
+
+candid_storable!(ArchivedProposal, 4096);
+
+thread_local! {
+    static ARCHIVE: RefCell<StableBTreeMap<u64, ArchivedProposal, Memory>> = RefCell::new(StableBTreeMap::init(memory(ARCHIVE_MEMORY_ID)));
+}
+
+pub fn is_archived(key: u64) -> bool {
+    ARCHIVE.with(|a| a.borrow().contains_key(&key))
+}
+
+// Only the owner or an admin, and only once nothing can change the proposal any more.
+fn check_removable(proposal: &Proposal) -> Result<(), VoteError> {
+    let caller = env::authenticated_caller()?;
+    if proposal.owner != caller && !roles::has_role(&caller, Role::Admin) {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    let settled = !proposal.is_active
+        && !execution::awaiting_execution(proposal)
+        && !matches!(
+            proposal.execution,
+            Some(execution::ExecutionStatus::Pending)
+        )
+        && challenges::window_ends_at(proposal).is_none_or(|ends_at| env::time() >= ends_at);
+    if !settled {
+        return Err(ErrorCode::NotRemovable.into());
+    }
+    Ok(())
+}
+
+// The event log keeps the proposal's history; everything else keyed by it goes.
+fn purge(key: u64, proposal: &Proposal) {
+    PROPOSAL_MAP.with(|p| p.borrow_mut().remove(&key));
+    certification::forget_proposal(key);
+    metrics::proposal_removed(key);
+    ballots::clear(key);
+    weights::clear(key);
+    options::clear(key);
+    secret::clear(key);
+    eligibility::clear(key);
+    comments::clear(key);
+    reactions::clear(ReactionTarget::Proposal(key));
+    history::clear(key);
+    tags::unindex(key, proposal.tags.as_deref().unwrap_or_default());
+    // Only proposals that never closed still hold one.
+    limits::settle_deposit(key, proposal);
+    if let Some(slug) = &proposal.slug {
+        slugs::unindex(slug);
+    }
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn delete_proposal(key: u64) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let proposal = load_proposal(key)?;
+    check_removable(&proposal)?;
+    purge(key, &proposal);
+    events::record(key, events::EventKind::ProposalDeleted);
+    Ok(())
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn archive_proposal(key: u64) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let proposal = load_proposal(key)?;
+    if proposal.closed_at.is_none() || seconding::is_draft(&proposal) {
+        return Err(ErrorCode::NotRemovable.with_message("only closed proposals can be archived"));
+    }
+    check_removable(&proposal)?;
+
+    let archived = ArchivedProposal {
+        key,
+        title: proposal.title.clone(),
+        tags: proposal.tags.clone(),
+        description: proposal.description.clone(),
+        owner: proposal.owner,
+        approve: proposal.approve,
+        reject: proposal.reject,
+        pass: proposal.pass,
+        weights: proposal.weights,
+        options: proposal.options.clone(),
+        option_tallies: proposal.option_tallies.clone(),
+        winner: proposal.runoff.as_ref().and_then(|r| r.winner),
+        result: proposal.result(),
+        created_at: proposal.created_at,
+        closed_at: proposal.closed_at,
+        archived_at: env::time(),
+    };
+    ARCHIVE.with(|a| a.borrow_mut().insert(key, archived));
+    purge(key, &proposal);
+    events::record(key, events::EventKind::ProposalArchived);
+    Ok(())
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_archived(key: u64) -> Option<ArchivedProposal> {
+    ARCHIVE.with(|a| a.borrow().get(&key))
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchivePage {
+    pub archived: Vec<ArchivedProposal>,
+    pub next: Option<u64>,
+}
+
+pub fn archive_page(after: Option<u64>, page: usize) -> ArchivePage {
+    let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+    let (archived, next) = ARCHIVE.with(|a| {
+        config::scan_page(
+            a.borrow().range((start, Bound::Unbounded)),
+            page,
+            |_, archived| Some(archived),
+        )
+    });
+    ArchivePage { archived, next }
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_archived_v2(after: Option<u64>, limit: u64) -> ArchivePage {
+    archive_page(after, config::page_limit(limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{as_caller, imported, open_proposal};
+
+    #[test]
+    fn an_archived_key_stays_taken() {
+        as_caller(1);
+        let key = open_proposal("archived");
+        crate::end_proposal_v2(key).unwrap();
+        archive_proposal(key).unwrap();
+        assert!(matches!(
+            crate::import(imported(key)),
+            Err(VoteError::KeyAlreadyExists(_))
+        ));
+        assert_ne!(open_proposal("after"), key);
+    }
+}
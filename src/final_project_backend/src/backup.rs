@@ -0,0 +1,130 @@
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::Memory as _;
+use serde_bytes::ByteBuf;
+
+use crate::{config, env, memory, roles, validation, ErrorCode, VoteError, PROPOSAL_MAP};
+
+// Goes up whenever a chunk changes shape; imports only accept their own version.
+const STATE_FORMAT_VERSION: u16 = 2;
+const WASM_PAGE_BYTES: u64 = 64 * 1024;
+const CHUNK_BYTES: u64 = 1024 * 1024;
+const MAX_CHUNKS_PER_IMPORT: usize = 1;
+// 255 is reserved by the memory manager.
+const MEMORY_IDS: std::ops::RangeInclusive<u8> = 0..=254;
+
+// Every map, cell and log lives in one of the memory manager's virtual memories, so
+// the export is those memories byte for byte, in id order. Stored records carry their
+// own schema version and are migrated by `post_upgrade` like any other old data.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ExportChunk {
+    pub format_version: u16,
+    pub index: u64,
+    pub total_chunks: u64,
+    pub memory_id: u8,
+    pub memory_pages: u64,
+    pub offset: u64,
+    pub bytes: ByteBuf,
+}
+
+fn pages_of(id: u8) -> u64 {
+    memory(MemoryId::new(id)).size()
+}
+
+fn chunks_of(pages: u64) -> u64 {
+    (pages * WASM_PAGE_BYTES).div_ceil(CHUNK_BYTES)
+}
+
+// Pausing stops endpoints and timers alike, so the memories hold still between export
+// calls.
+fn require_paused() -> Result<(), VoteError> {
+    if config::get().paused {
+        Ok(())
+    } else {
+        Err(ErrorCode::NotPaused.into())
+    }
+}
+
+fn chunk(mut index: u64) -> Option<ExportChunk> {
+    let total_chunks = MEMORY_IDS.map(|id| chunks_of(pages_of(id))).sum();
+    let requested = index;
+    for id in MEMORY_IDS {
+        let memory_pages = pages_of(id);
+        let chunks = chunks_of(memory_pages);
+        if index >= chunks {
+            index -= chunks;
+            continue;
+        }
+        let offset = index * CHUNK_BYTES;
+        let mut bytes = vec![0; CHUNK_BYTES.min(memory_pages * WASM_PAGE_BYTES - offset) as usize];
+        memory(MemoryId::new(id)).read(offset, &mut bytes);
+        return Some(ExportChunk {
+            format_version: STATE_FORMAT_VERSION,
+            index: requested,
+            total_chunks,
+            memory_id: id,
+            memory_pages,
+            offset,
+            bytes: ByteBuf::from(bytes),
+        });
+    }
+    None
+}
+
+// Returns None past the last chunk.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn export_state(chunk_index: u64) -> Result<Option<ExportChunk>, VoteError> {
+    roles::require_admin()?;
+    require_paused()?;
+    Ok(chunk(chunk_index))
+}
+
+fn check_chunk(chunk: &ExportChunk) -> Result<(), VoteError> {
+    if chunk.format_version != STATE_FORMAT_VERSION {
+        return Err(ErrorCode::InvalidStateChunk
+            .with_message(format!(
+                "this canister imports format version {}",
+                STATE_FORMAT_VERSION
+            ))
+            .with_field("format_version"));
+    }
+    let end = chunk.offset.saturating_add(chunk.bytes.len() as u64);
+    if chunk.memory_id == 255 || end > chunk.memory_pages.saturating_mul(WASM_PAGE_BYTES) {
+        return Err(ErrorCode::InvalidStateChunk
+            .with_message("the chunk lies outside its memory")
+            .with_field("offset"));
+    }
+    Ok(())
+}
+
+// Chunks are plain writes at their offset, so they can be sent in any order and sent
+// again after a failure. The heap still holds the structures as they were before the
+// import, which is why it is controller-only rather than checked against roles that
+// the import overwrites, and why it only ends with an upgrade to the same wasm: that
+// loads every structure from the imported memories, migrates them and re-arms the
+// timers. The exported canister's admins come with its roles.
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn import_state(chunks: Vec<ExportChunk>) -> Result<u64, VoteError> {
+    env::require_controller()?;
+    require_paused()?;
+    // The length is cached on the heap, so it stays zero until the upgrade.
+    if !PROPOSAL_MAP.with(|p| p.borrow().is_empty()) {
+        return Err(ErrorCode::InvalidStateChunk
+            .with_message("state can only be imported into a fresh canister"));
+    }
+    validation::item_count("chunks", chunks.len(), MAX_CHUNKS_PER_IMPORT)?;
+    let mut written = 0;
+    for chunk in chunks {
+        check_chunk(&chunk)?;
+        let target = memory(MemoryId::new(chunk.memory_id));
+        let missing = chunk.memory_pages.saturating_sub(target.size());
+        if missing > 0 && target.grow(missing) < 0 {
+            return Err(ErrorCode::UpdateError.with_message("stable memory could not grow"));
+        }
+        target.write(chunk.offset, &chunk.bytes);
+        written += chunk.bytes.len() as u64;
+    }
+    Ok(written)
+}
@@ -0,0 +1,299 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::ops::Bound;
+
+use crate::{
+    config, env, identity, load_proposal, memory, metrics, principal_from_key, principal_key,
+    Choice, ErrorCode, Memory, PrincipalKey, Proposal, VoteError, BALLOTS_MEMORY_ID,
+    VOTERS_MEMORY_ID, VOTES_BY_VOTER_MEMORY_ID,
+};
+
+// One byte per ballot, where the Candid encoding of the variant takes 28.
+impl Storable for Choice {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let byte = match self {
+            Choice::Approve => 0,
+            Choice::Reject => 1,
+            Choice::Pass => 2,
+        };
+        Cow::Owned(vec![byte])
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        match bytes.first() {
+            Some(0) => Choice::Approve,
+            Some(1) => Choice::Reject,
+            _ => Choice::Pass,
+        }
+    }
+}
+
+impl BoundedStorable for Choice {
+    const MAX_SIZE: u32 = 1;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+// Choices are only known for approve/reject/pass ballots, and times only for votes
+// cast since the index was added.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct VoteRecord {
+    pub choice: Option<Choice>,
+    pub voted_at: Option<u64>,
+}
+
+candid_storable!(VoteRecord, 64);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct MyVote {
+    pub key: u64,
+    pub choice: Option<Choice>,
+    pub voted_at: Option<u64>,
+}
+
+thread_local! {
+    // How each voter voted, for approve/reject/pass ballots.
+    static BALLOTS: RefCell<StableBTreeMap<(u64, PrincipalKey), Choice, Memory>> = RefCell::new(StableBTreeMap::init(memory(BALLOTS_MEMORY_ID)));
+
+    // Everyone who voted, whatever the ballot; kept out of `Proposal` so the number of
+    // voters is not limited by its size bound.
+    static VOTERS: RefCell<StableBTreeMap<(u64, PrincipalKey), (), Memory>> = RefCell::new(StableBTreeMap::init(memory(VOTERS_MEMORY_ID)));
+
+    // The same entries keyed by voter first, for "what did I vote on".
+    static VOTES_BY_VOTER: RefCell<StableBTreeMap<(PrincipalKey, u64), VoteRecord, Memory>> = RefCell::new(StableBTreeMap::init(memory(VOTES_BY_VOTER_MEMORY_ID)));
+}
+
+fn keys_of<V: ic_stable_structures::BoundedStorable>(
+    map: &StableBTreeMap<(u64, PrincipalKey), V, Memory>,
+    key: u64,
+) -> impl Iterator<Item = PrincipalKey> + '_ {
+    map.range((key, PrincipalKey::default())..)
+        .take_while(move |((k, _), _)| *k == key)
+        .map(|((_, voter), _)| voter)
+}
+
+fn votes_of(
+    index: &StableBTreeMap<(PrincipalKey, u64), VoteRecord, Memory>,
+    voter: PrincipalKey,
+) -> bool {
+    index.range((voter, 0)..=(voter, u64::MAX)).next().is_some()
+}
+
+fn index(key: u64, voter: PrincipalKey, choice: Option<Choice>) {
+    let first = VOTES_BY_VOTER.with(|i| {
+        let mut index = i.borrow_mut();
+        let first = !votes_of(&index, voter);
+        index.insert(
+            (voter, key),
+            VoteRecord {
+                choice,
+                voted_at: Some(env::time()),
+            },
+        );
+        first
+    });
+    if first {
+        metrics::voter_added();
+    }
+}
+
+fn unindex(key: u64, voter: PrincipalKey) {
+    let last = VOTES_BY_VOTER.with(|i| {
+        let mut index = i.borrow_mut();
+        index.remove(&(voter, key)).is_some() && !votes_of(&index, voter)
+    });
+    if last {
+        metrics::voter_removed();
+    }
+}
+
+pub fn add_voter(key: u64, voter: &Principal) {
+    let voter = principal_key(voter);
+    VOTERS.with(|v| v.borrow_mut().insert((key, voter), ()));
+    if !VOTES_BY_VOTER.with(|i| i.borrow().contains_key(&(voter, key))) {
+        index(key, voter, None);
+    }
+}
+
+// Votes recorded before the index existed are indexed once, on upgrade.
+pub fn index_unindexed() {
+    if !VOTES_BY_VOTER.with(|i| i.borrow().is_empty()) {
+        return;
+    }
+    VOTERS.with(|v| {
+        for ((key, voter), _) in v.borrow().iter() {
+            let choice = BALLOTS.with(|b| b.borrow().get(&(key, voter)));
+            let record = VoteRecord {
+                choice,
+                voted_at: None,
+            };
+            VOTES_BY_VOTER.with(|i| i.borrow_mut().insert((voter, key), record));
+        }
+    });
+}
+
+pub fn has_voted(key: u64, voter: &Principal) -> bool {
+    VOTERS.with(|v| v.borrow().contains_key(&(key, principal_key(voter))))
+}
+
+pub fn has_voted_anywhere(voter: &Principal) -> bool {
+    VOTES_BY_VOTER.with(|i| votes_of(&i.borrow(), principal_key(voter)))
+}
+
+pub fn voters(key: u64) -> Vec<Principal> {
+    VOTERS.with(|v| {
+        keys_of(&v.borrow(), key)
+            .map(|voter| principal_from_key(&voter))
+            .collect()
+    })
+}
+
+// Who voted is the owner's to share, as with `voters.csv`.
+pub fn may_list_voters(proposal: &Proposal, caller: &Principal) -> bool {
+    proposal.owner == *caller
+}
+
+pub fn has_voters(key: u64) -> bool {
+    VOTERS.with(|v| keys_of(&v.borrow(), key).next().is_some())
+}
+
+pub fn vote_count() -> u64 {
+    VOTERS.with(|v| v.borrow().len())
+}
+
+pub fn unique_voter_count() -> u64 {
+    VOTERS.with(|v| {
+        v.borrow()
+            .iter()
+            .map(|((_, voter), _)| voter)
+            .collect::<BTreeSet<_>>()
+            .len() as u64
+    })
+}
+
+pub fn record(key: u64, voter: &Principal, choice: Choice) {
+    let voter = principal_key(voter);
+    VOTERS.with(|v| v.borrow_mut().insert((key, voter), ()));
+    index(key, voter, Some(choice.clone()));
+    BALLOTS.with(|b| b.borrow_mut().insert((key, voter), choice));
+}
+
+// Ballots cast before choices were recorded have a voter but no choice.
+pub fn of(key: u64, voter: &Principal) -> Option<Choice> {
+    BALLOTS.with(|b| b.borrow().get(&(key, principal_key(voter))))
+}
+
+pub fn remove(key: u64, voter: &Principal) {
+    let voter = (key, principal_key(voter));
+    BALLOTS.with(|b| b.borrow_mut().remove(&voter));
+    VOTERS.with(|v| v.borrow_mut().remove(&voter));
+    unindex(key, voter.1);
+}
+
+pub fn clear(key: u64) {
+    BALLOTS.with(|b| {
+        let mut ballots = b.borrow_mut();
+        let voters: Vec<PrincipalKey> = keys_of(&ballots, key).collect();
+        for voter in voters {
+            ballots.remove(&(key, voter));
+        }
+    });
+    VOTERS.with(|v| {
+        let mut all = v.borrow_mut();
+        let voters: Vec<PrincipalKey> = keys_of(&all, key).collect();
+        for voter in voters {
+            all.remove(&(key, voter));
+            unindex(key, voter);
+        }
+    });
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct VoterPage {
+    pub voters: Vec<Principal>,
+    // The last voter listed.
+    pub next: Option<Principal>,
+}
+
+// Up to `page` voters after `after`, and the one to resume after while more remain.
+pub fn voters_after(
+    key: u64,
+    after: Option<Principal>,
+    page: usize,
+) -> (Vec<Principal>, Option<Principal>) {
+    let start = after.map_or(Bound::Included((key, PrincipalKey::default())), |voter| {
+        Bound::Excluded((key, principal_key(&voter)))
+    });
+    let (voters, next) = VOTERS.with(|v| {
+        config::scan_page(
+            v.borrow()
+                .range((start, Bound::Unbounded))
+                .take_while(|((k, _), _)| *k == key),
+            page,
+            |(_, voter), _| Some(principal_from_key(voter)),
+        )
+    });
+    (voters, next.map(|(_, voter)| principal_from_key(&voter)))
+}
+
+pub fn voter_page(key: u64, after: Option<Principal>, page: usize) -> Result<VoterPage, VoteError> {
+    let caller = env::authenticated_caller()?;
+    if !may_list_voters(&load_proposal(key)?, &caller) {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    let (voters, next) = voters_after(key, after, page);
+    Ok(VoterPage { voters, next })
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_voters_v2(key: u64, after: Option<Principal>, limit: u64) -> Result<VoterPage, VoteError> {
+    voter_page(key, after, config::page_limit(limit))
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct MyVotePage {
+    pub votes: Vec<MyVote>,
+    // The proposal key of the last vote listed.
+    pub next: Option<u64>,
+}
+
+pub fn my_vote_page(after: Option<u64>, page: usize) -> MyVotePage {
+    let voter = principal_key(&identity::canonical(&env::caller()));
+    let start = after.map_or(Bound::Included((voter, 0)), |key| {
+        Bound::Excluded((voter, key))
+    });
+    let (votes, next) = VOTES_BY_VOTER.with(|i| {
+        config::scan_page(
+            i.borrow()
+                .range((start, Bound::Included((voter, u64::MAX)))),
+            page,
+            |(_, key), record| {
+                Some(MyVote {
+                    key: *key,
+                    choice: record.choice,
+                    voted_at: record.voted_at,
+                })
+            },
+        )
+    });
+    MyVotePage {
+        votes,
+        next: next.map(|(_, key)| key),
+    }
+}
+
+// In proposal order.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_my_votes_v2(after: Option<u64>, limit: u64) -> MyVotePage {
+    my_vote_page(after, config::page_limit(limit))
+}
+
+#[ic_cdk::query(name = "has_voted")]
+#[candid::candid_method(query, rename = "has_voted")]
+fn caller_has_voted(key: u64) -> bool {
+    has_voted(key, &identity::canonical(&env::caller()))
+}
@@ -0,0 +1,473 @@
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use crate::events::{self, Event};
+use crate::{
+    config, load_proposal, memory, Memory, Proposal, ProposalStore, EVENT_SEGMENTS_MEMORY_ID,
+    PROPOSAL_MAP, PROPOSAL_TREE_MEMORY_ID,
+};
+
+const SEGMENT_SIZE: u64 = 64;
+const SEAL_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const MAX_SEGMENTS_PER_RUN: usize = 16;
+const TREE_DEPTH: u8 = 64;
+
+type Hash = [u8; 32];
+type LeafHash = Blob<32>;
+
+#[derive(CandidType, Deserialize, Clone, Default)]
+struct Segment {
+    root: ByteBuf,
+    // Concatenated 32-byte leaf hashes, kept so proofs survive pruning of the raw events.
+    leaves: ByteBuf,
+}
+
+candid_storable!(Segment, 2400);
+
+impl Segment {
+    fn leaves(&self) -> Vec<Hash> {
+        self.leaves
+            .chunks_exact(32)
+            .map(|chunk| chunk.try_into().unwrap())
+            .collect()
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProofStep {
+    pub sibling: ByteBuf,
+    pub sibling_on_left: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EventProof {
+    pub event: Event,
+    pub event_bytes: ByteBuf,
+    pub segment: u64,
+    pub segment_path: Vec<ProofStep>,
+    pub segment_root: ByteBuf,
+    pub roots_path: Vec<ProofStep>,
+    pub events_root: ByteBuf,
+    pub proposals_root: ByteBuf,
+    pub certified_root: ByteBuf,
+    pub certificate: Option<ByteBuf>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProposalCertificate {
+    pub key: u64,
+    pub proposal: Proposal,
+    pub proposal_bytes: ByteBuf,
+    pub path: Vec<ProofStep>,
+    pub events_root: ByteBuf,
+    pub proposals_root: ByteBuf,
+    pub certified_root: ByteBuf,
+    pub certificate: Option<ByteBuf>,
+}
+
+thread_local! {
+    static SEGMENTS: RefCell<StableBTreeMap<u64, Segment, Memory>> = RefCell::new(StableBTreeMap::init(memory(EVENT_SEGMENTS_MEMORY_ID)));
+
+    // Proposals sit in a sparse Merkle tree of depth 64 with the key as the leaf index, so
+    // a write rehashes the 64 nodes above its leaf and nothing else. Nodes are keyed by
+    // (height, index), and only those that differ from an empty subtree are stored.
+    static PROPOSAL_TREE: RefCell<StableBTreeMap<(u8, u64), LeafHash, Memory>> = RefCell::new(StableBTreeMap::init(memory(PROPOSAL_TREE_MEMORY_ID)));
+
+    // The hash of an empty subtree of each height; an empty leaf is all zeroes.
+    static EMPTY_SUBTREES: [Hash; TREE_DEPTH as usize + 1] = {
+        let mut empty = [[0; 32]; TREE_DEPTH as usize + 1];
+        for height in 1..empty.len() {
+            empty[height] = hash_node(&empty[height - 1], &empty[height - 1]);
+        }
+        empty
+    };
+
+    // Only sealing changes the events root.
+    static EVENTS_ROOT: Cell<Option<Hash>> = const { Cell::new(None) };
+}
+
+// Leaves are `sha256(0x00 || candid(event))` and inner nodes `sha256(0x01 || left || right)`;
+// in the event trees an odd node at the end of a level is carried up unchanged.
+fn hash_leaf(data: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+fn hash_node(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_node(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return [0; 32];
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+fn merkle_path(leaves: &[Hash], mut index: usize) -> Vec<ProofStep> {
+    let mut path = Vec::new();
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if sibling < level.len() {
+            path.push(ProofStep {
+                sibling: ByteBuf::from(level[sibling].to_vec()),
+                sibling_on_left: sibling < index,
+            });
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+    path
+}
+
+fn event_bytes(event: &Event) -> Vec<u8> {
+    candid::encode_one(event).expect("failed to encode event")
+}
+
+fn proposal_bytes(proposal: &Proposal) -> Vec<u8> {
+    candid::encode_one(proposal).expect("failed to encode proposal")
+}
+
+// `sha256(0x00 || key as 8 big-endian bytes || candid(proposal))`, so a leaf can't be
+// replayed under another key.
+fn proposal_leaf(key: u64, bytes: &[u8]) -> Hash {
+    hash_leaf(&[key.to_be_bytes().as_slice(), bytes].concat())
+}
+
+fn to_hash(blob: &LeafHash) -> Hash {
+    blob.as_slice().try_into().expect("hashes are 32 bytes")
+}
+
+fn empty_subtree(height: u8) -> Hash {
+    EMPTY_SUBTREES.with(|empty| empty[height as usize])
+}
+
+fn node(height: u8, index: u64) -> Hash {
+    PROPOSAL_TREE
+        .with(|t| t.borrow().get(&(height, index)))
+        .map(|hash| to_hash(&hash))
+        .unwrap_or_else(|| empty_subtree(height))
+}
+
+fn set_node(height: u8, index: u64, hash: Hash) {
+    PROPOSAL_TREE.with(|t| {
+        let mut tree = t.borrow_mut();
+        if hash == empty_subtree(height) {
+            tree.remove(&(height, index));
+        } else {
+            let hash = LeafHash::try_from(hash.as_slice()).expect("hashes are 32 bytes");
+            tree.insert((height, index), hash);
+        }
+    });
+}
+
+// Writing the empty leaf removes the proposal from the tree.
+fn set_leaf(key: u64, leaf: Hash) {
+    let mut hash = leaf;
+    let mut index = key;
+    set_node(0, index, hash);
+    for height in 0..TREE_DEPTH {
+        let sibling = node(height, index ^ 1);
+        hash = if index & 1 == 0 {
+            hash_node(&hash, &sibling)
+        } else {
+            hash_node(&sibling, &hash)
+        };
+        index >>= 1;
+        set_node(height + 1, index, hash);
+    }
+}
+
+fn proposals_root() -> Hash {
+    node(TREE_DEPTH, 0)
+}
+
+fn proposal_path(key: u64) -> Vec<ProofStep> {
+    (0..TREE_DEPTH)
+        .map(|height| {
+            let index = key >> height;
+            ProofStep {
+                sibling: ByteBuf::from(node(height, index ^ 1).to_vec()),
+                sibling_on_left: index & 1 == 1,
+            }
+        })
+        .collect()
+}
+
+fn segment_roots() -> Vec<Hash> {
+    SEGMENTS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, segment)| segment.root.as_slice().try_into().unwrap())
+            .collect()
+    })
+}
+
+fn events_root() -> Hash {
+    if let Some(root) = EVENTS_ROOT.get() {
+        return root;
+    }
+    let root = merkle_root(&segment_roots());
+    EVENTS_ROOT.set(Some(root));
+    root
+}
+
+// The certified data is `hash_node(events root, proposals root)`.
+fn certified_root(events_root: &Hash, proposals_root: &Hash) -> Hash {
+    hash_node(events_root, proposals_root)
+}
+
+pub fn refresh_certified_data() {
+    // Unit tests run outside a canister, which has no certified data to set.
+    #[cfg(not(test))]
+    ic_cdk::api::set_certified_data(&certified_root(&events_root(), &proposals_root()));
+}
+
+pub fn certify_proposal(key: u64, proposal: &Proposal) {
+    set_leaf(key, proposal_leaf(key, &proposal_bytes(proposal)));
+    refresh_certified_data();
+}
+
+pub fn forget_proposal(key: u64) {
+    set_leaf(key, empty_subtree(0));
+    refresh_certified_data();
+}
+
+// The candid encoding changes whenever `Proposal` gains a field, so every leaf is
+// checked after an upgrade. Only changed leaves, and those of proposals that are gone
+// or no longer decode, have their paths rehashed.
+pub fn rehash_proposals() {
+    let mut current = BTreeSet::new();
+    PROPOSAL_MAP.with(|p| {
+        for (key, proposal) in p.borrow().decoded() {
+            let leaf = proposal_leaf(key, &proposal_bytes(&proposal));
+            if node(0, key) != leaf {
+                set_leaf(key, leaf);
+            }
+            current.insert(key);
+        }
+    });
+    let stale: Vec<u64> = PROPOSAL_TREE.with(|t| {
+        t.borrow()
+            .range((0, 0)..=(0, u64::MAX))
+            .map(|((_, key), _)| key)
+            .filter(|key| !current.contains(key))
+            .collect()
+    });
+    for key in stale {
+        set_leaf(key, empty_subtree(0));
+    }
+}
+
+pub fn sealed_until() -> u64 {
+    SEGMENTS.with(|s| s.borrow().len()) * SEGMENT_SIZE
+}
+
+pub fn seal_segments() {
+    let mut sealed = 0;
+    while sealed < MAX_SEGMENTS_PER_RUN {
+        let start = sealed_until();
+        if start + SEGMENT_SIZE > events::next_seq() {
+            break;
+        }
+
+        let leaves: Vec<Hash> = (start..start + SEGMENT_SIZE)
+            .map(|seq| {
+                let event = events::get(seq).expect("unsealed events are never pruned");
+                hash_leaf(&event_bytes(&event))
+            })
+            .collect();
+        let segment = Segment {
+            root: ByteBuf::from(merkle_root(&leaves).to_vec()),
+            leaves: ByteBuf::from(leaves.concat()),
+        };
+        SEGMENTS.with(|s| s.borrow_mut().insert(start / SEGMENT_SIZE, segment));
+        EVENTS_ROOT.set(None);
+        sealed += 1;
+    }
+
+    if sealed > 0 {
+        refresh_certified_data();
+    }
+}
+
+pub fn start_sealing_timer() {
+    ic_cdk_timers::set_timer_interval(SEAL_INTERVAL, || {
+        if !config::get().paused {
+            seal_segments();
+        }
+    });
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_event_proof(seq: u64) -> Option<EventProof> {
+    let event = events::get(seq)?;
+    let index = seq / SEGMENT_SIZE;
+    let segment = SEGMENTS.with(|s| s.borrow().get(&index))?;
+    let roots = segment_roots();
+    let events_root = merkle_root(&roots);
+    let proposals_root = proposals_root();
+
+    Some(EventProof {
+        event_bytes: ByteBuf::from(event_bytes(&event)),
+        event,
+        segment: index,
+        segment_path: merkle_path(&segment.leaves(), (seq % SEGMENT_SIZE) as usize),
+        segment_root: segment.root,
+        roots_path: merkle_path(&roots, index as usize),
+        events_root: ByteBuf::from(events_root.to_vec()),
+        proposals_root: ByteBuf::from(proposals_root.to_vec()),
+        certified_root: ByteBuf::from(certified_root(&events_root, &proposals_root).to_vec()),
+        certificate: ic_cdk::api::data_certificate().map(ByteBuf::from),
+    })
+}
+
+// The path holds the 64 siblings from the proposal's leaf up to `proposals_root`, lowest
+// first; verifiers then check `certified_root` against the certificate's certified data.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_proposal_certified(key: u64) -> Option<ProposalCertificate> {
+    let proposal = load_proposal(key).ok()?;
+    if node(0, key) == empty_subtree(0) {
+        return None;
+    }
+    let events_root = events_root();
+    let proposals_root = proposals_root();
+
+    Some(ProposalCertificate {
+        key,
+        proposal_bytes: ByteBuf::from(proposal_bytes(&proposal)),
+        proposal,
+        path: proposal_path(key),
+        events_root: ByteBuf::from(events_root.to_vec()),
+        proposals_root: ByteBuf::from(proposals_root.to_vec()),
+        certified_root: ByteBuf::from(certified_root(&events_root, &proposals_root).to_vec()),
+        certificate: ic_cdk::api::data_certificate().map(ByteBuf::from),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{as_caller, open_proposal};
+
+    fn leaf(byte: u8) -> Hash {
+        hash_leaf(&[byte])
+    }
+
+    fn fold(leaf: Hash, path: &[ProofStep]) -> Hash {
+        path.iter().fold(leaf, |hash, step| {
+            let sibling: Hash = step.sibling.as_slice().try_into().unwrap();
+            if step.sibling_on_left {
+                hash_node(&sibling, &hash)
+            } else {
+                hash_node(&hash, &sibling)
+            }
+        })
+    }
+
+    #[test]
+    fn an_odd_node_is_carried_up() {
+        let (a, b, c) = (leaf(1), leaf(2), leaf(3));
+        assert_eq!(merkle_root(&[]), [0; 32]);
+        assert_eq!(merkle_root(&[a]), a);
+        assert_eq!(merkle_root(&[a, b, c]), hash_node(&hash_node(&a, &b), &c));
+    }
+
+    #[test]
+    fn every_event_path_leads_to_the_root() {
+        let leaves: Vec<Hash> = (0..7).map(leaf).collect();
+        let root = merkle_root(&leaves);
+        for (index, leaf) in leaves.iter().enumerate() {
+            assert_eq!(fold(*leaf, &merkle_path(&leaves, index)), root);
+        }
+        assert_ne!(fold(leaf(9), &merkle_path(&leaves, 0)), root);
+    }
+
+    #[test]
+    fn leaves_differ_by_key() {
+        assert_ne!(proposal_leaf(1, b"proposal"), proposal_leaf(2, b"proposal"));
+    }
+
+    #[test]
+    fn proposal_paths_lead_to_the_proposals_root() {
+        as_caller(1);
+        let first = open_proposal("first");
+        let second = open_proposal("second");
+        let root = proposals_root();
+        assert_ne!(root, empty_subtree(TREE_DEPTH));
+        for key in [first, second] {
+            let path = proposal_path(key);
+            assert_eq!(path.len(), TREE_DEPTH as usize);
+            assert_eq!(fold(node(0, key), &path), root);
+        }
+    }
+
+    #[test]
+    fn forgetting_every_proposal_empties_the_tree() {
+        as_caller(1);
+        let first = open_proposal("first");
+        let with_first = proposals_root();
+        let second = open_proposal("second");
+        forget_proposal(second);
+        assert_eq!(proposals_root(), with_first);
+        forget_proposal(first);
+        assert_eq!(proposals_root(), empty_subtree(TREE_DEPTH));
+        assert!(PROPOSAL_TREE.with(|t| t.borrow().is_empty()));
+    }
+
+    #[test]
+    fn rehashing_drops_proposals_that_are_gone() {
+        as_caller(1);
+        let key = open_proposal("kept");
+        let root = proposals_root();
+        set_leaf(key + 1, leaf(0));
+        assert_ne!(proposals_root(), root);
+        rehash_proposals();
+        assert_eq!(proposals_root(), root);
+    }
+
+    #[test]
+    fn full_segments_are_sealed() {
+        as_caller(1);
+        for _ in 0..SEGMENT_SIZE {
+            events::record(0, events::EventKind::ProposalCreated);
+        }
+        seal_segments();
+        assert_eq!(sealed_until(), SEGMENT_SIZE);
+        let segment = SEGMENTS.with(|s| s.borrow().get(&0)).unwrap();
+        let leaves = segment.leaves();
+        let event = hash_leaf(&event_bytes(&events::get(5).unwrap()));
+        assert_eq!(fold(event, &merkle_path(&leaves, 5)), merkle_root(&leaves));
+        assert_eq!(events_root(), merkle_root(&leaves));
+    }
+}
@@ -0,0 +1,218 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::execution::ExecutionStatus;
+use crate::{
+    ballots, config, elections, env, events, follows, identity, load_proposal, memory, options,
+    principal_key, secret, tracks, treasury, update_proposal, validation, weights, ErrorCode,
+    Memory, PrincipalKey, Proposal, VoteError, CHALLENGES_MEMORY_ID,
+};
+
+const MAX_CHALLENGE_REASON_BYTES: usize = 500;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Challenge {
+    pub challenger: Principal,
+    pub reason: String,
+    pub deposit: u64,
+    pub filed_at: u64,
+}
+
+impl Default for Challenge {
+    fn default() -> Self {
+        Challenge {
+            challenger: Principal::anonymous(),
+            reason: String::new(),
+            deposit: 0,
+            filed_at: 0,
+        }
+    }
+}
+
+candid_storable!(Challenge, 700);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Dispute {
+    pub reopened_at: u64,
+    pub challengers: Vec<Principal>,
+    pub approve: u32,
+    pub reject: u32,
+    pub pass: u32,
+}
+
+thread_local! {
+    static CHALLENGES: RefCell<StableBTreeMap<(u64, PrincipalKey), Challenge, Memory>> = RefCell::new(StableBTreeMap::init(memory(CHALLENGES_MEMORY_ID)));
+}
+
+// None until the proposal has closed.
+pub fn window_ends_at(proposal: &Proposal) -> Option<u64> {
+    let window = config::get()
+        .challenge_window_secs
+        .saturating_mul(NANOS_PER_SEC);
+    Some(proposal.closed_at?.saturating_add(window))
+}
+
+fn challenges_of(key: u64) -> Vec<Challenge> {
+    CHALLENGES.with(|c| {
+        c.borrow()
+            .range((key, PrincipalKey::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|(_, challenge)| challenge)
+            .collect()
+    })
+}
+
+// A proposal gets one second vote at most, and only while nothing has run yet.
+fn check_challengeable(proposal: &Proposal) -> Result<(), VoteError> {
+    if proposal.is_active || proposal.closed_at.is_none() {
+        return Err(ErrorCode::ProposalIsNotActive.into());
+    }
+    if elections::is_election(proposal)
+        || tracks::is_emergency(proposal)
+        || proposal.disputes.as_ref().is_some_and(|d| !d.is_empty())
+        || !matches!(
+            proposal.execution,
+            None | Some(ExecutionStatus::Queued { .. })
+        )
+    {
+        return Err(ErrorCode::NotChallengeable.into());
+    }
+    if window_ends_at(proposal).is_none_or(|ends_at| env::time() >= ends_at) {
+        return Err(ErrorCode::ChallengeWindowClosed.into());
+    }
+    Ok(())
+}
+
+async fn refund(challenges: Vec<Challenge>) {
+    for challenge in challenges {
+        treasury::refund_deposit(challenge.challenger, challenge.deposit).await;
+    }
+}
+
+// A proposal with a deadline gets as long again as it had the first time.
+fn reopen(key: u64) -> Option<Vec<Challenge>> {
+    let challenges = challenges_of(key);
+    let reopened = update_proposal(key, |proposal| {
+        check_challengeable(proposal)?;
+        let now = env::time();
+        if let Some(ends_at) = proposal.voting_ends_at {
+            let opened_at = proposal
+                .voting_opened_at
+                .or(proposal.created_at)
+                .unwrap_or(ends_at);
+            proposal.voting_ends_at = Some(now.saturating_add(ends_at.saturating_sub(opened_at)));
+        }
+        proposal.voting_opened_at = Some(now);
+        proposal
+            .disputes
+            .get_or_insert_with(Vec::new)
+            .push(Dispute {
+                reopened_at: now,
+                challengers: challenges.iter().map(|c| c.challenger).collect(),
+                approve: proposal.approve,
+                reject: proposal.reject,
+                pass: proposal.pass,
+            });
+        proposal.is_active = true;
+        proposal.approve = 0;
+        proposal.reject = 0;
+        proposal.pass = 0;
+        if proposal.weights.is_some() {
+            proposal.weights = Some(Default::default());
+        }
+        ballots::clear(key);
+        weights::clear_cast(key);
+        options::clear(key);
+        secret::clear(key);
+        proposal.runoff = None;
+        if let Some(tallies) = proposal.option_tallies.as_mut() {
+            tallies.iter_mut().for_each(|tally| *tally = 0);
+        }
+        proposal.closed_at = None;
+        proposal.result = None;
+        proposal.execution = None;
+        Ok(proposal.clone())
+    });
+    let reopened = reopened.ok()?;
+    tracks::schedule_close(key, &reopened);
+    Some(challenges)
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+async fn challenge_proposal(key: u64, reason: String) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let challenger = env::authenticated_caller()?;
+    validation::text("reason", &reason, MAX_CHALLENGE_REASON_BYTES)?;
+    let proposal = load_proposal(key)?;
+    check_challengeable(&proposal)?;
+    // One challenge per person, however many linked principals they hold; the deposit
+    // still comes from, and is refunded to, the principal that paid it.
+    let challenge_key = (key, principal_key(&identity::canonical(&challenger)));
+    if CHALLENGES.with(|c| c.borrow().contains_key(&challenge_key)) {
+        return Err(ErrorCode::AlreadyChallenged.into());
+    }
+
+    let deposit = treasury::collect_deposit(challenger, config::get().challenge_deposit).await?;
+    // The window may have closed or the proposal moved on while the deposit was taken.
+    let still_open = load_proposal(key)
+        .is_ok_and(|proposal| check_challengeable(&proposal).is_ok())
+        && !CHALLENGES.with(|c| c.borrow().contains_key(&challenge_key));
+    let challenge = Challenge {
+        challenger,
+        reason,
+        deposit,
+        filed_at: env::time(),
+    };
+    if !still_open {
+        refund(vec![challenge]).await;
+        return Err(ErrorCode::ChallengeWindowClosed.into());
+    }
+    CHALLENGES.with(|c| c.borrow_mut().insert(challenge_key, challenge));
+
+    if challenges_of(key).len() as u32 >= config::get().challenge_support_required {
+        if let Some(challenges) = reopen(key) {
+            events::record(key, events::EventKind::ProposalReopened);
+            follows::notify_followers(key, events::EventKind::ProposalReopened);
+            refund(challenges).await;
+        }
+    }
+    Ok(())
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_challenges(key: u64) -> Vec<Challenge> {
+    challenges_of(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{as_caller, block_on, principal, proposal, NOW};
+    use crate::{create, end_proposal_v2, vote_v2, Choice};
+
+    const HOUR: u64 = 3_600 * NANOS_PER_SEC;
+
+    #[test]
+    fn a_reopened_proposal_gets_its_voting_time_again() {
+        as_caller(1);
+        config::update(|config| config.challenge_window_secs = 86_400);
+        let mut with_deadline = proposal("deadline");
+        with_deadline.deadline_ns = Some(NOW + HOUR);
+        let key = create(with_deadline, None).unwrap().key;
+        end_proposal_v2(key).unwrap();
+
+        let later = NOW + 2 * HOUR;
+        env::set_time(later);
+        assert!(reopen(key).is_some());
+        let reopened = load_proposal(key).unwrap();
+        assert_eq!(reopened.voting_ends_at, Some(later + HOUR));
+
+        env::set_caller(principal(2));
+        block_on(vote_v2(key, Choice::Approve)).unwrap();
+        assert_eq!(load_proposal(key).unwrap().approve, 1);
+    }
+}
@@ -0,0 +1,337 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use std::cell::RefCell;
+use std::ops::Bound;
+
+use crate::reactions::{self, ReactionTarget};
+use crate::roles::Role;
+use crate::{
+    config, env, inbox, load_proposal, memory, principal_key, roles, validation, ErrorCode, Memory,
+    PrincipalKey, VoteError, COMMENTS_MEMORY_ID, COMMENT_INDEX_MEMORY_ID,
+    COMMENT_REPORTS_MEMORY_ID, COMMENT_SEQ_MEMORY_ID, PROPOSAL_MAP,
+};
+
+const MAX_COMMENT_BYTES: usize = 1000;
+const MAX_REASON_BYTES: usize = 200;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Comment {
+    pub id: u64,
+    pub proposal: u64,
+    pub parent: Option<u64>,
+    pub author: Principal,
+    pub created_at: u64,
+    pub text: String,
+    pub hidden: bool,
+    pub edited_at: Option<u64>,
+}
+
+impl Default for Comment {
+    fn default() -> Self {
+        Comment {
+            id: 0,
+            proposal: 0,
+            parent: None,
+            author: Principal::anonymous(),
+            created_at: 0,
+            text: String::new(),
+            hidden: true,
+            edited_at: None,
+        }
+    }
+}
+
+candid_storable!(Comment, 1500);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Report {
+    pub reporter: Principal,
+    pub reason: String,
+    pub created_at: u64,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Report {
+            reporter: Principal::anonymous(),
+            reason: String::new(),
+            created_at: 0,
+        }
+    }
+}
+
+candid_storable!(Report, 400);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ReportedComment {
+    pub comment: Comment,
+    pub reports: Vec<Report>,
+}
+
+thread_local! {
+    static COMMENTS: RefCell<StableBTreeMap<(u64, u64), Comment, Memory>> = RefCell::new(StableBTreeMap::init(memory(COMMENTS_MEMORY_ID)));
+
+    // Maps a comment id back to its proposal so comments can be addressed by id alone.
+    static COMMENT_INDEX: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(StableBTreeMap::init(memory(COMMENT_INDEX_MEMORY_ID)));
+
+    static REPORTS: RefCell<StableBTreeMap<(u64, PrincipalKey), Report, Memory>> = RefCell::new(StableBTreeMap::init(memory(COMMENT_REPORTS_MEMORY_ID)));
+
+    static NEXT_COMMENT_ID: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(memory(COMMENT_SEQ_MEMORY_ID), 0).expect("failed to initialize comment ids")
+    );
+}
+
+pub fn get(id: u64) -> Option<Comment> {
+    let proposal = COMMENT_INDEX.with(|i| i.borrow().get(&id))?;
+    COMMENTS.with(|c| c.borrow().get(&(proposal, id)))
+}
+
+pub fn count(proposal: u64) -> u64 {
+    COMMENTS.with(|c| {
+        c.borrow()
+            .range((proposal, 0)..=(proposal, u64::MAX))
+            .filter(|(_, comment)| !comment.hidden)
+            .count() as u64
+    })
+}
+
+fn insert(proposal: u64, parent: Option<u64>, text: String) -> Result<u64, VoteError> {
+    config::ensure_not_paused()?;
+    let author = env::authenticated_caller()?;
+    validation::text("text", &text, MAX_COMMENT_BYTES)?;
+
+    let id = NEXT_COMMENT_ID.with(|n| {
+        let id = *n.borrow().get();
+        n.borrow_mut()
+            .set(id + 1)
+            .expect("failed to advance comment ids");
+        id
+    });
+    let comment = Comment {
+        id,
+        proposal,
+        parent,
+        author,
+        created_at: env::time(),
+        text,
+        hidden: false,
+        edited_at: None,
+    };
+    inbox::notify_mentions(&comment);
+    COMMENTS.with(|c| c.borrow_mut().insert((proposal, id), comment));
+    COMMENT_INDEX.with(|i| i.borrow_mut().insert(id, proposal));
+    Ok(id)
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn add_comment(key: u64, text: String) -> Result<u64, VoteError> {
+    if !PROPOSAL_MAP.with(|p| p.borrow().contains_key(&key)) {
+        return Err(ErrorCode::NoSuchProposal.into());
+    }
+    insert(key, None, text)
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn reply_to_comment(parent: u64, text: String) -> Result<u64, VoteError> {
+    let parent = get(parent).ok_or(ErrorCode::NoSuchComment)?;
+    insert(parent.proposal, Some(parent.id), text)
+}
+
+fn remove_reports(id: u64) {
+    let reporters: Vec<PrincipalKey> = REPORTS.with(|r| {
+        r.borrow()
+            .range((id, PrincipalKey::default())..)
+            .take_while(|((comment, _), _)| *comment == id)
+            .map(|((_, reporter), _)| reporter)
+            .collect()
+    });
+    REPORTS.with(|r| {
+        let mut reports = r.borrow_mut();
+        for reporter in reporters {
+            reports.remove(&(id, reporter));
+        }
+    });
+}
+
+pub fn clear(proposal: u64) {
+    let ids: Vec<u64> = COMMENTS.with(|c| {
+        c.borrow()
+            .range((proposal, 0)..=(proposal, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    for id in ids {
+        remove_reports(id);
+        reactions::clear(ReactionTarget::Comment(id));
+        COMMENTS.with(|c| c.borrow_mut().remove(&(proposal, id)));
+        COMMENT_INDEX.with(|i| i.borrow_mut().remove(&id));
+    }
+}
+
+// Only the author or an admin may change or remove a comment.
+fn authored(id: u64) -> Result<Comment, VoteError> {
+    config::ensure_not_paused()?;
+    let comment = get(id).ok_or(ErrorCode::NoSuchComment)?;
+    let caller = env::authenticated_caller()?;
+    if comment.author != caller && !roles::has_role(&caller, Role::Admin) {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    Ok(comment)
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn edit_comment(id: u64, text: String) -> Result<(), VoteError> {
+    let mut comment = authored(id)?;
+    validation::text("text", &text, MAX_COMMENT_BYTES)?;
+
+    comment.text = text;
+    comment.edited_at = Some(env::time());
+    COMMENTS.with(|c| c.borrow_mut().insert((comment.proposal, id), comment));
+    Ok(())
+}
+
+// Replies keep pointing at the removed comment's id so threads still render.
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn delete_comment(id: u64) -> Result<(), VoteError> {
+    let comment = authored(id)?;
+    remove_reports(id);
+    reactions::clear(ReactionTarget::Comment(id));
+    COMMENTS.with(|c| c.borrow_mut().remove(&(comment.proposal, id)));
+    COMMENT_INDEX.with(|i| i.borrow_mut().remove(&id));
+    Ok(())
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CommentPage {
+    pub comments: Vec<Comment>,
+    // The id of the last comment looked at.
+    pub next: Option<u64>,
+}
+
+pub fn comment_page(key: u64, after: Option<u64>, page: usize) -> CommentPage {
+    let start = after.map_or(Bound::Included((key, 0)), |id| Bound::Excluded((key, id)));
+    let (comments, next) = COMMENTS.with(|c| {
+        config::scan_page(
+            c.borrow().range((start, Bound::Included((key, u64::MAX)))),
+            page,
+            |_, comment| (!comment.hidden).then_some(comment),
+        )
+    });
+    CommentPage {
+        comments,
+        next: next.map(|(_, id)| id),
+    }
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_comments_v2(key: u64, after: Option<u64>, limit: u64) -> CommentPage {
+    comment_page(key, after, config::page_limit(limit))
+}
+
+fn reports_for(id: u64) -> Vec<Report> {
+    REPORTS.with(|r| {
+        r.borrow()
+            .range((id, PrincipalKey::default())..)
+            .take_while(|((comment, _), _)| *comment == id)
+            .map(|(_, report)| report)
+            .collect()
+    })
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn report_comment(id: u64, reason: String) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    if get(id).is_none() {
+        return Err(ErrorCode::NoSuchComment.into());
+    }
+    validation::text("reason", &reason, MAX_REASON_BYTES)?;
+
+    let reporter = env::authenticated_caller()?;
+    let report_key = (id, principal_key(&reporter));
+    if REPORTS.with(|r| r.borrow().contains_key(&report_key)) {
+        return Err(ErrorCode::AlreadyReported.into());
+    }
+    REPORTS.with(|r| {
+        r.borrow_mut().insert(
+            report_key,
+            Report {
+                reporter,
+                reason,
+                created_at: env::time(),
+            },
+        )
+    });
+    Ok(())
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn hide_comment(id: u64) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let caller = env::authenticated_caller()?;
+    let mut comment = get(id).ok_or(ErrorCode::NoSuchComment)?;
+    let is_proposal_owner =
+        load_proposal(comment.proposal).is_ok_and(|proposal| proposal.owner == caller);
+    if !is_proposal_owner && !roles::is_moderator(&caller) {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+
+    comment.hidden = true;
+    COMMENTS.with(|c| c.borrow_mut().insert((comment.proposal, id), comment));
+    Ok(())
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ModerationPage {
+    pub reported: Vec<ReportedComment>,
+    // The id of the last reported comment looked at.
+    pub next: Option<u64>,
+}
+
+// Reports are keyed by comment, so a comment's reports sit together and it is picked up
+// at its first one.
+pub fn moderation_page(after: Option<u64>, page: usize) -> Result<ModerationPage, VoteError> {
+    if !roles::is_moderator(&env::caller()) {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    let Some(start) = after.map_or(Some(0), |id| id.checked_add(1)) else {
+        return Ok(ModerationPage {
+            reported: Vec::new(),
+            next: None,
+        });
+    };
+
+    let mut previous = None;
+    let (comments, next) = REPORTS.with(|r| {
+        config::scan_page(
+            r.borrow().range((start, PrincipalKey::default())..),
+            page,
+            |(id, _), _| {
+                let first = previous.replace(*id) != Some(*id);
+                get(*id).filter(|comment| first && !comment.hidden)
+            },
+        )
+    });
+    Ok(ModerationPage {
+        reported: comments
+            .into_iter()
+            .map(|comment| ReportedComment {
+                reports: reports_for(comment.id),
+                comment,
+            })
+            .collect(),
+        next: next.map(|(id, _)| id),
+    })
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_moderation_queue_v2(after: Option<u64>, limit: u64) -> Result<ModerationPage, VoteError> {
+    moderation_page(after, config::page_limit(limit))
+}
@@ -0,0 +1,412 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableCell;
+use std::cell::RefCell;
+
+use crate::roles::{self, Role};
+use crate::tiebreak::TieBreak;
+use crate::{env, memory, schema, ErrorCode, Memory, VoteError, CONFIG_MEMORY_ID};
+
+const DEFAULT_EVENT_RETENTION_DAYS: u64 = 90;
+const DEFAULT_APPROVAL_THRESHOLD_PERCENT: u8 = 50;
+const DEFAULT_AMENDMENT_THRESHOLD_PERCENT: u8 = 66;
+const DEFAULT_VETO_WINDOW_SECS: u64 = 2 * 24 * 60 * 60;
+const DEFAULT_EXECUTION_DELAY_SECS: u64 = 2 * 24 * 60 * 60;
+const DEFAULT_EMERGENCY_VOTING_PERIOD_SECS: u64 = 24 * 60 * 60;
+const DEFAULT_EMERGENCY_THRESHOLD_PERCENT: u8 = 75;
+const DEFAULT_COUNCIL_QUORUM_PERCENT: u8 = 50;
+const DEFAULT_CHALLENGE_SUPPORT_REQUIRED: u32 = 3;
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const DEFAULT_MAX_PAGE_SIZE: u64 = 100;
+const DEFAULT_MAX_SCAN_PER_CALL: u64 = 10_000;
+const DEFAULT_REVEAL_WINDOW_SECS: u64 = 24 * 60 * 60;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Config {
+    pub event_retention_days: u64,
+    pub default_quorum: u32,
+    pub default_approval_threshold_percent: u8,
+    pub amendment_threshold_percent: u8,
+    pub paused: bool,
+    pub veto_window_secs: u64,
+    pub execution_delay_secs: u64,
+    pub emergency_voting_period_secs: u64,
+    pub emergency_threshold_percent: u8,
+    pub executor: Option<Principal>,
+    pub council_quorum_percent: u8,
+    pub seconds_required: u32,
+    pub challenge_window_secs: u64,
+    pub challenge_support_required: u32,
+    pub challenge_deposit: u64,
+    pub deposit_ledger: Option<Principal>,
+    pub default_page_size: u64,
+    pub max_page_size: u64,
+    pub max_scan_per_call: u64,
+    pub reveal_window_secs: u64,
+    pub max_proposals_per_day: u32,
+    pub proposal_deposit: u64,
+    pub tie_break: TieBreak,
+    pub identity_attestor: Option<Principal>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            event_retention_days: DEFAULT_EVENT_RETENTION_DAYS,
+            default_quorum: 0,
+            default_approval_threshold_percent: DEFAULT_APPROVAL_THRESHOLD_PERCENT,
+            amendment_threshold_percent: DEFAULT_AMENDMENT_THRESHOLD_PERCENT,
+            paused: false,
+            veto_window_secs: DEFAULT_VETO_WINDOW_SECS,
+            execution_delay_secs: DEFAULT_EXECUTION_DELAY_SECS,
+            emergency_voting_period_secs: DEFAULT_EMERGENCY_VOTING_PERIOD_SECS,
+            emergency_threshold_percent: DEFAULT_EMERGENCY_THRESHOLD_PERCENT,
+            executor: None,
+            council_quorum_percent: DEFAULT_COUNCIL_QUORUM_PERCENT,
+            seconds_required: 0,
+            challenge_window_secs: 0,
+            challenge_support_required: DEFAULT_CHALLENGE_SUPPORT_REQUIRED,
+            challenge_deposit: 0,
+            deposit_ledger: None,
+            default_page_size: DEFAULT_PAGE_SIZE,
+            max_page_size: DEFAULT_MAX_PAGE_SIZE,
+            max_scan_per_call: DEFAULT_MAX_SCAN_PER_CALL,
+            reveal_window_secs: DEFAULT_REVEAL_WINDOW_SECS,
+            max_proposals_per_day: 0,
+            proposal_deposit: 0,
+            tie_break: TieBreak::Reject,
+            identity_attestor: None,
+        }
+    }
+}
+
+// Config fields are not optional, so values stored before a field existed are completed
+// from the defaults. Unlike other records, a config that still does not decode traps:
+// falling back to the defaults would lift the pause and drop the executor, ledger and
+// attestor settings without anyone deciding to.
+impl ic_stable_structures::Storable for Config {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(
+            candid::encode_one(self).expect("derived Candid types always encode"),
+        )
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        schema::decode_with_defaults(bytes.as_ref()).expect("failed to decode config")
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct ConfigDiff {
+    pub event_retention_days: Option<u64>,
+    pub default_quorum: Option<u32>,
+    pub default_approval_threshold_percent: Option<u8>,
+    pub amendment_threshold_percent: Option<u8>,
+    pub paused: Option<bool>,
+    pub veto_window_secs: Option<u64>,
+    pub execution_delay_secs: Option<u64>,
+    pub emergency_voting_period_secs: Option<u64>,
+    pub emergency_threshold_percent: Option<u8>,
+    pub executor: Option<Option<Principal>>,
+    pub council_quorum_percent: Option<u8>,
+    pub seconds_required: Option<u32>,
+    pub challenge_window_secs: Option<u64>,
+    pub challenge_support_required: Option<u32>,
+    pub challenge_deposit: Option<u64>,
+    pub deposit_ledger: Option<Option<Principal>>,
+    pub default_page_size: Option<u64>,
+    pub max_page_size: Option<u64>,
+    pub max_scan_per_call: Option<u64>,
+    pub reveal_window_secs: Option<u64>,
+    pub max_proposals_per_day: Option<u32>,
+    pub proposal_deposit: Option<u64>,
+    pub tie_break: Option<TieBreak>,
+    pub identity_attestor: Option<Option<Principal>>,
+    pub add_admins: Vec<Principal>,
+    pub remove_admins: Vec<Principal>,
+}
+
+impl ConfigDiff {
+    pub fn validate(&self) -> Result<(), String> {
+        self.validate_settings()?;
+        if self.remaining_admins().is_empty() {
+            return Err("the change would leave the canister without admins".to_string());
+        }
+        Ok(())
+    }
+
+    // Init args go through these too; their admins are checked once the installer is added.
+    fn validate_settings(&self) -> Result<(), String> {
+        if self.event_retention_days == Some(0) {
+            return Err("event_retention_days must be at least 1".to_string());
+        }
+        if self
+            .default_approval_threshold_percent
+            .is_some_and(|percent| percent > 100)
+        {
+            return Err("default_approval_threshold_percent must be at most 100".to_string());
+        }
+        if self
+            .amendment_threshold_percent
+            .is_some_and(|percent| percent > 100)
+        {
+            return Err("amendment_threshold_percent must be at most 100".to_string());
+        }
+        if self.emergency_voting_period_secs == Some(0) {
+            return Err("emergency_voting_period_secs must be at least 1".to_string());
+        }
+        if self
+            .emergency_threshold_percent
+            .is_some_and(|percent| percent > 100)
+        {
+            return Err("emergency_threshold_percent must be at most 100".to_string());
+        }
+        if self.reveal_window_secs == Some(0) {
+            return Err("reveal_window_secs must be at least 1".to_string());
+        }
+        if self.challenge_support_required == Some(0) {
+            return Err("challenge_support_required must be at least 1".to_string());
+        }
+        if self
+            .council_quorum_percent
+            .is_some_and(|percent| percent > 100)
+        {
+            return Err("council_quorum_percent must be at most 100".to_string());
+        }
+        let current = get();
+        let default_page_size = self.default_page_size.unwrap_or(current.default_page_size);
+        let max_page_size = self.max_page_size.unwrap_or(current.max_page_size);
+        if default_page_size == 0 || default_page_size > max_page_size {
+            return Err("default_page_size must be between 1 and max_page_size".to_string());
+        }
+        if self.max_scan_per_call.unwrap_or(current.max_scan_per_call) < max_page_size {
+            return Err("max_scan_per_call must be at least max_page_size".to_string());
+        }
+        Ok(())
+    }
+
+    fn remaining_admins(&self) -> Vec<Principal> {
+        let mut admins = roles::members(Role::Admin);
+        admins.extend(self.add_admins.iter().copied());
+        admins.retain(|admin| !self.remove_admins.contains(admin));
+        admins
+    }
+
+    pub fn apply(&self) -> Result<(), String> {
+        self.validate()?;
+        self.write();
+        Ok(())
+    }
+
+    fn write(&self) {
+        update(|config| {
+            if let Some(days) = self.event_retention_days {
+                config.event_retention_days = days;
+            }
+            if let Some(quorum) = self.default_quorum {
+                config.default_quorum = quorum;
+            }
+            if let Some(percent) = self.default_approval_threshold_percent {
+                config.default_approval_threshold_percent = percent;
+            }
+            if let Some(percent) = self.amendment_threshold_percent {
+                config.amendment_threshold_percent = percent;
+            }
+            if let Some(paused) = self.paused {
+                config.paused = paused;
+            }
+            if let Some(secs) = self.veto_window_secs {
+                config.veto_window_secs = secs;
+            }
+            if let Some(secs) = self.execution_delay_secs {
+                config.execution_delay_secs = secs;
+            }
+            if let Some(secs) = self.emergency_voting_period_secs {
+                config.emergency_voting_period_secs = secs;
+            }
+            if let Some(percent) = self.emergency_threshold_percent {
+                config.emergency_threshold_percent = percent;
+            }
+            if let Some(executor) = self.executor {
+                config.executor = executor;
+            }
+            if let Some(percent) = self.council_quorum_percent {
+                config.council_quorum_percent = percent;
+            }
+            if let Some(seconds) = self.seconds_required {
+                config.seconds_required = seconds;
+            }
+            if let Some(secs) = self.challenge_window_secs {
+                config.challenge_window_secs = secs;
+            }
+            if let Some(support) = self.challenge_support_required {
+                config.challenge_support_required = support;
+            }
+            if let Some(deposit) = self.challenge_deposit {
+                config.challenge_deposit = deposit;
+            }
+            if let Some(ledger) = self.deposit_ledger {
+                config.deposit_ledger = ledger;
+            }
+            if let Some(size) = self.default_page_size {
+                config.default_page_size = size;
+            }
+            if let Some(size) = self.max_page_size {
+                config.max_page_size = size;
+            }
+            if let Some(scan) = self.max_scan_per_call {
+                config.max_scan_per_call = scan;
+            }
+            if let Some(secs) = self.reveal_window_secs {
+                config.reveal_window_secs = secs;
+            }
+            if let Some(limit) = self.max_proposals_per_day {
+                config.max_proposals_per_day = limit;
+            }
+            if let Some(deposit) = self.proposal_deposit {
+                config.proposal_deposit = deposit;
+            }
+            if let Some(tie_break) = self.tie_break {
+                config.tie_break = tie_break;
+            }
+            if let Some(attestor) = self.identity_attestor {
+                config.identity_attestor = attestor;
+            }
+        });
+        for admin in &self.add_admins {
+            roles::grant(*admin, Role::Admin);
+        }
+        for admin in &self.remove_admins {
+            roles::revoke(admin, Role::Admin);
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct InitArgs {
+    pub event_retention_days: Option<u64>,
+    pub executor: Option<Principal>,
+    pub admins: Option<Vec<Principal>>,
+}
+
+thread_local! {
+    static CONFIG: RefCell<StableCell<Config, Memory>> = RefCell::new(
+        StableCell::init(memory(CONFIG_MEMORY_ID), Config::default()).expect("failed to initialize config")
+    );
+}
+
+pub fn get() -> Config {
+    CONFIG.with(|c| c.borrow().get().clone())
+}
+
+pub fn update(f: impl FnOnce(&mut Config)) {
+    let resumed = CONFIG.with(|c| {
+        let mut config = c.borrow().get().clone();
+        let was_paused = config.paused;
+        f(&mut config);
+        let resumed = was_paused && !config.paused;
+        c.borrow_mut().set(config).expect("failed to write config");
+        resumed
+    });
+    if resumed {
+        crate::rearm_timers();
+    }
+}
+
+// A limit of 0 asks for the configured default.
+pub fn page_limit(limit: u64) -> usize {
+    let config = get();
+    let limit = if limit == 0 {
+        config.default_page_size
+    } else {
+        limit
+    };
+    limit.min(config.max_page_size) as usize
+}
+
+pub fn max_scan() -> usize {
+    get().max_scan_per_call as usize
+}
+
+// Walks `entries` in key order, looking at no more than `max_scan_per_call` of them and
+// keeping what `select` returns until the page is full. While entries remain, the key of
+// the last one looked at comes back as the cursor the next call resumes after.
+pub fn scan_page<K, V, T>(
+    entries: impl Iterator<Item = (K, V)>,
+    page: usize,
+    mut select: impl FnMut(&K, V) -> Option<T>,
+) -> (Vec<T>, Option<K>) {
+    let mut entries = entries.peekable();
+    let mut items = Vec::new();
+    let mut last = None;
+    for _ in 0..max_scan() {
+        if items.len() >= page {
+            break;
+        }
+        let Some((key, value)) = entries.next() else {
+            break;
+        };
+        if let Some(item) = select(&key, value) {
+            items.push(item);
+        }
+        last = Some(key);
+    }
+    let next = if entries.peek().is_some() { last } else { None };
+    (items, next)
+}
+
+pub fn ensure_not_paused() -> Result<(), VoteError> {
+    if get().paused {
+        Err(ErrorCode::CanisterPaused.into())
+    } else {
+        Ok(())
+    }
+}
+
+pub fn apply_init_args(args: Option<InitArgs>) -> Result<(), String> {
+    let Some(args) = args else {
+        return Ok(());
+    };
+    let diff = ConfigDiff {
+        event_retention_days: args.event_retention_days,
+        executor: args.executor.map(Some),
+        add_admins: args.admins.unwrap_or_default(),
+        ..ConfigDiff::default()
+    };
+    diff.validate_settings()?;
+    diff.write();
+    Ok(())
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_config() -> Config {
+    get()
+}
+
+// Pausing is also reachable outside of proposals, otherwise a paused canister could
+// never vote itself back open.
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn set_paused(paused: bool) -> Result<(), VoteError> {
+    if !roles::has_role(&env::caller(), Role::Admin) {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    update(|config| config.paused = paused);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn init_args_are_validated_like_a_config_change() {
+        let args = InitArgs {
+            event_retention_days: Some(0),
+            executor: None,
+            admins: None,
+        };
+        assert!(apply_init_args(Some(args)).is_err());
+        assert_ne!(get().event_retention_days, 0);
+    }
+}
@@ -0,0 +1,91 @@
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::ops::Bound;
+
+use crate::kinds::Amendment;
+use crate::{config, env, memory, Memory, CONSTITUTION_MEMORY_ID};
+
+// Amendments travel inside the proposal, which has to fit its own storage bound.
+pub const MAX_CONSTITUTION_BYTES: usize = 3000;
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct ConstitutionVersion {
+    pub version: u64,
+    pub text: String,
+    pub proposal: u64,
+    pub adopted_at: u64,
+}
+
+candid_storable!(ConstitutionVersion, 3200);
+
+thread_local! {
+    static VERSIONS: RefCell<StableBTreeMap<u64, ConstitutionVersion, Memory>> = RefCell::new(StableBTreeMap::init(memory(CONSTITUTION_MEMORY_ID)));
+}
+
+pub fn current_version() -> u64 {
+    VERSIONS.with(|v| {
+        v.borrow()
+            .last_key_value()
+            .map_or(0, |(version, _)| version)
+    })
+}
+
+pub fn amend(proposal: u64, amendment: Amendment) -> Result<u64, String> {
+    let current = current_version();
+    if amendment.base_version != current {
+        return Err(format!(
+            "amendment was written against version {} but the constitution is at version {}",
+            amendment.base_version, current
+        ));
+    }
+    let version = current + 1;
+    VERSIONS.with(|v| {
+        v.borrow_mut().insert(
+            version,
+            ConstitutionVersion {
+                version,
+                text: amendment.text,
+                proposal,
+                adopted_at: env::time(),
+            },
+        )
+    });
+    Ok(version)
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_constitution() -> Option<ConstitutionVersion> {
+    VERSIONS.with(|v| v.borrow().last_key_value().map(|(_, version)| version))
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_constitution_version(version: u64) -> Option<ConstitutionVersion> {
+    VERSIONS.with(|v| v.borrow().get(&version))
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ConstitutionPage {
+    pub versions: Vec<ConstitutionVersion>,
+    pub next: Option<u64>,
+}
+
+pub fn history_page(after: Option<u64>, page: usize) -> ConstitutionPage {
+    let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+    let (versions, next) = VERSIONS.with(|v| {
+        config::scan_page(
+            v.borrow().range((start, Bound::Unbounded)),
+            page,
+            |_, version| Some(version),
+        )
+    });
+    ConstitutionPage { versions, next }
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_constitution_history_v2(after: Option<u64>, limit: u64) -> ConstitutionPage {
+    history_page(after, config::page_limit(limit))
+}
@@ -0,0 +1,131 @@
+use candid::{CandidType, Deserialize};
+
+use crate::{http, load_proposal, seconding, tracks, Proposal, ProposalResult};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+// Everything preformatted as text, for the Candid UI and quick demos. Clients that do
+// their own rendering should use `get_proposal` or `get_proposal_summary`.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProposalDisplay {
+    pub key: u64,
+    pub description: String,
+    pub status: String,
+    pub outcome: String,
+    pub approve: String,
+    pub reject: String,
+    pub pass: String,
+    pub total_votes: u32,
+    pub owner: String,
+    pub created: Option<String>,
+    pub voting_ends: Option<String>,
+    pub closed: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Status {
+    Draft,
+    Open,
+    Passed,
+    Rejected,
+    QuorumNotReached,
+    Tied,
+}
+
+impl Status {
+    fn of(proposal: &Proposal) -> Self {
+        if seconding::is_draft(proposal) {
+            return Status::Draft;
+        }
+        match proposal.result() {
+            ProposalResult::Open => Status::Open,
+            ProposalResult::Passed => Status::Passed,
+            ProposalResult::Rejected => Status::Rejected,
+            ProposalResult::QuorumNotReached => Status::QuorumNotReached,
+            ProposalResult::Tied => Status::Tied,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Status::Draft => "Draft",
+            Status::Open => "Open",
+            Status::Passed => "Passed",
+            Status::Rejected => "Rejected",
+            Status::QuorumNotReached => "Quorum not reached",
+            Status::Tied => "Tied",
+        }
+    }
+
+    fn outcome(self, proposal: &Proposal) -> String {
+        let total = proposal.approve + proposal.reject + proposal.pass;
+        let approval = http::percentage(proposal.approve, proposal.approve + proposal.reject);
+        match self {
+            Status::Draft => "Waiting for seconds".to_string(),
+            Status::Open => format!("{} votes so far, {}% approval", total, approval),
+            Status::QuorumNotReached => {
+                format!("{} of {} votes needed", total, tracks::quorum(proposal))
+            }
+            Status::Tied => "Tied, waiting for the random draw".to_string(),
+            Status::Passed | Status::Rejected => {
+                format!("{} with {}% approval", self.label(), approval)
+            }
+        }
+    }
+}
+
+fn count(votes: u32, total: u32) -> String {
+    format!("{} ({}%)", votes, http::percentage(votes, total))
+}
+
+// "2026-10-14 11:36:34 UTC", from days since the epoch to a civil date as in
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn timestamp(nanos: u64) -> String {
+    let secs = nanos / NANOS_PER_SEC;
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+fn display(key: u64, proposal: &Proposal) -> ProposalDisplay {
+    let total = proposal.approve + proposal.reject + proposal.pass;
+    let status = Status::of(proposal);
+    ProposalDisplay {
+        key,
+        description: proposal.description.clone(),
+        status: status.label().to_string(),
+        outcome: status.outcome(proposal),
+        approve: count(proposal.approve, total),
+        reject: count(proposal.reject, total),
+        pass: count(proposal.pass, total),
+        total_votes: total,
+        owner: proposal.owner.to_text(),
+        created: proposal.created_at.map(timestamp),
+        voting_ends: proposal.voting_ends_at.map(timestamp),
+        closed: proposal.closed_at.map(timestamp),
+    }
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_proposal_display(key: u64) -> Option<ProposalDisplay> {
+    let proposal = load_proposal(key).ok()?;
+    Some(display(key, &proposal))
+}
@@ -0,0 +1,487 @@
+// Ed25519 signature verification (RFC 8032), kept in-tree so the canister does not
+// need an external crypto dependency. Verification only handles public data, so
+// nothing here needs to run in constant time.
+use sha2::{Digest, Sha512};
+
+#[derive(Clone, Copy)]
+struct Fe([u64; 5]);
+
+const MASK51: u64 = (1 << 51) - 1;
+
+// Exponents, little-endian: p - 2, (p - 5) / 8 and (p - 1) / 4.
+const P_MINUS_2: [u8; 32] = exponent(0xeb, 0x7f);
+const P_MINUS_5_OVER_8: [u8; 32] = exponent(0xfd, 0x0f);
+const P_MINUS_1_OVER_4: [u8; 32] = exponent(0xfb, 0x1f);
+
+const fn exponent(low: u8, high: u8) -> [u8; 32] {
+    let mut bytes = [0xff; 32];
+    bytes[0] = low;
+    bytes[31] = high;
+    bytes
+}
+
+const L: [u8; 32] = [
+    0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+];
+
+const BASE_POINT: [u8; 32] = [
+    0x58, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+    0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+];
+
+impl Fe {
+    const ZERO: Fe = Fe([0; 5]);
+    const ONE: Fe = Fe([1, 0, 0, 0, 0]);
+
+    fn from_u64(n: u64) -> Fe {
+        Fe([n & MASK51, n >> 51, 0, 0, 0])
+    }
+
+    fn from_bytes(bytes: &[u8; 32]) -> Fe {
+        let load = |i: usize| {
+            let mut word = [0u8; 8];
+            let end = (i + 8).min(32);
+            word[..end - i].copy_from_slice(&bytes[i..end]);
+            u64::from_le_bytes(word)
+        };
+        Fe([
+            load(0) & MASK51,
+            (load(6) >> 3) & MASK51,
+            (load(12) >> 6) & MASK51,
+            (load(19) >> 1) & MASK51,
+            (load(24) >> 12) & MASK51,
+        ])
+    }
+
+    fn carry(mut limbs: [u64; 5]) -> Fe {
+        for _ in 0..2 {
+            for i in 0..4 {
+                limbs[i + 1] += limbs[i] >> 51;
+                limbs[i] &= MASK51;
+            }
+            limbs[0] += 19 * (limbs[4] >> 51);
+            limbs[4] &= MASK51;
+        }
+        Fe(limbs)
+    }
+
+    fn to_bytes(self) -> [u8; 32] {
+        let mut h = Fe::carry(self.0).0;
+        let mut q = (h[0] + 19) >> 51;
+        for limb in &h[1..] {
+            q = (limb + q) >> 51;
+        }
+        h[0] += 19 * q;
+        for i in 0..4 {
+            h[i + 1] += h[i] >> 51;
+            h[i] &= MASK51;
+        }
+        h[4] &= MASK51;
+
+        let mut out = [0u8; 32];
+        let mut acc: u128 = 0;
+        let mut bits = 0;
+        let mut pos = 0;
+        for limb in h {
+            acc |= (limb as u128) << bits;
+            bits += 51;
+            while bits >= 8 && pos < 32 {
+                out[pos] = acc as u8;
+                acc >>= 8;
+                bits -= 8;
+                pos += 1;
+            }
+        }
+        if pos < 32 {
+            out[pos] = acc as u8;
+        }
+        out
+    }
+
+    fn add(self, other: Fe) -> Fe {
+        let mut limbs = [0; 5];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb = self.0[i] + other.0[i];
+        }
+        Fe::carry(limbs)
+    }
+
+    fn sub(self, other: Fe) -> Fe {
+        // Adding 2p first keeps every limb non-negative.
+        const TWO_P: [u64; 5] = [
+            0xfffffffffffda,
+            0xffffffffffffe,
+            0xffffffffffffe,
+            0xffffffffffffe,
+            0xffffffffffffe,
+        ];
+        let a = Fe::carry(self.0).0;
+        let b = Fe::carry(other.0).0;
+        let mut limbs = [0; 5];
+        for i in 0..5 {
+            limbs[i] = a[i] + TWO_P[i] - b[i];
+        }
+        Fe::carry(limbs)
+    }
+
+    fn neg(self) -> Fe {
+        Fe::ZERO.sub(self)
+    }
+
+    fn mul(self, other: Fe) -> Fe {
+        let a = self.0.map(|x| x as u128);
+        let b = other.0.map(|x| x as u128);
+        let mut r = [
+            a[0] * b[0] + 19 * (a[1] * b[4] + a[2] * b[3] + a[3] * b[2] + a[4] * b[1]),
+            a[0] * b[1] + a[1] * b[0] + 19 * (a[2] * b[4] + a[3] * b[3] + a[4] * b[2]),
+            a[0] * b[2] + a[1] * b[1] + a[2] * b[0] + 19 * (a[3] * b[4] + a[4] * b[3]),
+            a[0] * b[3] + a[1] * b[2] + a[2] * b[1] + a[3] * b[0] + 19 * (a[4] * b[4]),
+            a[0] * b[4] + a[1] * b[3] + a[2] * b[2] + a[3] * b[1] + a[4] * b[0],
+        ];
+        for i in 0..4 {
+            r[i + 1] += r[i] >> 51;
+            r[i] &= MASK51 as u128;
+        }
+        r[0] += 19 * (r[4] >> 51);
+        r[4] &= MASK51 as u128;
+        Fe::carry(r.map(|x| x as u64))
+    }
+
+    fn square(self) -> Fe {
+        self.mul(self)
+    }
+
+    fn pow(self, exponent: &[u8; 32]) -> Fe {
+        let mut result = Fe::ONE;
+        for byte in exponent.iter().rev() {
+            for bit in (0..8).rev() {
+                result = result.square();
+                if (byte >> bit) & 1 == 1 {
+                    result = result.mul(self);
+                }
+            }
+        }
+        result
+    }
+
+    fn invert(self) -> Fe {
+        self.pow(&P_MINUS_2)
+    }
+
+    fn equals(self, other: Fe) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+
+    fn is_negative(self) -> bool {
+        self.to_bytes()[0] & 1 == 1
+    }
+}
+
+fn curve_d() -> Fe {
+    Fe::from_u64(121665)
+        .neg()
+        .mul(Fe::from_u64(121666).invert())
+}
+
+#[derive(Clone, Copy)]
+struct Point {
+    x: Fe,
+    y: Fe,
+    z: Fe,
+    t: Fe,
+}
+
+impl Point {
+    const IDENTITY: Point = Point {
+        x: Fe::ZERO,
+        y: Fe::ONE,
+        z: Fe::ONE,
+        t: Fe::ZERO,
+    };
+
+    fn decompress(bytes: &[u8; 32]) -> Option<Point> {
+        let sign = bytes[31] >> 7 == 1;
+        let mut y_bytes = *bytes;
+        y_bytes[31] &= 0x7f;
+        let y = Fe::from_bytes(&y_bytes);
+        if y.to_bytes() != y_bytes {
+            return None;
+        }
+        let d = curve_d();
+        let y2 = y.square();
+        let u = y2.sub(Fe::ONE);
+        let v = d.mul(y2).add(Fe::ONE);
+        let v3 = v.square().mul(v);
+        let v7 = v3.square().mul(v);
+        let mut x = u.mul(v3).mul(u.mul(v7).pow(&P_MINUS_5_OVER_8));
+        let vx2 = v.mul(x.square());
+        if !vx2.equals(u) {
+            if !vx2.equals(u.neg()) {
+                return None;
+            }
+            x = x.mul(Fe::from_u64(2).pow(&P_MINUS_1_OVER_4));
+        }
+        if x.equals(Fe::ZERO) && sign {
+            return None;
+        }
+        if x.is_negative() != sign {
+            x = x.neg();
+        }
+        Some(Point {
+            x,
+            y,
+            z: Fe::ONE,
+            t: x.mul(y),
+        })
+    }
+
+    fn compress(self) -> [u8; 32] {
+        let z_inv = self.z.invert();
+        let x = self.x.mul(z_inv);
+        let mut bytes = self.y.mul(z_inv).to_bytes();
+        if x.is_negative() {
+            bytes[31] |= 0x80;
+        }
+        bytes
+    }
+
+    fn add(self, other: Point, d2: Fe) -> Point {
+        let a = self.y.sub(self.x).mul(other.y.sub(other.x));
+        let b = self.y.add(self.x).mul(other.y.add(other.x));
+        let c = self.t.mul(d2).mul(other.t);
+        let d = self.z.add(self.z).mul(other.z);
+        let e = b.sub(a);
+        let f = d.sub(c);
+        let g = d.add(c);
+        let h = b.add(a);
+        Point {
+            x: e.mul(f),
+            y: g.mul(h),
+            z: f.mul(g),
+            t: e.mul(h),
+        }
+    }
+
+    fn neg(self) -> Point {
+        Point {
+            x: self.x.neg(),
+            y: self.y,
+            z: self.z,
+            t: self.t.neg(),
+        }
+    }
+
+    fn mul(self, scalar: &[u8; 32], d2: Fe) -> Point {
+        let mut result = Point::IDENTITY;
+        for byte in scalar.iter().rev() {
+            for bit in (0..8).rev() {
+                result = result.add(result, d2);
+                if (byte >> bit) & 1 == 1 {
+                    result = result.add(self, d2);
+                }
+            }
+        }
+        result
+    }
+
+    // Multiplying by the cofactor sends points of order 1, 2, 4 or 8 to the identity.
+    fn is_small_order(self, d2: Fe) -> bool {
+        let eight = (0..3).fold(self, |point, _| point.add(point, d2));
+        eight.compress() == Point::IDENTITY.compress()
+    }
+}
+
+fn less_than_l(scalar: &[u8; 32]) -> bool {
+    for i in (0..32).rev() {
+        if scalar[i] != L[i] {
+            return scalar[i] < L[i];
+        }
+    }
+    false
+}
+
+// Bitwise long division is slow but only runs once per signature.
+fn reduce_mod_l(wide: &[u8; 64]) -> [u8; 32] {
+    let l = {
+        let mut limbs = [0u64; 5];
+        for (i, chunk) in L.chunks(8).enumerate() {
+            limbs[i] = u64::from_le_bytes(chunk.try_into().unwrap_or([0; 8]));
+        }
+        limbs
+    };
+    let mut r = [0u64; 5];
+    for byte in wide.iter().rev() {
+        for bit in (0..8).rev() {
+            let mut carry = ((byte >> bit) & 1) as u64;
+            for limb in r.iter_mut() {
+                let next = *limb >> 63;
+                *limb = (*limb << 1) | carry;
+                carry = next;
+            }
+            let ge = (0..5)
+                .rev()
+                .find(|&i| r[i] != l[i])
+                .is_none_or(|i| r[i] > l[i]);
+            if ge {
+                let mut borrow = 0u64;
+                for i in 0..5 {
+                    let (diff, b1) = r[i].overflowing_sub(l[i]);
+                    let (diff, b2) = diff.overflowing_sub(borrow);
+                    r[i] = diff;
+                    borrow = (b1 || b2) as u64;
+                }
+            }
+        }
+    }
+    let mut out = [0u8; 32];
+    for i in 0..4 {
+        out[i * 8..i * 8 + 8].copy_from_slice(&r[i].to_le_bytes());
+    }
+    out
+}
+
+pub fn verify(public_key: &[u8; 32], message: &[u8], signature: &[u8; 64]) -> bool {
+    let d = curve_d();
+    let d2 = d.add(d);
+    // A key of small order makes `h * A` vanish, so any `R = s * B` would verify.
+    let Some(a) = Point::decompress(public_key).filter(|a| !a.is_small_order(d2)) else {
+        return false;
+    };
+    let Some(base) = Point::decompress(&BASE_POINT) else {
+        return false;
+    };
+    let r_bytes: [u8; 32] = signature[..32].try_into().unwrap_or([0; 32]);
+    let s: [u8; 32] = signature[32..].try_into().unwrap_or([0; 32]);
+    if !less_than_l(&s) || Point::decompress(&r_bytes).is_none() {
+        return false;
+    }
+
+    let digest: [u8; 64] = Sha512::new()
+        .chain_update(r_bytes)
+        .chain_update(public_key)
+        .chain_update(message)
+        .finalize()
+        .into();
+    let h = reduce_mod_l(&digest);
+
+    let check = base.mul(&s, d2).add(a.neg().mul(&h, d2), d2);
+    check.compress() == r_bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes<const N: usize>(hex: &str) -> [u8; N] {
+        let mut out = [0; N];
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+        out
+    }
+
+    // RFC 8032, section 7.1: public key, message and signature of TEST 1 to 3.
+    const VECTORS: [(&str, &str, &str); 3] = [
+        (
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+            "",
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        ),
+        (
+            "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+            "72",
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+        ),
+        (
+            "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+            "af82",
+            "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+        ),
+    ];
+
+    fn vector(index: usize) -> ([u8; 32], Vec<u8>, [u8; 64]) {
+        let (public_key, message, signature) = VECTORS[index];
+        let message = (0..message.len() / 2)
+            .map(|i| u8::from_str_radix(&message[2 * i..2 * i + 2], 16).unwrap())
+            .collect();
+        (bytes(public_key), message, bytes(signature))
+    }
+
+    #[test]
+    fn the_rfc_vectors_verify() {
+        for index in 0..VECTORS.len() {
+            let (public_key, message, signature) = vector(index);
+            assert!(
+                verify(&public_key, &message, &signature),
+                "TEST {}",
+                index + 1
+            );
+        }
+    }
+
+    #[test]
+    fn a_flipped_bit_is_rejected() {
+        let (public_key, message, signature) = vector(2);
+        for bit in [0, 100, 255, 256, 300, 511] {
+            let mut tampered = signature;
+            tampered[bit / 8] ^= 1 << (bit % 8);
+            assert!(
+                !verify(&public_key, &message, &tampered),
+                "signature bit {bit}"
+            );
+        }
+        let mut tampered = message.clone();
+        tampered[0] ^= 1;
+        assert!(!verify(&public_key, &tampered, &signature));
+        let mut tampered = public_key;
+        tampered[0] ^= 1;
+        assert!(!verify(&tampered, &message, &signature));
+        let (other_key, _, _) = vector(1);
+        assert!(!verify(&other_key, &message, &signature));
+    }
+
+    #[test]
+    fn a_non_canonical_s_is_rejected() {
+        let (public_key, message, signature) = vector(0);
+        // S + L multiplies to the same point as S, so only the range check catches it.
+        let mut tampered = signature;
+        let mut carry = 0u16;
+        for i in 0..32 {
+            let sum = tampered[32 + i] as u16 + L[i] as u16 + carry;
+            tampered[32 + i] = sum as u8;
+            carry = sum >> 8;
+        }
+        assert_eq!(carry, 0);
+        assert!(!verify(&public_key, &message, &tampered));
+        let mut s_is_l = signature;
+        s_is_l[32..].copy_from_slice(&L);
+        assert!(!verify(&public_key, &message, &s_is_l));
+    }
+
+    #[test]
+    fn a_small_order_key_is_rejected() {
+        // With A the identity, R = B and S = 1 pass the equation for every message.
+        let identity = Point::IDENTITY.compress();
+        let mut signature = [0; 64];
+        signature[..32].copy_from_slice(&BASE_POINT);
+        signature[32] = 1;
+        assert!(!verify(&identity, b"anything", &signature));
+
+        // The point of order 2, (0, -1).
+        let mut order_two = [0xff; 32];
+        order_two[0] = 0xec;
+        order_two[31] = 0x7f;
+        assert!(!verify(&order_two, b"anything", &signature));
+    }
+
+    #[test]
+    fn a_non_canonical_key_is_rejected() {
+        let (_, message, signature) = vector(0);
+        // y = p, which is 0 written out of range.
+        let mut y_is_p = [0xff; 32];
+        y_is_p[0] = 0xed;
+        y_is_p[31] = 0x7f;
+        assert!(!verify(&y_is_p, &message, &signature));
+    }
+}
@@ -0,0 +1,218 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::kinds::{Election, ProposalKind};
+use crate::roles::{self, Role};
+use crate::{already_voted, ballots, eligibility, ensure_open, identity, seconding, tracks};
+use crate::{
+    config, events, load_proposal, memory, principal_from_key, principal_key, store_proposal,
+    validation, ErrorCode, Memory, PrincipalKey, Proposal, ProposalResult, VoteError,
+    CANDIDATES_MEMORY_ID,
+};
+
+pub const MAX_SEATS: u8 = 21;
+const MAX_CANDIDATES: usize = 50;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CandidateTally {
+    pub candidate: Principal,
+    pub approvals: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ElectionResults {
+    pub seats: u8,
+    pub candidates: Vec<CandidateTally>,
+    pub winners: Option<Vec<Principal>>,
+}
+
+thread_local! {
+    static CANDIDATES: RefCell<StableBTreeMap<(u64, PrincipalKey), u64, Memory>> = RefCell::new(StableBTreeMap::init(memory(CANDIDATES_MEMORY_ID)));
+}
+
+fn election_of(proposal: &Proposal) -> Option<&Election> {
+    match &proposal.kind {
+        Some(ProposalKind::Election(election)) => Some(election),
+        _ => None,
+    }
+}
+
+pub fn is_election(proposal: &Proposal) -> bool {
+    election_of(proposal).is_some()
+}
+
+fn tallies(key: u64) -> Vec<CandidateTally> {
+    CANDIDATES.with(|c| {
+        c.borrow()
+            .range((key, PrincipalKey::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|((_, candidate), approvals)| CandidateTally {
+                candidate: principal_from_key(&candidate),
+                approvals,
+            })
+            .collect()
+    })
+}
+
+fn tallies_len(candidates: &StableBTreeMap<(u64, PrincipalKey), u64, Memory>, key: u64) -> usize {
+    candidates
+        .range((key, PrincipalKey::default())..)
+        .take_while(|((k, _), _)| *k == key)
+        .count()
+}
+
+// Candidates with more approvals win; ties go to the lower principal so the outcome
+// does not depend on who finalizes the election.
+fn winners(key: u64, seats: u8) -> Vec<Principal> {
+    let mut ranked: Vec<CandidateTally> = tallies(key)
+        .into_iter()
+        .filter(|tally| tally.approvals > 0)
+        .collect();
+    ranked.sort_by(|a, b| {
+        b.approvals
+            .cmp(&a.approvals)
+            .then(a.candidate.cmp(&b.candidate))
+    });
+    ranked
+        .into_iter()
+        .take(seats as usize)
+        .map(|tally| tally.candidate)
+        .collect()
+}
+
+// An election that did not pass, such as one nobody voted in, leaves the sitting
+// council in place.
+pub fn finalize(key: u64, proposal: &Proposal) {
+    let Some(election) = election_of(proposal) else {
+        return;
+    };
+    if proposal.result() != ProposalResult::Passed {
+        return;
+    }
+    let elected = winners(key, election.seats);
+    if elected.is_empty() {
+        return;
+    }
+    for member in roles::members(Role::Council) {
+        roles::revoke(&member, Role::Council);
+    }
+    for winner in elected {
+        roles::grant(winner, Role::Council);
+    }
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn register_candidate(key: u64) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let proposal = load_proposal(key)?;
+    if !is_election(&proposal) {
+        return Err(ErrorCode::InvalidBallot.into());
+    }
+    // Registration closes with the first ballot so every voter sees the same field.
+    if !proposal.is_active || ballots::has_voters(key) {
+        return Err(ErrorCode::RegistrationClosed.into());
+    }
+    let candidate = (key, principal_key(&identity::voter()?));
+    CANDIDATES.with(|c| {
+        let mut candidates = c.borrow_mut();
+        if candidates.contains_key(&candidate) {
+            return Err(ErrorCode::AlreadyRegistered.into());
+        }
+        if tallies_len(&candidates, key) >= MAX_CANDIDATES {
+            return Err(ErrorCode::RegistrationClosed.into());
+        }
+        candidates.insert(candidate, 0);
+        Ok(())
+    })
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn vote_election(key: u64, approved: Vec<Principal>) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let caller = identity::voter()?;
+    let mut proposal = load_proposal(key)?;
+    if !is_election(&proposal) {
+        return Err(ErrorCode::InvalidBallot.into());
+    }
+    ensure_open(&proposal)?;
+    if !tracks::may_vote(&proposal, &caller) {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    eligibility::check(key, &proposal, &caller)?;
+    if seconding::is_draft(&proposal) {
+        return Err(ErrorCode::ProposalIsDraft.into());
+    }
+    if ballots::has_voted(key, &caller) {
+        return Err(already_voted(None));
+    }
+
+    validation::item_count("approved", approved.len(), MAX_CANDIDATES)?;
+    let mut approved = approved;
+    approved.sort();
+    approved.dedup();
+    let keys: Vec<(u64, PrincipalKey)> = approved
+        .iter()
+        .map(|candidate| (key, principal_key(candidate)))
+        .collect();
+    CANDIDATES.with(|c| -> Result<(), VoteError> {
+        let mut candidates = c.borrow_mut();
+        if keys.is_empty() || keys.iter().any(|k| !candidates.contains_key(k)) {
+            return Err(ErrorCode::InvalidBallot.with_field("approved"));
+        }
+        for k in keys {
+            let approvals = candidates.get(&k).unwrap_or(0);
+            candidates.insert(k, approvals + 1);
+        }
+        Ok(())
+    })?;
+
+    proposal.approve += 1;
+    store_proposal(key, &proposal)?;
+    ballots::add_voter(key, &caller);
+    events::record(key, events::EventKind::VoteCast);
+    Ok(())
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_election_results(key: u64) -> Option<ElectionResults> {
+    let proposal = load_proposal(key).ok()?;
+    let seats = election_of(&proposal)?.seats;
+    Some(ElectionResults {
+        seats,
+        candidates: tallies(key),
+        winners: (proposal.result() == ProposalResult::Passed).then(|| winners(key, seats)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kinds::Election;
+    use crate::testing::{as_caller, principal, proposal};
+    use crate::{create, end_proposal_v2};
+
+    #[test]
+    fn an_election_short_of_quorum_keeps_the_council() {
+        roles::grant(principal(5), Role::Council);
+        as_caller(1);
+        let mut election = proposal("election");
+        election.kind = Some(ProposalKind::Election(Election { seats: 1 }));
+        election.quorum = Some(3);
+        let key = create(election, None).unwrap().key;
+        as_caller(2);
+        register_candidate(key).unwrap();
+        as_caller(3);
+        vote_election(key, vec![principal(2)]).unwrap();
+        as_caller(1);
+        end_proposal_v2(key).unwrap();
+
+        let proposal = load_proposal(key).unwrap();
+        assert_eq!(proposal.result(), ProposalResult::QuorumNotReached);
+        assert_eq!(roles::members(Role::Council), vec![principal(5)]);
+        assert!(get_election_results(key).unwrap().winners.is_none());
+    }
+}
@@ -0,0 +1,95 @@
+use candid::Principal;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::{
+    config, env, load_proposal, memory, principal_key, validation, ErrorCode, Memory, PrincipalKey,
+    Proposal, VoteError, ELIGIBLE_VOTERS_MEMORY_ID,
+};
+
+const MAX_ELIGIBLE_VOTERS_PER_CALL: usize = 1000;
+
+thread_local! {
+    // Only consulted for proposals created with an allowlist; the rest are open to everyone.
+    static ELIGIBLE: RefCell<StableBTreeMap<(u64, PrincipalKey), (), Memory>> = RefCell::new(StableBTreeMap::init(memory(ELIGIBLE_VOTERS_MEMORY_ID)));
+}
+
+pub fn validate(voters: &[Principal]) -> Result<(), VoteError> {
+    validation::item_count(
+        "eligible_voters",
+        voters.len(),
+        MAX_ELIGIBLE_VOTERS_PER_CALL,
+    )
+}
+
+pub fn add_all(key: u64, voters: &[Principal]) {
+    ELIGIBLE.with(|e| {
+        let mut eligible = e.borrow_mut();
+        for voter in voters {
+            eligible.insert((key, principal_key(voter)), ());
+        }
+    });
+}
+
+pub fn clear(key: u64) {
+    ELIGIBLE.with(|e| {
+        let mut eligible = e.borrow_mut();
+        let voters: Vec<PrincipalKey> = eligible
+            .range((key, PrincipalKey::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|((_, voter), _)| voter)
+            .collect();
+        for voter in voters {
+            eligible.remove(&(key, voter));
+        }
+    });
+}
+
+pub fn is_eligible(key: u64, proposal: &Proposal, voter: &Principal) -> bool {
+    proposal.restricted != Some(true)
+        || ELIGIBLE.with(|e| e.borrow().contains_key(&(key, principal_key(voter))))
+}
+
+pub fn check(key: u64, proposal: &Proposal, voter: &Principal) -> Result<(), VoteError> {
+    if is_eligible(key, proposal, voter) {
+        Ok(())
+    } else {
+        Err(ErrorCode::NotEligible.into())
+    }
+}
+
+fn restricted_owned_by_caller(key: u64) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let proposal = load_proposal(key)?;
+    if proposal.owner != env::authenticated_caller()? {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    if proposal.restricted != Some(true) {
+        return Err(ErrorCode::InvalidProposal
+            .with_message("the proposal has no voter allowlist")
+            .with_field("eligible_voters"));
+    }
+    if proposal.snapshot.is_some() {
+        return Err(ErrorCode::InvalidProposal
+            .with_message("snapshot voters are fixed when the proposal is created")
+            .with_field("eligible_voters"));
+    }
+    Ok(())
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn add_eligible_voter(key: u64, voter: Principal) -> Result<(), VoteError> {
+    restricted_owned_by_caller(key)?;
+    add_all(key, &[voter]);
+    Ok(())
+}
+
+// Votes already cast stay counted.
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn remove_eligible_voter(key: u64, voter: Principal) -> Result<(), VoteError> {
+    restricted_owned_by_caller(key)?;
+    ELIGIBLE.with(|e| e.borrow_mut().remove(&(key, principal_key(&voter))));
+    Ok(())
+}
@@ -0,0 +1,146 @@
+// Everything that reads the clock, the caller or randomness goes through here, so the
+// `test-mode` feature can substitute them for lifecycle tests, and unit tests, which run
+// outside a canister, can set them directly. Otherwise these are the plain system calls.
+// One-off timers go through here too, since unit tests cannot set them.
+
+#[cfg(not(any(test, feature = "test-mode")))]
+pub fn time() -> u64 {
+    ic_cdk::api::time()
+}
+
+#[cfg(not(any(test, feature = "test-mode")))]
+pub fn caller() -> candid::Principal {
+    ic_cdk::caller()
+}
+
+#[cfg(not(any(test, feature = "test-mode")))]
+pub async fn random() -> Result<Vec<u8>, crate::VoteError> {
+    raw_rand().await
+}
+
+#[cfg(any(test, feature = "test-mode"))]
+pub use mock::{caller, random, time};
+
+#[cfg(test)]
+pub use mock::{set_caller, set_time};
+
+#[cfg(not(test))]
+pub fn set_timer(
+    delay: std::time::Duration,
+    func: impl FnOnce() + 'static,
+) -> ic_cdk_timers::TimerId {
+    ic_cdk_timers::set_timer(delay, func)
+}
+
+#[cfg(test)]
+pub fn set_timer(
+    _delay: std::time::Duration,
+    _func: impl FnOnce() + 'static,
+) -> ic_cdk_timers::TimerId {
+    ic_cdk_timers::TimerId::default()
+}
+
+async fn raw_rand() -> Result<Vec<u8>, crate::VoteError> {
+    let (randomness,) = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .map_err(|(_, message)| crate::ErrorCode::RandomnessUnavailable.with_message(message))?;
+    Ok(randomness)
+}
+
+// A typed error rather than a `guard`, which could only reject the call with a string.
+pub fn authenticated_caller() -> Result<candid::Principal, crate::VoteError> {
+    let caller = caller();
+    if caller == candid::Principal::anonymous() {
+        return Err(crate::ErrorCode::AnonymousNotAllowed.into());
+    }
+    Ok(caller)
+}
+
+// Checked against the real caller, so impersonation in test mode cannot get past it.
+pub fn require_controller() -> Result<(), crate::VoteError> {
+    let caller = ic_cdk::caller();
+    let caller = caller.as_slice();
+    // ic-cdk 0.7 has no wrapper for this system call.
+    if unsafe { ic0::is_controller(caller.as_ptr() as i32, caller.len() as i32) } != 0 {
+        Ok(())
+    } else {
+        Err(crate::ErrorCode::AccessRejected.into())
+    }
+}
+
+#[cfg(any(test, feature = "test-mode"))]
+mod mock {
+    use candid::Principal;
+    use std::cell::{Cell, RefCell};
+
+    use crate::VoteError;
+
+    thread_local! {
+        pub(super) static MOCK_TIME: Cell<Option<u64>> = const { Cell::new(None) };
+        pub(super) static IMPERSONATED: Cell<Option<Principal>> = const { Cell::new(None) };
+        pub(super) static MOCK_RANDOMNESS: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+    }
+
+    pub fn time() -> u64 {
+        MOCK_TIME.with(Cell::get).unwrap_or_else(ic_cdk::api::time)
+    }
+
+    pub fn caller() -> Principal {
+        IMPERSONATED.with(Cell::get).unwrap_or_else(ic_cdk::caller)
+    }
+
+    pub async fn random() -> Result<Vec<u8>, VoteError> {
+        match MOCK_RANDOMNESS.with(|r| r.borrow().clone()) {
+            Some(randomness) => Ok(randomness),
+            None => super::raw_rand().await,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn set_time(nanos: u64) {
+        MOCK_TIME.with(|t| t.set(Some(nanos)));
+    }
+
+    #[cfg(test)]
+    pub fn set_caller(principal: Principal) {
+        IMPERSONATED.with(|i| i.set(Some(principal)));
+    }
+}
+
+#[cfg(feature = "test-mode")]
+mod mock_endpoints {
+    use candid::Principal;
+    use serde_bytes::ByteBuf;
+
+    use super::mock::{IMPERSONATED, MOCK_RANDOMNESS, MOCK_TIME};
+    use super::require_controller;
+    use crate::VoteError;
+
+    // Timers run on the real clock, so they are re-armed against the mocked one; anything
+    // now overdue fires on the next round.
+    #[ic_cdk::update]
+    #[candid::candid_method(update)]
+    fn set_mock_time(nanos: Option<u64>) -> Result<(), VoteError> {
+        require_controller()?;
+        MOCK_TIME.with(|t| t.set(nanos));
+        crate::rearm_timers();
+        Ok(())
+    }
+
+    #[ic_cdk::update]
+    #[candid::candid_method(update)]
+    fn impersonate(principal: Option<Principal>) -> Result<(), VoteError> {
+        require_controller()?;
+        IMPERSONATED.with(|i| i.set(principal));
+        Ok(())
+    }
+
+    // Every draw returns these bytes until they are cleared with None.
+    #[ic_cdk::update]
+    #[candid::candid_method(update)]
+    fn set_mock_randomness(randomness: Option<ByteBuf>) -> Result<(), VoteError> {
+        require_controller()?;
+        MOCK_RANDOMNESS.with(|r| *r.borrow_mut() = randomness.map(ByteBuf::into_vec));
+        Ok(())
+    }
+}
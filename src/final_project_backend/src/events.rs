@@ -0,0 +1,275 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use std::cell::RefCell;
+use std::ops::Bound;
+use std::time::Duration;
+
+use crate::{
+    certification, config, env, memory, Memory, EVENTS_MEMORY_ID, EVENT_SEQ_MEMORY_ID,
+    EVENT_SUMMARIES_MEMORY_ID, PROPOSAL_EVENTS_MEMORY_ID,
+};
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const MAX_PRUNED_PER_RUN: usize = 1000;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum EventKind {
+    #[default]
+    ProposalCreated,
+    ProposalEdited,
+    ProposalEnded,
+    VoteCast,
+    ProposalReopened,
+    ProposalImported,
+    VoteChanged,
+    VoteRetracted,
+    ProposalDeleted,
+    ProposalArchived,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Event {
+    pub seq: u64,
+    pub timestamp: u64,
+    pub caller: Principal,
+    pub proposal: u64,
+    pub kind: EventKind,
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Event {
+            seq: 0,
+            timestamp: 0,
+            caller: Principal::anonymous(),
+            proposal: 0,
+            kind: EventKind::default(),
+        }
+    }
+}
+
+candid_storable!(Event, 512);
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct DailySummary {
+    pub day: u64,
+    pub first_seq: u64,
+    pub last_seq: u64,
+    pub proposals_created: u64,
+    pub proposals_edited: u64,
+    pub proposals_ended: u64,
+    pub votes_cast: u64,
+    pub proposals_reopened: u64,
+}
+
+candid_storable!(DailySummary, 256);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ChangeFeed {
+    pub events: Vec<Event>,
+    pub next_seq: u64,
+    pub has_more: bool,
+    pub gap: bool,
+}
+
+thread_local! {
+    static EVENTS: RefCell<StableBTreeMap<u64, Event, Memory>> = RefCell::new(StableBTreeMap::init(memory(EVENTS_MEMORY_ID)));
+
+    // The sequence counter lives in its own cell so pruning never causes numbers to be reused.
+    static NEXT_SEQ: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(memory(EVENT_SEQ_MEMORY_ID), 0).expect("failed to initialize event sequence")
+    );
+
+    static SUMMARIES: RefCell<StableBTreeMap<u64, DailySummary, Memory>> = RefCell::new(StableBTreeMap::init(memory(EVENT_SUMMARIES_MEMORY_ID)));
+
+    // (proposal, seq) for every retained event, so a proposal's history needs no full scan.
+    static PROPOSAL_EVENTS: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(StableBTreeMap::init(memory(PROPOSAL_EVENTS_MEMORY_ID)));
+}
+
+pub fn record(proposal: u64, kind: EventKind) -> u64 {
+    let seq = NEXT_SEQ.with(|s| {
+        let seq = *s.borrow().get();
+        s.borrow_mut()
+            .set(seq + 1)
+            .expect("failed to advance event sequence");
+        seq
+    });
+    let event = Event {
+        seq,
+        timestamp: env::time(),
+        caller: env::caller(),
+        proposal,
+        kind,
+    };
+    EVENTS.with(|e| e.borrow_mut().insert(seq, event));
+    PROPOSAL_EVENTS.with(|p| p.borrow_mut().insert((proposal, seq), ()));
+    seq
+}
+
+// Events recorded before the per-proposal index existed are indexed once, on upgrade.
+pub fn index_unindexed() {
+    if !PROPOSAL_EVENTS.with(|p| p.borrow().is_empty()) {
+        return;
+    }
+    EVENTS.with(|e| {
+        for (seq, event) in e.borrow().iter() {
+            PROPOSAL_EVENTS.with(|p| p.borrow_mut().insert((event.proposal, seq), ()));
+        }
+    });
+}
+
+pub fn get(seq: u64) -> Option<Event> {
+    EVENTS.with(|e| e.borrow().get(&seq))
+}
+
+pub fn next_seq() -> u64 {
+    NEXT_SEQ.with(|s| *s.borrow().get())
+}
+
+pub fn start_pruning_timer() {
+    ic_cdk_timers::set_timer_interval(PRUNE_INTERVAL, || {
+        if !config::get().paused {
+            prune(env::time());
+        }
+    });
+}
+
+fn prune(now: u64) {
+    let retention = config::get()
+        .event_retention_days
+        .saturating_mul(NANOS_PER_DAY);
+    let cutoff = now.saturating_sub(retention);
+
+    // Events are only dropped once their segment root is certified.
+    certification::seal_segments();
+    let sealed_until = certification::sealed_until();
+
+    let expired: Vec<Event> = EVENTS.with(|e| {
+        e.borrow()
+            .iter()
+            .take(MAX_PRUNED_PER_RUN)
+            .map(|(_, event)| event)
+            .take_while(|event| event.timestamp < cutoff && event.seq < sealed_until)
+            .collect()
+    });
+
+    for event in expired {
+        roll_up(&event);
+        EVENTS.with(|e| e.borrow_mut().remove(&event.seq));
+        PROPOSAL_EVENTS.with(|p| p.borrow_mut().remove(&(event.proposal, event.seq)));
+    }
+}
+
+fn roll_up(event: &Event) {
+    let day = event.timestamp / NANOS_PER_DAY;
+    SUMMARIES.with(|s| {
+        let mut summary = s.borrow().get(&day).unwrap_or(DailySummary {
+            day,
+            first_seq: event.seq,
+            ..Default::default()
+        });
+        summary.last_seq = event.seq;
+        match event.kind {
+            EventKind::ProposalCreated | EventKind::ProposalImported => {
+                summary.proposals_created += 1
+            }
+            EventKind::ProposalEdited => summary.proposals_edited += 1,
+            EventKind::ProposalEnded => summary.proposals_ended += 1,
+            EventKind::VoteCast => summary.votes_cast += 1,
+            EventKind::ProposalReopened => summary.proposals_reopened += 1,
+            EventKind::VoteChanged
+            | EventKind::VoteRetracted
+            | EventKind::ProposalDeleted
+            | EventKind::ProposalArchived => {}
+        }
+        s.borrow_mut().insert(day, summary);
+    });
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_event_summaries(from_day: u64, to_day: u64) -> Vec<DailySummary> {
+    SUMMARIES.with(|s| {
+        s.borrow()
+            .range(from_day..=to_day)
+            .map(|(_, summary)| summary)
+            .collect()
+    })
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EventPage {
+    pub events: Vec<Event>,
+    // The sequence number of the last event looked at.
+    pub next: Option<u64>,
+}
+
+pub fn event_page(after: Option<u64>, page: usize) -> EventPage {
+    let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+    let (events, next) = EVENTS.with(|e| {
+        config::scan_page(
+            e.borrow().range((start, Bound::Unbounded)),
+            page,
+            |_, event| Some(event),
+        )
+    });
+    EventPage { events, next }
+}
+
+// Oldest first; events past the retention window only survive in the daily summaries.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_events_v2(after: Option<u64>, limit: u64) -> EventPage {
+    event_page(after, config::page_limit(limit))
+}
+
+pub fn proposal_event_page(key: u64, after: Option<u64>, page: usize) -> EventPage {
+    let start = after.map_or(Bound::Included((key, 0)), |seq| Bound::Excluded((key, seq)));
+    let (events, next) = PROPOSAL_EVENTS.with(|p| {
+        config::scan_page(
+            p.borrow().range((start, Bound::Included((key, u64::MAX)))),
+            page,
+            |(_, seq), _| get(*seq),
+        )
+    });
+    EventPage {
+        events,
+        next: next.map(|(_, seq)| seq),
+    }
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_events_for_proposal_v2(key: u64, after: Option<u64>, limit: u64) -> EventPage {
+    proposal_event_page(key, after, config::page_limit(limit))
+}
+
+// Clients pass back the `next_seq` they last received; `gap` tells them pruning removed
+// events they never saw and they should resync from full state.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn await_changes(since_seq: u64) -> ChangeFeed {
+    let latest = next_seq();
+    let oldest_retained = EVENTS.with(|e| e.borrow().first_key_value().map(|(seq, _)| seq));
+
+    let page = config::get().max_page_size as usize;
+    let mut events: Vec<Event> = EVENTS.with(|e| {
+        e.borrow()
+            .range(since_seq..)
+            .take(page + 1)
+            .map(|(_, event)| event)
+            .collect()
+    });
+    let has_more = events.len() > page;
+    events.truncate(page);
+
+    ChangeFeed {
+        next_seq: events
+            .last()
+            .map_or(latest.max(since_seq), |event| event.seq + 1),
+        has_more,
+        gap: since_seq < latest && oldest_retained.is_none_or(|oldest| since_seq < oldest),
+        events,
+    }
+}
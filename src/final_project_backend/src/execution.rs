@@ -0,0 +1,585 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::api::call::{call_raw128, RejectionCode};
+use ic_cdk::api::management_canister::main::{
+    create_canister_with_extra_cycles, install_code, CanisterInstallMode, CanisterSettings,
+    CreateCanisterArgument, InstallCodeArgument,
+};
+use serde_bytes::ByteBuf;
+use std::time::Duration;
+
+use crate::icrc1::{self, TransferArg, TransferError};
+use crate::kinds::{
+    Amendment, CanisterCall, ExecutionPayload, ProposalKind, ReleaseMilestone, SubDao, Transfer,
+    UpgradeCanister,
+};
+use crate::roles::{self, Role};
+use crate::{
+    challenges, config, constitution, env, grants, load_proposal, parameters, subdaos, tracks,
+    treasury, update_proposal, veto, wasm_store, ErrorCode, Proposal, ProposalStore, VoteError,
+    PROPOSAL_MAP,
+};
+
+const MAX_RECORDED_REPLY_BYTES: usize = 512;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const MAX_AUTO_RETRIES: u32 = 3;
+const RETRY_BACKOFF_SECS: u64 = 5 * 60;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ExecutionOutcome {
+    Reply(ByteBuf),
+    TransferBlock(Nat),
+    ConfigApplied,
+    CanisterUpgraded,
+    ConstitutionAmended { version: u64 },
+    Bundle(Vec<ActionResult>),
+    SubDaoCreated { canister_id: Principal },
+    GrantApproved,
+    ParameterSet,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ActionResult {
+    Succeeded(ExecutionOutcome),
+    Failed {
+        code: Option<RejectionCode>,
+        message: String,
+    },
+    Skipped,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ExecutionStatus {
+    Queued {
+        queued_at: u64,
+        executable_at: u64,
+    },
+    Pending,
+    Succeeded {
+        outcome: ExecutionOutcome,
+        executed_at: u64,
+    },
+    Failed {
+        code: Option<RejectionCode>,
+        message: String,
+        executed_at: u64,
+        actions: Option<Vec<ActionResult>>,
+        attempts: u32,
+        next_retry_at: Option<u64>,
+    },
+    Vetoed {
+        by: Principal,
+        reason: String,
+        vetoed_at: u64,
+    },
+}
+
+// Executors implement `execute_proposal_action : (ExecutorRequest) -> (variant { Ok : ExecutionOutcome; Err : text })`.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ExecutorRequest {
+    pub proposal: u64,
+    pub kind: ProposalKind,
+}
+
+struct Failure {
+    code: Option<RejectionCode>,
+    message: String,
+    actions: Option<Vec<ActionResult>>,
+}
+
+impl From<(RejectionCode, String)> for Failure {
+    fn from((code, message): (RejectionCode, String)) -> Self {
+        Failure {
+            code: Some(code),
+            message,
+            actions: None,
+        }
+    }
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Failure {
+            code: None,
+            message,
+            actions: None,
+        }
+    }
+}
+
+fn truncate_message(message: &mut String) {
+    if message.len() > MAX_RECORDED_REPLY_BYTES {
+        let mut end = MAX_RECORDED_REPLY_BYTES;
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        message.truncate(end);
+    }
+}
+
+pub fn awaiting_execution(proposal: &Proposal) -> bool {
+    proposal.passed()
+        && matches!(
+            proposal.execution,
+            None | Some(ExecutionStatus::Queued { .. })
+        )
+        && proposal
+            .kind
+            .as_ref()
+            .is_some_and(ProposalKind::is_executable)
+}
+
+// None for a proposal that never closed, which is never executable.
+pub fn executable_at(proposal: &Proposal) -> Option<u64> {
+    match proposal.execution {
+        Some(ExecutionStatus::Queued { executable_at, .. }) => Some(executable_at),
+        _ => veto::deadline(proposal),
+    }
+}
+
+pub fn is_executable(proposal: &Proposal) -> bool {
+    awaiting_execution(proposal) && executable_at(proposal).is_some_and(|at| env::time() >= at)
+}
+
+// The delay is fixed when the proposal is queued so that later config changes
+// cannot shorten the time members were promised to react.
+pub fn queue(proposal: &mut Proposal) {
+    if !awaiting_execution(proposal) || proposal.execution.is_some() {
+        return;
+    }
+    let (Some(veto_deadline), Some(challenge_window_ends_at)) = (
+        veto::deadline(proposal),
+        challenges::window_ends_at(proposal),
+    ) else {
+        return;
+    };
+    let now = env::time();
+    // Emergencies still pass through the veto window but skip the timelock and
+    // cannot be challenged.
+    let (delay, challenge_ends_at) = if tracks::is_emergency(proposal) {
+        (0, 0)
+    } else {
+        let delay = config::get()
+            .execution_delay_secs
+            .saturating_mul(NANOS_PER_SEC);
+        (delay, challenge_window_ends_at)
+    };
+    proposal.execution = Some(ExecutionStatus::Queued {
+        queued_at: now,
+        executable_at: now
+            .saturating_add(delay)
+            .max(veto_deadline)
+            .max(challenge_ends_at),
+    });
+}
+
+#[derive(Clone, Copy)]
+enum Trigger {
+    Scheduled,
+    AutoRetry,
+    Manual,
+}
+
+fn may_run(proposal: &Proposal, trigger: Trigger) -> bool {
+    match (trigger, &proposal.execution) {
+        (Trigger::Scheduled, _) => is_executable(proposal),
+        (
+            Trigger::AutoRetry,
+            Some(ExecutionStatus::Failed {
+                next_retry_at: Some(at),
+                ..
+            }),
+        ) => proposal.passed() && env::time() >= *at,
+        (Trigger::Manual, Some(ExecutionStatus::Failed { .. })) => proposal.passed(),
+        _ => false,
+    }
+}
+
+// Only failures that say nothing about the action itself are worth retrying
+// unattended, such as a stopped or frozen target.
+fn is_transient(code: Option<RejectionCode>) -> bool {
+    matches!(
+        code,
+        Some(RejectionCode::SysTransient) | Some(RejectionCode::CanisterError)
+    )
+}
+
+fn run_at(key: u64, at: u64, trigger: Trigger) {
+    let delay = at.saturating_sub(env::time());
+    env::set_timer(Duration::from_nanos(delay), move || {
+        ic_cdk::spawn(async move {
+            let _ = run(key, trigger).await;
+        })
+    });
+}
+
+pub fn schedule(key: u64, proposal: &Proposal) {
+    if awaiting_execution(proposal) {
+        if let Some(at) = executable_at(proposal) {
+            run_at(key, at, Trigger::Scheduled);
+        }
+    } else if let Some(ExecutionStatus::Failed {
+        next_retry_at: Some(at),
+        ..
+    }) = proposal.execution
+    {
+        run_at(key, at, Trigger::AutoRetry);
+    }
+}
+
+pub fn reschedule_all() {
+    let pending: Vec<(u64, Proposal)> = PROPOSAL_MAP.with(|p| {
+        p.borrow()
+            .decoded()
+            .filter(|(_, proposal)| {
+                awaiting_execution(proposal)
+                    || matches!(
+                        proposal.execution,
+                        Some(ExecutionStatus::Failed {
+                            next_retry_at: Some(_),
+                            ..
+                        })
+                    )
+            })
+            .collect()
+    });
+    for (key, proposal) in pending {
+        schedule(key, &proposal);
+    }
+}
+
+fn set_status(key: u64, status: ExecutionStatus) {
+    let _ = update_proposal(key, |proposal| {
+        proposal.execution = Some(status);
+        Ok(())
+    });
+}
+
+async fn call_canister(call: CanisterCall) -> Result<ExecutionOutcome, Failure> {
+    let mut reply = call_raw128(call.canister_id, &call.method, &call.args, call.cycles).await?;
+    reply.truncate(MAX_RECORDED_REPLY_BYTES);
+    Ok(ExecutionOutcome::Reply(ByteBuf::from(reply)))
+}
+
+async fn transfer(transfer: Transfer) -> Result<ExecutionOutcome, Failure> {
+    let arg = TransferArg {
+        from_subaccount: Some(treasury::subaccount()),
+        to: transfer.to,
+        amount: transfer.amount,
+        fee: None,
+        memo: transfer.memo,
+        created_at_time: None,
+    };
+    let block = icrc1::transfer(transfer.ledger, arg).await?;
+    Ok(ExecutionOutcome::TransferBlock(block))
+}
+
+fn change_config(diff: config::ConfigDiff) -> Result<ExecutionOutcome, Failure> {
+    diff.apply()?;
+    Ok(ExecutionOutcome::ConfigApplied)
+}
+
+async fn upgrade(upgrade: UpgradeCanister) -> Result<ExecutionOutcome, Failure> {
+    let (hash, wasm_module) = wasm_store::module_for(&upgrade.wasm_hash)?;
+    let arg = match upgrade.arg {
+        Some(arg) => arg.into_vec(),
+        None => candid::encode_args(()).map_err(|e| e.to_string())?,
+    };
+    install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Upgrade,
+        canister_id: upgrade.canister_id,
+        wasm_module,
+        arg,
+    })
+    .await?;
+    wasm_store::remove(&hash);
+    Ok(ExecutionOutcome::CanisterUpgraded)
+}
+
+// The child is registered as soon as it exists so that a retry installs into the
+// same canister instead of creating another one.
+async fn create_subdao(key: u64, subdao: SubDao) -> Result<ExecutionOutcome, Failure> {
+    let (_, wasm_module) = wasm_store::module_for(&subdao.wasm_hash)?;
+    let mut child = match subdaos::by_proposal(key) {
+        Some(child) => child,
+        None => {
+            let settings = CanisterSettings {
+                controllers: Some(vec![ic_cdk::id()]),
+                compute_allocation: None,
+                memory_allocation: None,
+                freezing_threshold: None,
+            };
+            let (record,) = create_canister_with_extra_cycles(
+                CreateCanisterArgument {
+                    settings: Some(settings),
+                },
+                subdao.cycles,
+            )
+            .await?;
+            let child = subdaos::ChildDao {
+                proposal: key,
+                canister_id: record.canister_id,
+                name: subdao.name.clone(),
+                wasm_hash: subdao.wasm_hash.clone(),
+                created_at: env::time(),
+                installed: false,
+            };
+            subdaos::register(child.clone());
+            child
+        }
+    };
+    if !child.installed {
+        let init = config::InitArgs {
+            event_retention_days: subdao.event_retention_days,
+            executor: None,
+            admins: Some(subdao.admins),
+        };
+        install_code(InstallCodeArgument {
+            mode: CanisterInstallMode::Install,
+            canister_id: child.canister_id,
+            wasm_module,
+            arg: candid::encode_one(Some(init)).map_err(|e| e.to_string())?,
+        })
+        .await?;
+        child.installed = true;
+        subdaos::register(child.clone());
+    }
+    Ok(ExecutionOutcome::SubDaoCreated {
+        canister_id: child.canister_id,
+    })
+}
+
+async fn release_milestone(
+    key: u64,
+    release: ReleaseMilestone,
+) -> Result<ExecutionOutcome, Failure> {
+    let tranche = grants::begin_release(key, &release)?;
+    let arg = TransferArg {
+        from_subaccount: Some(treasury::subaccount()),
+        to: tranche.to,
+        amount: tranche.amount,
+        fee: None,
+        memo: Some(ByteBuf::from(tranche.memo)),
+        created_at_time: Some(tranche.created_at_time),
+    };
+    // After a failed call, or once the claim is too old for the ledger to check for
+    // duplicates, the transfer may have gone through, so the milestone stays claimed.
+    match icrc1::try_transfer(tranche.ledger, arg).await? {
+        Ok(block)
+        | Err(TransferError::Duplicate {
+            duplicate_of: block,
+        }) => {
+            grants::finish_release(key, &release, block.clone());
+            Ok(ExecutionOutcome::TransferBlock(block))
+        }
+        Err(TransferError::TooOld) => Err(Failure::from(format!(
+            "milestone {} of grant {} is too old to release safely; check the ledger",
+            release.milestone, release.grant
+        ))),
+        Err(error) => {
+            grants::cancel_release(&release);
+            Err(Failure::from(format!(
+                "ledger rejected transfer: {:?}",
+                error
+            )))
+        }
+    }
+}
+
+fn amend(key: u64, amendment: Amendment) -> Result<ExecutionOutcome, Failure> {
+    let version = constitution::amend(key, amendment)?;
+    Ok(ExecutionOutcome::ConstitutionAmended { version })
+}
+
+async fn delegate(
+    executor: Principal,
+    key: u64,
+    kind: ProposalKind,
+) -> Result<ExecutionOutcome, Failure> {
+    let request = ExecutorRequest {
+        proposal: key,
+        kind,
+    };
+    let (result,): (Result<ExecutionOutcome, String>,) =
+        ic_cdk::call(executor, "execute_proposal_action", (request,)).await?;
+    Ok(result?)
+}
+
+fn preflight(kind: &ProposalKind) -> Result<(), String> {
+    match kind {
+        ProposalKind::ConfigChange(diff) => diff.validate(),
+        ProposalKind::Amendment(amendment)
+            if amendment.base_version != constitution::current_version() =>
+        {
+            Err("amendment is based on an outdated constitution".to_string())
+        }
+        ProposalKind::CreateSubDao(subdao) => wasm_store::module_for(&subdao.wasm_hash).map(|_| ()),
+        ProposalKind::Upgrade(upgrade) if config::get().executor.is_none() => {
+            wasm_store::module_for(&upgrade.wasm_hash).map(|_| ())
+        }
+        _ => Ok(()),
+    }
+}
+
+// Calls to other canisters cannot be rolled back, so a bundle checks every action
+// up front, runs its external actions first and stops at the first failure, and
+// only touches local state once all of them went through.
+// A retried bundle resumes after the actions that already succeeded.
+async fn execute_bundle(
+    key: u64,
+    actions: Vec<ProposalKind>,
+    previous: Option<Vec<ActionResult>>,
+) -> Result<ExecutionOutcome, Failure> {
+    let mut results = previous
+        .filter(|results| results.len() == actions.len())
+        .unwrap_or_else(|| vec![ActionResult::Skipped; actions.len()]);
+    let mut order: Vec<usize> = (0..actions.len())
+        .filter(|&index| !matches!(results[index], ActionResult::Succeeded(_)))
+        .collect();
+    for &index in &order {
+        preflight(&actions[index]).map_err(|message| format!("action {}: {}", index, message))?;
+    }
+    order.sort_by_key(|&index| actions[index].is_local());
+
+    for index in order {
+        match dispatch_action(key, actions[index].clone()).await {
+            Ok(outcome) => results[index] = ActionResult::Succeeded(outcome),
+            Err(mut failure) => {
+                truncate_message(&mut failure.message);
+                results[index] = ActionResult::Failed {
+                    code: failure.code,
+                    message: failure.message.clone(),
+                };
+                return Err(Failure {
+                    code: failure.code,
+                    message: format!("action {}: {}", index, failure.message),
+                    actions: Some(results),
+                });
+            }
+        }
+    }
+    Ok(ExecutionOutcome::Bundle(results))
+}
+
+async fn dispatch(
+    key: u64,
+    kind: ProposalKind,
+    previous: Option<Vec<ActionResult>>,
+) -> Result<ExecutionOutcome, Failure> {
+    match kind {
+        ProposalKind::Omnibus(actions) => execute_bundle(key, actions, previous).await,
+        kind => dispatch_action(key, kind).await,
+    }
+}
+
+async fn dispatch_action(key: u64, kind: ProposalKind) -> Result<ExecutionOutcome, Failure> {
+    // Kinds that only touch this canister's own state are never handed to the executor.
+    if let Some(executor) = config::get().executor {
+        if matches!(
+            kind,
+            ProposalKind::ExecuteCall(_) | ProposalKind::Transfer(_) | ProposalKind::Upgrade(_)
+        ) {
+            return delegate(executor, key, kind).await;
+        }
+    }
+    match kind {
+        ProposalKind::Motion | ProposalKind::Signaling => {
+            Err(Failure::from("motions have nothing to execute".to_string()))
+        }
+        ProposalKind::Election(_) => Err(Failure::from(
+            "elections are settled when they end".to_string(),
+        )),
+        ProposalKind::ExecuteCall(call) => call_canister(call).await,
+        ProposalKind::Transfer(payload) => transfer(payload).await,
+        ProposalKind::ConfigChange(diff) => change_config(*diff),
+        ProposalKind::Upgrade(payload) => upgrade(payload).await,
+        ProposalKind::Amendment(amendment) => amend(key, amendment),
+        ProposalKind::CreateSubDao(subdao) => create_subdao(key, subdao).await,
+        ProposalKind::Grant(grant) => {
+            grants::approve(key, &grant);
+            Ok(ExecutionOutcome::GrantApproved)
+        }
+        ProposalKind::ReleaseMilestone(release) => release_milestone(key, release).await,
+        ProposalKind::SetParameter(change) => {
+            parameters::apply(key, change)?;
+            Ok(ExecutionOutcome::ParameterSet)
+        }
+        ProposalKind::Omnibus(_) => Err(Failure::from("bundles cannot be nested".to_string())),
+    }
+}
+
+async fn run(key: u64, trigger: Trigger) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let proposal = load_proposal(key)?;
+    if !may_run(&proposal, trigger) {
+        return Err(ErrorCode::NotExecutable.into());
+    }
+    let (attempts, previous) = match proposal.execution {
+        Some(ExecutionStatus::Failed {
+            attempts, actions, ..
+        }) => (attempts, actions),
+        _ => (0, None),
+    };
+    let kind = proposal.kind.ok_or(ErrorCode::NotExecutable)?;
+
+    // Marking the proposal before awaiting keeps a second caller from executing it again.
+    set_status(key, ExecutionStatus::Pending);
+    let result = dispatch(key, kind, previous).await;
+    let executed_at = env::time();
+    let status = match result {
+        Ok(outcome) => ExecutionStatus::Succeeded {
+            outcome,
+            executed_at,
+        },
+        Err(mut failure) => {
+            truncate_message(&mut failure.message);
+            let attempts = attempts + 1;
+            let next_retry_at =
+                (is_transient(failure.code) && attempts <= MAX_AUTO_RETRIES).then(|| {
+                    let backoff = RETRY_BACKOFF_SECS << (attempts - 1);
+                    executed_at.saturating_add(backoff.saturating_mul(NANOS_PER_SEC))
+                });
+            if let Some(at) = next_retry_at {
+                run_at(key, at, Trigger::AutoRetry);
+            }
+            ExecutionStatus::Failed {
+                code: failure.code,
+                message: failure.message,
+                executed_at,
+                actions: failure.actions,
+                attempts,
+                next_retry_at,
+            }
+        }
+    };
+    set_status(key, status);
+    Ok(())
+}
+
+// The call a proposal makes once it passes, for calls that attach no cycles.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_execution_payload(key: u64) -> Option<ExecutionPayload> {
+    match load_proposal(key).ok()?.kind? {
+        ProposalKind::ExecuteCall(call) if call.cycles == 0 => Some(ExecutionPayload {
+            canister_id: call.canister_id,
+            method: call.method,
+            arg_blob: call.args,
+        }),
+        _ => None,
+    }
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+async fn execute_proposal(key: u64) -> Result<(), VoteError> {
+    run(key, Trigger::Scheduled).await
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+async fn retry_execution(key: u64) -> Result<(), VoteError> {
+    if !roles::has_role(&env::caller(), Role::Admin) {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    run(key, Trigger::Manual).await
+}
@@ -0,0 +1,212 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::ops::Bound;
+
+use crate::events::EventKind;
+use crate::inbox::{self, NotificationKind};
+use crate::tags::{self, MAX_TAG_BYTES};
+use crate::{
+    ballots, config, env, memory, principal_from_key, principal_key, ErrorCode, Memory,
+    PrincipalKey, VoteError, FOLLOWERS_MEMORY_ID, FOLLOWS_MEMORY_ID, PROPOSAL_MAP,
+};
+
+const MAX_FOLLOWS: usize = 200;
+
+// One kind byte followed by the proposal key or the tag.
+type FollowKey = Blob<{ MAX_TAG_BYTES + 1 }>;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum Followed {
+    Proposal(u64),
+    Tag(String),
+}
+
+impl Followed {
+    fn key(&self) -> FollowKey {
+        let mut bytes = Vec::with_capacity(MAX_TAG_BYTES + 1);
+        match self {
+            Followed::Proposal(key) => {
+                bytes.push(0);
+                bytes.extend_from_slice(&key.to_be_bytes());
+            }
+            Followed::Tag(tag) => {
+                bytes.push(1);
+                bytes.extend_from_slice(tag.as_bytes());
+            }
+        }
+        FollowKey::try_from(bytes.as_slice()).expect("follow keys are bounded")
+    }
+
+    fn from_key(key: &FollowKey) -> Self {
+        match key.as_slice() {
+            [0, rest @ ..] => {
+                Followed::Proposal(u64::from_be_bytes(rest.try_into().unwrap_or_default()))
+            }
+            [_, rest @ ..] => Followed::Tag(String::from_utf8_lossy(rest).into_owned()),
+            [] => Followed::Tag(String::new()),
+        }
+    }
+}
+
+thread_local! {
+    // member -> what they follow, and the reverse for fanning out notifications.
+    static FOLLOWS: RefCell<StableBTreeMap<(PrincipalKey, FollowKey), u64, Memory>> = RefCell::new(StableBTreeMap::init(memory(FOLLOWS_MEMORY_ID)));
+
+    static FOLLOWERS: RefCell<StableBTreeMap<(FollowKey, PrincipalKey), u64, Memory>> = RefCell::new(StableBTreeMap::init(memory(FOLLOWERS_MEMORY_ID)));
+}
+
+fn followed_by(member: &Principal) -> Vec<Followed> {
+    let member = principal_key(member);
+    FOLLOWS.with(|f| {
+        f.borrow()
+            .range((member, FollowKey::default())..)
+            .take_while(|((m, _), _)| *m == member)
+            .map(|((_, followed), _)| Followed::from_key(&followed))
+            .collect()
+    })
+}
+
+fn followers_of(followed: &Followed) -> Vec<Principal> {
+    let followed = followed.key();
+    FOLLOWERS.with(|f| {
+        f.borrow()
+            .range((followed, PrincipalKey::default())..)
+            .take_while(|((k, _), _)| *k == followed)
+            .map(|((_, follower), _)| principal_from_key(&follower))
+            .collect()
+    })
+}
+
+fn follow(followed: Followed) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let member = env::authenticated_caller()?;
+    let key = (principal_key(&member), followed.key());
+    if FOLLOWS.with(|f| f.borrow().contains_key(&key)) {
+        return Ok(());
+    }
+    if followed_by(&member).len() >= MAX_FOLLOWS {
+        return Err(ErrorCode::InvalidItemCount
+            .with_message(format!("a member can follow at most {} items", MAX_FOLLOWS)));
+    }
+    let now = env::time();
+    FOLLOWS.with(|f| f.borrow_mut().insert(key, now));
+    FOLLOWERS.with(|f| f.borrow_mut().insert((key.1, key.0), now));
+    Ok(())
+}
+
+// The anonymous principal cannot follow anything, so there is nothing to remove. A
+// paused canister keeps its follows as they are.
+fn unfollow(followed: Followed) {
+    let Ok(member) = env::authenticated_caller() else {
+        return;
+    };
+    if config::get().paused {
+        return;
+    }
+    let member = principal_key(&member);
+    let followed = followed.key();
+    FOLLOWS.with(|f| f.borrow_mut().remove(&(member, followed)));
+    FOLLOWERS.with(|f| f.borrow_mut().remove(&(followed, member)));
+}
+
+// Voters already hear about the outcome through `inbox::notify_outcome`, and nobody
+// is told about their own edits.
+pub fn notify_followers(key: u64, event: EventKind) {
+    let caller = env::caller();
+    for follower in followers_of(&Followed::Proposal(key)) {
+        let is_voter = event == EventKind::ProposalEnded && ballots::has_voted(key, &follower);
+        if follower == caller || is_voter {
+            continue;
+        }
+        inbox::notify(follower, key, NotificationKind::FollowedUpdate { event });
+    }
+}
+
+// Someone following several of the proposal's tags hears about it once.
+pub fn notify_tag_followers(key: u64, proposal_tags: &[String]) {
+    let caller = env::caller();
+    let mut notified = Vec::new();
+    for tag in proposal_tags {
+        for follower in followers_of(&Followed::Tag(tag.clone())) {
+            if follower == caller || notified.contains(&follower) {
+                continue;
+            }
+            inbox::notify(
+                follower,
+                key,
+                NotificationKind::FollowedUpdate {
+                    event: EventKind::ProposalCreated,
+                },
+            );
+            notified.push(follower);
+        }
+    }
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn follow_proposal(key: u64) -> Result<(), VoteError> {
+    if !PROPOSAL_MAP.with(|p| p.borrow().contains_key(&key)) {
+        return Err(ErrorCode::NoSuchProposal.into());
+    }
+    follow(Followed::Proposal(key))
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn unfollow_proposal(key: u64) {
+    unfollow(Followed::Proposal(key));
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn follow_tag(tag: String) -> Result<(), VoteError> {
+    follow(Followed::Tag(tags::normalize(&tag)?))
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn unfollow_tag(tag: String) -> Result<(), VoteError> {
+    unfollow(Followed::Tag(tags::normalize(&tag)?));
+    Ok(())
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FollowedPage {
+    pub followed: Vec<Followed>,
+    // The last proposal or tag listed.
+    pub next: Option<Followed>,
+}
+
+pub fn followed_page(after: Option<Followed>, page: usize) -> Result<FollowedPage, VoteError> {
+    let member = principal_key(&env::caller());
+    let start = match after {
+        Some(Followed::Tag(tag)) => Bound::Excluded((
+            member,
+            Followed::Tag(tags::normalize(&tag).map_err(|e| e.with_field("after"))?).key(),
+        )),
+        Some(followed) => Bound::Excluded((member, followed.key())),
+        None => Bound::Included((member, FollowKey::default())),
+    };
+    let (followed, next) = FOLLOWS.with(|f| {
+        config::scan_page(
+            f.borrow()
+                .range((start, Bound::Unbounded))
+                .take_while(|((m, _), _)| *m == member),
+            page,
+            |(_, followed), _| Some(Followed::from_key(followed)),
+        )
+    });
+    Ok(FollowedPage {
+        followed,
+        next: next.map(|(_, followed)| Followed::from_key(&followed)),
+    })
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_followed_v2(after: Option<Followed>, limit: u64) -> Result<FollowedPage, VoteError> {
+    followed_page(after, config::page_limit(limit))
+}
@@ -0,0 +1,191 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+
+use crate::execution::{ExecutionOutcome, ExecutionStatus};
+use crate::icrc1::Account;
+use crate::kinds::{Grant, ProposalKind, ReleaseMilestone};
+use crate::{env, load_proposal, update_proposal};
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum MilestoneStatus {
+    Pending,
+    Releasing {
+        release_proposal: u64,
+        created_at_time: Option<u64>,
+    },
+    Released {
+        release_proposal: u64,
+        block_index: Nat,
+        released_at: u64,
+    },
+}
+
+pub struct Tranche {
+    pub ledger: Principal,
+    pub to: Account,
+    pub amount: Nat,
+    pub memo: Vec<u8>,
+    pub created_at_time: u64,
+}
+
+fn approved_grant(key: u64) -> Result<(Grant, Vec<MilestoneStatus>), String> {
+    let proposal =
+        load_proposal(key).map_err(|_| format!("grant proposal {} cannot be read", key))?;
+    let Some(ProposalKind::Grant(grant)) = proposal.kind else {
+        return Err(format!("proposal {} is not a grant", key));
+    };
+    if !matches!(
+        proposal.execution,
+        Some(ExecutionStatus::Succeeded {
+            outcome: ExecutionOutcome::GrantApproved,
+            ..
+        })
+    ) {
+        return Err(format!("grant {} has not been approved", key));
+    }
+    Ok((grant, proposal.milestones.unwrap_or_default()))
+}
+
+pub fn check_release(release: &ReleaseMilestone) -> Result<(), String> {
+    let (_, milestones) = approved_grant(release.grant)?;
+    match milestones.get(release.milestone as usize) {
+        Some(MilestoneStatus::Pending) => Ok(()),
+        Some(_) => Err(format!(
+            "milestone {} of grant {} is already being released",
+            release.milestone, release.grant
+        )),
+        None => Err(format!(
+            "grant {} has no milestone {}",
+            release.grant, release.milestone
+        )),
+    }
+}
+
+fn set_milestone(grant: u64, index: u32, status: MilestoneStatus) {
+    let _ = update_proposal(grant, |proposal| {
+        if let Some(slot) = proposal
+            .milestones
+            .as_mut()
+            .and_then(|milestones| milestones.get_mut(index as usize))
+        {
+            *slot = status;
+        }
+        Ok(())
+    });
+}
+
+pub fn approve(key: u64, grant: &Grant) {
+    let _ = update_proposal(key, |proposal| {
+        proposal.milestones = Some(vec![MilestoneStatus::Pending; grant.milestones.len()]);
+        Ok(())
+    });
+}
+
+// The milestone is claimed before the transfer is awaited so that a second release
+// proposal for it cannot pay out the same tranche. A retry by the proposal holding
+// the claim sends the same memo and created_at_time, so the ledger rejects it as a
+// duplicate if the first attempt went through.
+pub fn begin_release(release_proposal: u64, release: &ReleaseMilestone) -> Result<Tranche, String> {
+    let (grant, milestones) = approved_grant(release.grant)?;
+    let created_at_time = match milestones.get(release.milestone as usize) {
+        Some(MilestoneStatus::Releasing {
+            release_proposal: holder,
+            created_at_time: Some(at),
+        }) if *holder == release_proposal => *at,
+        _ => {
+            check_release(release)?;
+            env::time()
+        }
+    };
+    set_milestone(
+        release.grant,
+        release.milestone,
+        MilestoneStatus::Releasing {
+            release_proposal,
+            created_at_time: Some(created_at_time),
+        },
+    );
+    let milestone = &grant.milestones[release.milestone as usize];
+    Ok(Tranche {
+        ledger: grant.ledger,
+        to: grant.recipient,
+        amount: milestone.amount.clone(),
+        memo: [
+            release.grant.to_be_bytes(),
+            u64::from(release.milestone).to_be_bytes(),
+        ]
+        .concat(),
+        created_at_time,
+    })
+}
+
+pub fn finish_release(release_proposal: u64, release: &ReleaseMilestone, block_index: Nat) {
+    set_milestone(
+        release.grant,
+        release.milestone,
+        MilestoneStatus::Released {
+            release_proposal,
+            block_index,
+            released_at: env::time(),
+        },
+    );
+}
+
+// Only for a transfer the ledger refused, so the tranche is free to release again.
+pub fn cancel_release(release: &ReleaseMilestone) {
+    set_milestone(release.grant, release.milestone, MilestoneStatus::Pending);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kinds::Milestone;
+    use crate::testing::{as_caller, open_proposal, principal, NOW};
+
+    fn approved(key: u64) -> Grant {
+        let grant = Grant {
+            ledger: principal(7),
+            recipient: Account {
+                owner: principal(8),
+                subaccount: None,
+            },
+            milestones: vec![Milestone {
+                description: "first".to_string(),
+                amount: Nat::from(100u64),
+            }],
+        };
+        let _ = update_proposal(key, |proposal| {
+            proposal.kind = Some(ProposalKind::Grant(grant.clone()));
+            proposal.execution = Some(ExecutionStatus::Succeeded {
+                outcome: ExecutionOutcome::GrantApproved,
+                executed_at: NOW,
+            });
+            Ok(())
+        });
+        approve(key, &grant);
+        grant
+    }
+
+    #[test]
+    fn a_retried_release_resends_the_same_transfer() {
+        as_caller(1);
+        let key = open_proposal("grant");
+        approved(key);
+        let release = ReleaseMilestone {
+            grant: key,
+            milestone: 0,
+        };
+
+        let first = begin_release(10, &release).unwrap();
+        env::set_time(NOW + 1);
+        let retry = begin_release(10, &release).unwrap();
+        assert_eq!(retry.created_at_time, first.created_at_time);
+        assert_eq!(retry.memo, first.memo);
+        assert!(begin_release(11, &release).is_err());
+
+        cancel_release(&release);
+        assert_eq!(
+            begin_release(11, &release).unwrap().created_at_time,
+            NOW + 1
+        );
+    }
+}
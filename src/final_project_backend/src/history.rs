@@ -0,0 +1,128 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::roles::{self, Role};
+use crate::{
+    config, env, events, follows, load_proposal, memory, store_proposal, validation, ErrorCode,
+    Memory, Proposal, VoteError, HISTORY_MEMORY_ID,
+};
+
+// A description and title as they were before an edit replaced them.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Revision {
+    pub description: String,
+    pub title: Option<String>,
+    pub written_at: Option<u64>,
+    pub replaced_at: u64,
+    pub replaced_by: Principal,
+    pub forced: bool,
+}
+
+impl Default for Revision {
+    fn default() -> Self {
+        Revision {
+            description: String::new(),
+            title: None,
+            written_at: None,
+            replaced_at: 0,
+            replaced_by: Principal::anonymous(),
+            forced: false,
+        }
+    }
+}
+
+candid_storable!(Revision, 2500);
+
+thread_local! {
+    // (proposal, revision number), oldest first.
+    static HISTORY: RefCell<StableBTreeMap<(u64, u32), Revision, Memory>> = RefCell::new(StableBTreeMap::init(memory(HISTORY_MEMORY_ID)));
+}
+
+fn revisions(key: u64) -> Vec<Revision> {
+    HISTORY.with(|h| {
+        h.borrow()
+            .range((key, 0)..=(key, u32::MAX))
+            .map(|(_, revision)| revision)
+            .collect()
+    })
+}
+
+pub fn is_amendment(old: &Proposal, description: &str, title: Option<&str>) -> bool {
+    old.description != description || old.title.as_deref() != title
+}
+
+pub fn revision_of(old: &Proposal, forced: bool) -> Revision {
+    Revision {
+        description: old.description.clone(),
+        title: old.title.clone(),
+        written_at: old.updated_at,
+        replaced_at: env::time(),
+        replaced_by: env::caller(),
+        forced,
+    }
+}
+
+pub fn record(key: u64, revision: Revision) {
+    let next = HISTORY.with(|h| {
+        h.borrow()
+            .range((key, 0)..=(key, u32::MAX))
+            .last()
+            .map_or(0, |((_, n), _)| n + 1)
+    });
+    HISTORY.with(|h| h.borrow_mut().insert((key, next), revision));
+}
+
+pub fn clear(key: u64) {
+    let numbers: Vec<u32> = HISTORY.with(|h| {
+        h.borrow()
+            .range((key, 0)..=(key, u32::MAX))
+            .map(|((_, n), _)| n)
+            .collect()
+    });
+    HISTORY.with(|h| {
+        let mut history = h.borrow_mut();
+        for n in numbers {
+            history.remove(&(key, n));
+        }
+    });
+}
+
+// For fixing a description after voting started; the replaced text stays in the history
+// marked as forced.
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn force_amend(key: u64, description: String, title: Option<String>) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    if !roles::has_role(&env::caller(), Role::Admin) {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    validation::description(&description)?;
+    if let Some(title) = &title {
+        validation::title(title)?;
+    }
+    let old = load_proposal(key)?;
+    if !is_amendment(&old, &description, title.as_deref()) {
+        return Ok(());
+    }
+    let revision = revision_of(&old, true);
+    store_proposal(
+        key,
+        &Proposal {
+            description,
+            title,
+            updated_at: Some(env::time()),
+            ..old
+        },
+    )?;
+    record(key, revision);
+    events::record(key, events::EventKind::ProposalEdited);
+    follows::notify_followers(key, events::EventKind::ProposalEdited);
+    Ok(())
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_proposal_history(key: u64) -> Vec<Revision> {
+    revisions(key)
+}
@@ -0,0 +1,413 @@
+use candid::parser::types::FuncMode;
+use candid::types::{Function, Serializer, Type};
+use candid::{CandidType, Deserialize, Func, Principal};
+use serde_bytes::ByteBuf;
+
+use crate::{
+    ballots, config, env, json, listing, load_proposal, metrics, ErrorCode, Proposal, VoteError,
+};
+
+const CSV_CHUNK_ROWS: usize = 1000;
+
+type HeaderField = (String, String);
+
+#[derive(CandidType, Deserialize)]
+pub struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<HeaderField>,
+    body: ByteBuf,
+}
+
+#[derive(CandidType)]
+pub struct HttpResponse {
+    status_code: u16,
+    headers: Vec<HeaderField>,
+    body: ByteBuf,
+    streaming_strategy: Option<StreamingStrategy>,
+}
+
+#[derive(CandidType)]
+enum StreamingStrategy {
+    Callback {
+        callback: CallbackFunc,
+        token: StreamingCallbackToken,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy)]
+enum CsvExport {
+    Results,
+    Voters,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct StreamingCallbackToken {
+    key: u64,
+    export: CsvExport,
+    chunk: u64,
+}
+
+#[derive(CandidType)]
+pub struct StreamingCallbackHttpResponse {
+    body: ByteBuf,
+    token: Option<StreamingCallbackToken>,
+}
+
+#[derive(CandidType)]
+pub struct CsvChunk {
+    body: ByteBuf,
+    next_chunk: Option<u64>,
+}
+
+// `candid::Func` has no argument or return types, but the HTTP gateway checks the
+// callback signature, so the func type is spelled out by hand.
+struct CallbackFunc(Func);
+
+impl CandidType for CallbackFunc {
+    fn _ty() -> Type {
+        Type::Func(Function {
+            modes: vec![FuncMode::Query],
+            args: vec![StreamingCallbackToken::ty()],
+            rets: vec![StreamingCallbackHttpResponse::ty()],
+        })
+    }
+
+    fn idl_serialize<S: Serializer>(&self, serializer: S) -> Result<(), S::Error> {
+        self.0.idl_serialize(serializer)
+    }
+}
+
+impl HttpResponse {
+    fn new(status_code: u16, content_type: &str, body: Vec<u8>) -> Self {
+        HttpResponse {
+            status_code,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: ByteBuf::from(body),
+            streaming_strategy: None,
+        }
+    }
+
+    fn text(status_code: u16, message: &str) -> Self {
+        HttpResponse::new(
+            status_code,
+            "text/plain; charset=utf-8",
+            message.as_bytes().to_vec(),
+        )
+    }
+
+    fn json(status_code: u16, body: String) -> Self {
+        HttpResponse::new(
+            status_code,
+            "application/json; charset=utf-8",
+            body.into_bytes(),
+        )
+    }
+
+    fn json_error(status_code: u16, message: &str) -> Self {
+        HttpResponse::json(status_code, json::error_json(message))
+    }
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    if request.method != "GET" {
+        return HttpResponse::text(405, "Method Not Allowed");
+    }
+
+    let (path, query) = request.url.split_once('?').unwrap_or((&request.url, ""));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match segments.as_slice() {
+        ["proposals"] => {
+            let after = query_param(query, "after");
+            let limit = config::page_limit(query_param(query, "limit").unwrap_or(0));
+            json_response(json::proposals_json(
+                listing::proposal_page(after, limit).proposals,
+            ))
+        }
+        ["proposals", id] => proposal_json(id, |_, proposal| json::to_json(proposal)),
+        ["proposals", id, "result"] => proposal_json(id, json::results_json),
+        ["proposals", id, file] => {
+            let key = match id.parse::<u64>() {
+                Ok(key) => key,
+                Err(_) => return HttpResponse::text(400, "Invalid proposal id"),
+            };
+            let export = match *file {
+                "results.csv" => CsvExport::Results,
+                "voters.csv" => CsvExport::Voters,
+                _ => return HttpResponse::text(404, "Not Found"),
+            };
+            csv_response(key, export)
+        }
+        ["metrics"] => HttpResponse::new(
+            200,
+            "text/plain; version=0.0.4; charset=utf-8",
+            metrics::prometheus(&metrics::collect()).into_bytes(),
+        ),
+        _ => HttpResponse::text(404, "Not Found"),
+    }
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn http_request_streaming_callback(token: StreamingCallbackToken) -> StreamingCallbackHttpResponse {
+    match load_proposal(token.key) {
+        Ok(proposal) if is_public(token.export) => {
+            let (body, next) = csv_chunk(&proposal, token.key, token.export, token.chunk);
+            StreamingCallbackHttpResponse {
+                body: ByteBuf::from(body),
+                token: next,
+            }
+        }
+        _ => StreamingCallbackHttpResponse {
+            body: ByteBuf::new(),
+            token: None,
+        },
+    }
+}
+
+// The same rows as `/proposals/<id>/results.csv`, for callers going through an agent.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn export_results_csv(key: u64, chunk: Option<u64>) -> Result<CsvChunk, VoteError> {
+    let proposal = load_proposal(key)?;
+    let (body, next) = csv_chunk(&proposal, key, CsvExport::Results, chunk.unwrap_or(0));
+    Ok(CsvChunk {
+        body: ByteBuf::from(body),
+        next_chunk: next.map(|token| token.chunk),
+    })
+}
+
+// Gateway requests are anonymous, so the owner fetches the voter list here instead of
+// from `voters.csv`.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn export_voters_csv(key: u64, chunk: Option<u64>) -> Result<CsvChunk, VoteError> {
+    let caller = env::authenticated_caller()?;
+    let proposal = load_proposal(key)?;
+    if !ballots::may_list_voters(&proposal, &caller) {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    let (body, next) = csv_chunk(&proposal, key, CsvExport::Voters, chunk.unwrap_or(0));
+    Ok(CsvChunk {
+        body: ByteBuf::from(body),
+        next_chunk: next.map(|token| token.chunk),
+    })
+}
+
+fn query_param(query: &str, name: &str) -> Option<u64> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+fn proposal_json(
+    id: &str,
+    render: impl FnOnce(u64, &Proposal) -> Result<String, VoteError>,
+) -> HttpResponse {
+    let Ok(key) = id.parse::<u64>() else {
+        return HttpResponse::json_error(400, "Invalid proposal id");
+    };
+    match load_proposal(key) {
+        Ok(proposal) => json_response(render(key, &proposal)),
+        Err(VoteError::NoSuchProposal(_)) => HttpResponse::json_error(404, "No such proposal"),
+        Err(_) => HttpResponse::json_error(500, "Proposal could not be read"),
+    }
+}
+
+fn json_response(body: Result<String, VoteError>) -> HttpResponse {
+    match body {
+        Ok(body) => HttpResponse::json(200, body),
+        Err(_) => HttpResponse::json_error(500, "Response could not be encoded"),
+    }
+}
+
+fn csv_response(key: u64, export: CsvExport) -> HttpResponse {
+    let proposal = match load_proposal(key) {
+        Ok(proposal) => proposal,
+        Err(VoteError::NoSuchProposal(_)) => return HttpResponse::text(404, "No such proposal"),
+        Err(_) => return HttpResponse::text(500, "Proposal could not be read"),
+    };
+    if !is_public(export) {
+        return HttpResponse::text(
+            403,
+            "The proposal owner can export voters with the export_voters_csv query",
+        );
+    }
+
+    let (body, next) = csv_chunk(&proposal, key, export, 0);
+    let mut response = HttpResponse::new(200, "text/csv; charset=utf-8", body);
+    response.headers.push((
+        "Content-Disposition".to_string(),
+        format!(
+            "attachment; filename=\"proposal-{}-{}\"",
+            key,
+            export.file_name()
+        ),
+    ));
+    response.streaming_strategy = next.map(|token| StreamingStrategy::Callback {
+        callback: CallbackFunc(Func {
+            principal: ic_cdk::id(),
+            method: "http_request_streaming_callback".to_string(),
+        }),
+        token,
+    });
+    response
+}
+
+// Nobody is signed in over the gateway, so the owner-only voter list is never served
+// there.
+fn is_public(export: CsvExport) -> bool {
+    match export {
+        CsvExport::Results => true,
+        CsvExport::Voters => false,
+    }
+}
+
+fn csv_chunk(
+    proposal: &Proposal,
+    key: u64,
+    export: CsvExport,
+    chunk: u64,
+) -> (Vec<u8>, Option<StreamingCallbackToken>) {
+    let rows = match export {
+        CsvExport::Results => results_rows(proposal),
+        CsvExport::Voters => voter_rows(&ballots::voters(key)),
+    };
+
+    let start = chunk as usize * CSV_CHUNK_ROWS;
+    let end = (start + CSV_CHUNK_ROWS).min(rows.len());
+    let mut body = String::new();
+    if chunk == 0 {
+        body.push_str(export.header());
+        body.push('\n');
+    }
+    for row in rows.get(start..end).unwrap_or_default() {
+        body.push_str(row);
+        body.push('\n');
+    }
+
+    let next = (end < rows.len()).then(|| StreamingCallbackToken {
+        key,
+        export,
+        chunk: chunk + 1,
+    });
+    (body.into_bytes(), next)
+}
+
+fn results_rows(proposal: &Proposal) -> Vec<String> {
+    let total = proposal.approve + proposal.reject + proposal.pass;
+    [
+        ("approve", proposal.approve),
+        ("reject", proposal.reject),
+        ("pass", proposal.pass),
+    ]
+    .iter()
+    .map(|(choice, count)| {
+        format!(
+            "{},{},{},{},{},{}",
+            choice,
+            count,
+            percentage(*count, total),
+            timestamp(proposal.created_at),
+            timestamp(proposal.voting_ends_at),
+            timestamp(proposal.closed_at)
+        )
+    })
+    .collect()
+}
+
+fn voter_rows(voted: &[Principal]) -> Vec<String> {
+    voted.iter().map(Principal::to_text).collect()
+}
+
+fn timestamp(nanos: Option<u64>) -> String {
+    nanos.map(|n| n.to_string()).unwrap_or_default()
+}
+
+pub fn percentage(count: u32, total: u32) -> String {
+    if total == 0 {
+        return "0.00".to_string();
+    }
+    format!("{:.2}", count as f64 * 100.0 / total as f64)
+}
+
+impl CsvExport {
+    fn header(self) -> &'static str {
+        match self {
+            CsvExport::Results => "choice,votes,percentage,created_at,voting_ends_at,closed_at",
+            CsvExport::Voters => "principal",
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            CsvExport::Results => "results.csv",
+            CsvExport::Voters => "voters.csv",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{as_caller, open_proposal};
+
+    #[test]
+    fn percentages_have_two_decimals() {
+        assert_eq!(percentage(1, 3), "33.33");
+        assert_eq!(percentage(2, 3), "66.67");
+        assert_eq!(percentage(3, 3), "100.00");
+        assert_eq!(percentage(0, 0), "0.00");
+    }
+
+    #[test]
+    fn results_are_one_row_per_choice() {
+        as_caller(1);
+        let key = open_proposal("csv");
+        let mut proposal = load_proposal(key).unwrap();
+        proposal.approve = 2;
+        proposal.reject = 1;
+        proposal.closed_at = Some(20);
+        let created_at = proposal.created_at.unwrap();
+        let (body, next) = csv_chunk(&proposal, key, CsvExport::Results, 0);
+        assert!(next.is_none());
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            format!(
+                "choice,votes,percentage,created_at,voting_ends_at,closed_at\n\
+                 approve,2,66.67,{created_at},,20\n\
+                 reject,1,33.33,{created_at},,20\n\
+                 pass,0,0.00,{created_at},,20\n"
+            )
+        );
+    }
+
+    #[test]
+    fn voters_are_streamed_in_chunks() {
+        as_caller(1);
+        let key = open_proposal("voters");
+        let proposal = load_proposal(key).unwrap();
+        for id in 0..=CSV_CHUNK_ROWS as u32 {
+            let voter = Principal::from_slice(&id.to_be_bytes());
+            ballots::add_voter(key, &voter);
+        }
+
+        let (first, next) = csv_chunk(&proposal, key, CsvExport::Voters, 0);
+        let first = String::from_utf8(first).unwrap();
+        assert!(first.starts_with("principal\n"));
+        assert_eq!(first.lines().count(), CSV_CHUNK_ROWS + 1);
+        let next = next.expect("one voter is left");
+        assert_eq!(next.chunk, 1);
+
+        let (last, next) = csv_chunk(&proposal, key, CsvExport::Voters, next.chunk);
+        let last = String::from_utf8(last).unwrap();
+        assert_eq!(last.lines().count(), 1);
+        assert!(!last.contains("principal"));
+        assert!(next.is_none());
+    }
+}
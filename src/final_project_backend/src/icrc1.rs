@@ -0,0 +1,88 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::api::call::CallResult;
+use serde_bytes::ByteBuf;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<ByteBuf>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferArg {
+    pub from_subaccount: Option<ByteBuf>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<ByteBuf>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<ByteBuf>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<ByteBuf>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+// Keeps a ledger rejection apart from a failed call, after which the transfer may
+// or may not have happened.
+pub async fn try_transfer(
+    ledger: Principal,
+    arg: TransferArg,
+) -> CallResult<Result<Nat, TransferError>> {
+    let (result,): (Result<Nat, TransferError>,) =
+        ic_cdk::call(ledger, "icrc1_transfer", (arg,)).await?;
+    Ok(result)
+}
+
+pub async fn transfer(ledger: Principal, arg: TransferArg) -> Result<Nat, String> {
+    try_transfer(ledger, arg)
+        .await
+        .map_err(|(code, message)| format!("ledger call failed ({:?}): {}", code, message))?
+        .map_err(|error| format!("ledger rejected transfer: {:?}", error))
+}
+
+pub async fn transfer_from(ledger: Principal, arg: TransferFromArgs) -> Result<Nat, String> {
+    let (result,): (Result<Nat, TransferFromError>,) =
+        ic_cdk::call(ledger, "icrc2_transfer_from", (arg,))
+            .await
+            .map_err(|(code, message)| format!("ledger call failed ({:?}): {}", code, message))?;
+    result.map_err(|error| format!("ledger rejected transfer_from: {:?}", error))
+}
+
+pub async fn balance_of(ledger: Principal, account: Account) -> Result<Nat, String> {
+    let (balance,): (Nat,) = ic_cdk::call(ledger, "icrc1_balance_of", (account,))
+        .await
+        .map_err(|(code, message)| format!("ledger call failed ({:?}): {}", code, message))?;
+    Ok(balance)
+}
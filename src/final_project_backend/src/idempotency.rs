@@ -0,0 +1,244 @@
+use candid::{CandidType, Deserialize, Encode, Principal};
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::ops::Bound;
+use std::time::Duration;
+
+use crate::{
+    config, env, memory, principal_key, validation, CreateProposal, ErrorCode, ErrorContext,
+    Memory, PrincipalKey, VoteError, IDEMPOTENCY_KEYS_MEMORY_ID,
+};
+
+const MAX_IDEMPOTENCY_KEY_BYTES: usize = 64;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+// How long a retry returns the proposal the first call created.
+const KEY_TTL_SECS: u64 = 24 * 60 * 60;
+// A first call that has not finished by then trapped after its await and never will.
+const CLAIM_TTL_SECS: u64 = 10 * 60;
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+type RequestKey = Blob<MAX_IDEMPOTENCY_KEY_BYTES>;
+
+// The proposal is unset while the first call is still waiting on a deposit or snapshot.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct Request {
+    hash: ByteBuf,
+    proposal: Option<u64>,
+    claimed_at: u64,
+}
+
+candid_storable!(Request, 96);
+
+impl Request {
+    fn is_expired(&self, now: u64) -> bool {
+        let ttl = if self.proposal.is_some() {
+            KEY_TTL_SECS
+        } else {
+            CLAIM_TTL_SECS
+        };
+        now >= self
+            .claimed_at
+            .saturating_add(ttl.saturating_mul(NANOS_PER_SEC))
+    }
+}
+
+thread_local! {
+    // Keys are scoped to the caller, so clients only need them unique among their own calls.
+    static REQUESTS: RefCell<StableBTreeMap<(PrincipalKey, RequestKey), Request, Memory>> = RefCell::new(StableBTreeMap::init(memory(IDEMPOTENCY_KEYS_MEMORY_ID)));
+
+    // Where the next expiry run picks up; None starts over from the first key.
+    static EXPIRE_AFTER: RefCell<Option<(PrincipalKey, RequestKey)>> = const { RefCell::new(None) };
+}
+
+fn request_key(caller: &Principal, key: &str) -> Result<(PrincipalKey, RequestKey), VoteError> {
+    validation::text("idempotency_key", key, MAX_IDEMPOTENCY_KEY_BYTES)?;
+    let key = RequestKey::try_from(key.as_bytes()).map_err(|_| ErrorCode::TextTooLong)?;
+    Ok((principal_key(caller), key))
+}
+
+fn hash(proposal: &CreateProposal) -> Result<ByteBuf, VoteError> {
+    let bytes = Encode!(proposal).map_err(|_| ErrorCode::UpdateError)?;
+    Ok(ByteBuf::from(Sha256::digest(bytes).to_vec()))
+}
+
+// Returns the proposal an identical earlier call created, or claims the key for this one.
+// An expired entry counts as no entry, so the key is free again.
+pub fn claim(
+    caller: &Principal,
+    key: &str,
+    proposal: &CreateProposal,
+) -> Result<Option<u64>, VoteError> {
+    let request_key = request_key(caller, key)?;
+    let hash = hash(proposal)?;
+    let now = env::time();
+    let existing = REQUESTS
+        .with(|r| r.borrow().get(&request_key))
+        .filter(|request| !request.is_expired(now));
+    match existing {
+        Some(request) if request.hash != hash => {
+            let error = match request.proposal {
+                Some(key) => {
+                    ErrorCode::IdempotencyConflict.with_context(ErrorContext::Created { key })
+                }
+                None => ErrorCode::IdempotencyConflict.into(),
+            };
+            Err(error.with_field("idempotency_key"))
+        }
+        Some(Request {
+            proposal: Some(key),
+            ..
+        }) => Ok(Some(key)),
+        Some(_) => Err(ErrorCode::IdempotencyConflict
+            .with_message("a request with this idempotency key is still running")
+            .with_field("idempotency_key")),
+        None => {
+            let request = Request {
+                hash,
+                proposal: None,
+                claimed_at: now,
+            };
+            REQUESTS.with(|r| r.borrow_mut().insert(request_key, request));
+            Ok(None)
+        }
+    }
+}
+
+pub fn complete(caller: &Principal, key: &str, proposal: u64) {
+    let Ok(request_key) = request_key(caller, key) else {
+        return;
+    };
+    REQUESTS.with(|r| {
+        let mut requests = r.borrow_mut();
+        if let Some(mut request) = requests.get(&request_key) {
+            request.proposal = Some(proposal);
+            requests.insert(request_key, request);
+        }
+    });
+}
+
+// A failed call frees the key, so retrying it tries again.
+pub fn release(caller: &Principal, key: &str) {
+    if let Ok(request_key) = request_key(caller, key) {
+        REQUESTS.with(|r| r.borrow_mut().remove(&request_key));
+    }
+}
+
+// Each run looks at up to `max_scan_per_call` keys, resuming where the last one stopped,
+// so the whole map is swept a slice at a time.
+fn expire(now: u64) {
+    let start = EXPIRE_AFTER.with(|a| a.borrow().map_or(Bound::Unbounded, Bound::Excluded));
+    let (expired, next) = REQUESTS.with(|r| {
+        config::scan_page(
+            r.borrow().range((start, Bound::Unbounded)),
+            usize::MAX,
+            |key, request| request.is_expired(now).then_some(*key),
+        )
+    });
+    REQUESTS.with(|r| {
+        let mut requests = r.borrow_mut();
+        for key in expired {
+            requests.remove(&key);
+        }
+    });
+    EXPIRE_AFTER.with(|a| *a.borrow_mut() = next);
+}
+
+pub fn start_expiry_timer() {
+    ic_cdk_timers::set_timer_interval(EXPIRY_INTERVAL, || {
+        if !config::get().paused {
+            expire(env::time());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{as_caller, proposal, NOW};
+
+    fn stored() -> u64 {
+        REQUESTS.with(|r| r.borrow().len())
+    }
+
+    #[test]
+    fn a_completed_request_is_replayed() {
+        let caller = as_caller(1);
+        let first = proposal("first");
+        assert_eq!(claim(&caller, "key", &first).unwrap(), None);
+        complete(&caller, "key", 4);
+        assert_eq!(claim(&caller, "key", &first).unwrap(), Some(4));
+    }
+
+    #[test]
+    fn a_key_belongs_to_one_request() {
+        let caller = as_caller(1);
+        claim(&caller, "key", &proposal("first")).unwrap();
+        let running = claim(&caller, "key", &proposal("first"));
+        assert!(matches!(running, Err(VoteError::IdempotencyConflict(_))));
+        complete(&caller, "key", 4);
+        let Err(VoteError::IdempotencyConflict(info)) = claim(&caller, "key", &proposal("other"))
+        else {
+            panic!("a different request reused the key");
+        };
+        assert!(matches!(
+            info.context,
+            Some(ErrorContext::Created { key: 4 })
+        ));
+    }
+
+    #[test]
+    fn keys_are_scoped_to_the_caller() {
+        let first = as_caller(1);
+        claim(&first, "key", &proposal("first")).unwrap();
+        let second = as_caller(2);
+        assert_eq!(claim(&second, "key", &proposal("second")).unwrap(), None);
+    }
+
+    #[test]
+    fn a_released_key_is_free_again() {
+        let caller = as_caller(1);
+        claim(&caller, "key", &proposal("first")).unwrap();
+        release(&caller, "key");
+        assert_eq!(claim(&caller, "key", &proposal("other")).unwrap(), None);
+    }
+
+    #[test]
+    fn stale_claims_and_old_keys_expire() {
+        let caller = as_caller(1);
+        claim(&caller, "running", &proposal("first")).unwrap();
+        claim(&caller, "done", &proposal("second")).unwrap();
+        complete(&caller, "done", 4);
+
+        let after_claim = NOW + CLAIM_TTL_SECS * NANOS_PER_SEC;
+        env::set_time(after_claim);
+        assert_eq!(claim(&caller, "running", &proposal("other")).unwrap(), None);
+        assert_eq!(
+            claim(&caller, "done", &proposal("second")).unwrap(),
+            Some(4)
+        );
+
+        expire(after_claim + CLAIM_TTL_SECS * NANOS_PER_SEC);
+        assert_eq!(stored(), 1);
+        expire(NOW + KEY_TTL_SECS * NANOS_PER_SEC);
+        assert_eq!(stored(), 0);
+    }
+
+    #[test]
+    fn expiry_resumes_where_it_stopped() {
+        let caller = as_caller(1);
+        config::update(|config| config.max_scan_per_call = 10);
+        for n in 0..=10 {
+            claim(&caller, &n.to_string(), &proposal("many")).unwrap();
+        }
+        let later = NOW + CLAIM_TTL_SECS * NANOS_PER_SEC;
+        expire(later);
+        assert_eq!(stored(), 1);
+        assert!(EXPIRE_AFTER.with(|a| a.borrow().is_some()));
+        expire(later);
+        assert_eq!(stored(), 0);
+        assert!(EXPIRE_AFTER.with(|a| a.borrow().is_none()));
+    }
+}
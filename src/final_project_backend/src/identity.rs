@@ -0,0 +1,163 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use serde_bytes::ByteBuf;
+use std::cell::RefCell;
+
+use crate::{
+    ballots, config, ed25519, env, memory, principal_from_key, principal_key, signaling, ErrorCode,
+    Memory, PrincipalKey, VoteError, IDENTITY_LINKS_MEMORY_ID, LINKED_IDENTITIES_MEMORY_ID,
+};
+
+const MAX_LINKED_IDENTITIES: usize = 10;
+
+// Internet Identity gives a person a different principal on every frontend origin, so
+// ballots are cast under a canonical identity that the other principals link to. Voter
+// lists, council seats and weight tables name canonical identities.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum IdentityProof {
+    // The Ed25519 key behind the canonical identity signed `get_link_payload` for the
+    // caller; the canonical identity is the key's self-authenticating principal.
+    Signed {
+        public_key: ByteBuf,
+        signature: ByteBuf,
+    },
+    // The configured attestation canister confirms both principals belong together.
+    Attested {
+        canonical: Principal,
+    },
+}
+
+thread_local! {
+    // alternate -> canonical, and the reverse for counting and listing.
+    static LINKS: RefCell<StableBTreeMap<PrincipalKey, PrincipalKey, Memory>> = RefCell::new(StableBTreeMap::init(memory(IDENTITY_LINKS_MEMORY_ID)));
+
+    static LINKED: RefCell<StableBTreeMap<(PrincipalKey, PrincipalKey), u64, Memory>> = RefCell::new(StableBTreeMap::init(memory(LINKED_IDENTITIES_MEMORY_ID)));
+}
+
+pub fn canonical(principal: &Principal) -> Principal {
+    LINKS
+        .with(|l| l.borrow().get(&principal_key(principal)))
+        .map_or(*principal, |canonical| principal_from_key(&canonical))
+}
+
+// The authenticated caller as it votes.
+pub fn voter() -> Result<Principal, VoteError> {
+    Ok(canonical(&env::authenticated_caller()?))
+}
+
+fn linked_to(canonical: &Principal) -> Vec<Principal> {
+    let canonical = principal_key(canonical);
+    LINKED.with(|l| {
+        l.borrow()
+            .range((canonical, PrincipalKey::default())..)
+            .take_while(|((c, _), _)| *c == canonical)
+            .map(|((_, alternate), _)| principal_from_key(&alternate))
+            .collect()
+    })
+}
+
+fn payload(alternate: &Principal) -> Vec<u8> {
+    format!("icp-vote-link:{}:{}", ic_cdk::id(), alternate).into_bytes()
+}
+
+async fn prove(alternate: Principal, proof: IdentityProof) -> Result<Principal, VoteError> {
+    let invalid = || ErrorCode::InvalidIdentityProof.with_field("proof");
+    match proof {
+        IdentityProof::Signed {
+            public_key,
+            signature,
+        } => {
+            let public_key: [u8; 32] = public_key.as_slice().try_into().map_err(|_| invalid())?;
+            let signature: [u8; 64] = signature.as_slice().try_into().map_err(|_| invalid())?;
+            if !ed25519::verify(&public_key, &payload(&alternate), &signature) {
+                return Err(invalid());
+            }
+            Ok(signaling::principal_of(&public_key))
+        }
+        IdentityProof::Attested { canonical } => {
+            let Some(attestor) = config::get().identity_attestor else {
+                return Err(ErrorCode::InvalidIdentityProof
+                    .with_message("no attestation canister is configured")
+                    .with_field("proof"));
+            };
+            let (attested,): (bool,) =
+                ic_cdk::call(attestor, "attest_identity_link", (alternate, canonical))
+                    .await
+                    .map_err(|(_, message)| {
+                        ErrorCode::InvalidIdentityProof.with_message(message)
+                    })?;
+            if !attested {
+                return Err(invalid());
+            }
+            Ok(canonical)
+        }
+    }
+}
+
+// A principal that voted under its own name would vote a second time through its
+// canonical identity, so only fresh principals can be linked. Links are permanent for
+// the same reason.
+fn check_linkable(alternate: &Principal, canonical: &Principal) -> Result<(), VoteError> {
+    if canonical == alternate || *canonical == Principal::anonymous() {
+        return Err(ErrorCode::InvalidIdentityProof
+            .with_message("the canonical identity must be another, authenticated principal"));
+    }
+    if LINKS.with(|l| l.borrow().contains_key(&principal_key(alternate))) {
+        return Err(ErrorCode::AlreadyLinked.into());
+    }
+    if !linked_to(alternate).is_empty() {
+        return Err(ErrorCode::AlreadyLinked
+            .with_message("principals that others link to cannot be linked themselves"));
+    }
+    if ballots::has_voted_anywhere(alternate) {
+        return Err(ErrorCode::AlreadyLinked
+            .with_message("the caller has already voted under its own principal"));
+    }
+    if linked_to(canonical).len() >= MAX_LINKED_IDENTITIES {
+        return Err(ErrorCode::InvalidItemCount.with_message(format!(
+            "an identity can have at most {} linked principals",
+            MAX_LINKED_IDENTITIES
+        )));
+    }
+    Ok(())
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_link_payload() -> ByteBuf {
+    ByteBuf::from(payload(&env::caller()))
+}
+
+// Links the caller to the identity in the proof, or to whatever that identity is
+// itself linked to. Returns the canonical identity.
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+async fn link_identity(proof: IdentityProof) -> Result<Principal, VoteError> {
+    config::ensure_not_paused()?;
+    let alternate = env::authenticated_caller()?;
+    let canonical = canonical(&prove(alternate, proof).await?);
+    check_linkable(&alternate, &canonical)?;
+    link(&alternate, &canonical);
+    Ok(canonical)
+}
+
+pub fn link(alternate: &Principal, canonical: &Principal) {
+    let (alternate_key, canonical_key) = (principal_key(alternate), principal_key(canonical));
+    LINKS.with(|l| l.borrow_mut().insert(alternate_key, canonical_key));
+    LINKED.with(|l| {
+        l.borrow_mut()
+            .insert((canonical_key, alternate_key), env::time())
+    });
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_canonical_identity(principal: Principal) -> Principal {
+    canonical(&principal)
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_linked_identities(principal: Principal) -> Vec<Principal> {
+    linked_to(&canonical(&principal))
+}
@@ -0,0 +1,309 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use std::cell::RefCell;
+use std::ops::Bound;
+use std::time::Duration;
+
+use crate::comments::Comment;
+use crate::events::EventKind;
+use crate::{
+    ballots, config, env, identity, memory, principal_key, Memory, PrincipalKey, Proposal,
+    INBOX_MEMORY_ID, INBOX_SEQ_MEMORY_ID, PENDING_OUTCOMES_MEMORY_ID,
+};
+
+const MAX_MENTIONS_PER_COMMENT: usize = 10;
+const FANOUT_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum NotificationKind {
+    Mention {
+        comment: u64,
+        author: Principal,
+    },
+    ProposalOutcome {
+        approve: u32,
+        reject: u32,
+        pass: u32,
+    },
+    FollowedUpdate {
+        event: EventKind,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Notification {
+    pub id: u64,
+    pub proposal: u64,
+    pub kind: NotificationKind,
+    pub created_at: u64,
+    pub read: bool,
+}
+
+impl Default for Notification {
+    fn default() -> Self {
+        Notification {
+            id: 0,
+            proposal: 0,
+            kind: NotificationKind::FollowedUpdate {
+                event: EventKind::default(),
+            },
+            created_at: 0,
+            read: true,
+        }
+    }
+}
+
+candid_storable!(Notification, 256);
+
+// An outcome still to be delivered to a closed proposal's voters, and the voter the
+// last run stopped after.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct PendingOutcome {
+    approve: u32,
+    reject: u32,
+    pass: u32,
+    after: Option<Principal>,
+}
+
+candid_storable!(PendingOutcome, 128);
+
+thread_local! {
+    // Keys store `u64::MAX - id` so that iterating a member's range yields the newest entries first.
+    static INBOX: RefCell<StableBTreeMap<(PrincipalKey, u64), Notification, Memory>> = RefCell::new(StableBTreeMap::init(memory(INBOX_MEMORY_ID)));
+
+    static NEXT_NOTIFICATION_ID: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(memory(INBOX_SEQ_MEMORY_ID), 0).expect("failed to initialize notification ids")
+    );
+
+    static PENDING_OUTCOMES: RefCell<StableBTreeMap<u64, PendingOutcome, Memory>> = RefCell::new(StableBTreeMap::init(memory(PENDING_OUTCOMES_MEMORY_ID)));
+}
+
+// Filed under the recipient's canonical identity, so every linked principal reads the
+// same inbox.
+fn recipient_key(recipient: &Principal) -> PrincipalKey {
+    principal_key(&identity::canonical(recipient))
+}
+
+fn inbox_key(recipient: &Principal, id: u64) -> (PrincipalKey, u64) {
+    (recipient_key(recipient), u64::MAX - id)
+}
+
+pub fn notify(recipient: Principal, proposal: u64, kind: NotificationKind) {
+    let id = NEXT_NOTIFICATION_ID.with(|n| {
+        let id = *n.borrow().get();
+        n.borrow_mut()
+            .set(id + 1)
+            .expect("failed to advance notification ids");
+        id
+    });
+    let notification = Notification {
+        id,
+        proposal,
+        kind,
+        created_at: env::time(),
+        read: false,
+    };
+    INBOX.with(|i| {
+        i.borrow_mut()
+            .insert(inbox_key(&recipient, id), notification)
+    });
+}
+
+// Mentions are written as `@<principal>` anywhere in the comment text.
+pub fn notify_mentions(comment: &Comment) {
+    let mut mentioned: Vec<Principal> = comment
+        .text
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .filter_map(|text| {
+            Principal::from_text(text.trim_end_matches(|c: char| !c.is_alphanumeric())).ok()
+        })
+        .filter(|principal| *principal != comment.author)
+        .collect();
+    mentioned.sort();
+    mentioned.dedup();
+
+    for recipient in mentioned.into_iter().take(MAX_MENTIONS_PER_COMMENT) {
+        notify(
+            recipient,
+            comment.proposal,
+            NotificationKind::Mention {
+                comment: comment.id,
+                author: comment.author,
+            },
+        );
+    }
+}
+
+// Queued rather than written here, since a proposal can have more voters than one call
+// can notify.
+pub fn notify_outcome(key: u64, proposal: &Proposal) {
+    let pending = PendingOutcome {
+        approve: proposal.approve,
+        reject: proposal.reject,
+        pass: proposal.pass,
+        after: None,
+    };
+    PENDING_OUTCOMES.with(|p| p.borrow_mut().insert(key, pending));
+}
+
+// Each run notifies up to `max_scan_per_call` voters of the oldest queued outcome,
+// resuming after the last voter the previous run reached.
+fn fan_out() {
+    let Some((key, mut pending)) = PENDING_OUTCOMES.with(|p| p.borrow().iter().next()) else {
+        return;
+    };
+    let (voters, next) = ballots::voters_after(key, pending.after, usize::MAX);
+    for voter in voters {
+        notify(
+            voter,
+            key,
+            NotificationKind::ProposalOutcome {
+                approve: pending.approve,
+                reject: pending.reject,
+                pass: pending.pass,
+            },
+        );
+    }
+    PENDING_OUTCOMES.with(|p| {
+        let mut outcomes = p.borrow_mut();
+        match next {
+            Some(voter) => {
+                pending.after = Some(voter);
+                outcomes.insert(key, pending);
+            }
+            None => {
+                outcomes.remove(&key);
+            }
+        }
+    });
+}
+
+pub fn start_fanout_timer() {
+    ic_cdk_timers::set_timer_interval(FANOUT_INTERVAL, || {
+        if !config::get().paused {
+            fan_out();
+        }
+    });
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct NotificationPage {
+    pub notifications: Vec<Notification>,
+    // The id of the last notification looked at.
+    pub next: Option<u64>,
+}
+
+pub fn inbox_page(after: Option<u64>, page: usize) -> NotificationPage {
+    let recipient = recipient_key(&env::caller());
+    let start = after.map_or(Bound::Included((recipient, 0)), |id| {
+        Bound::Excluded((recipient, u64::MAX - id))
+    });
+    let (notifications, next) = INBOX.with(|i| {
+        config::scan_page(
+            i.borrow()
+                .range((start, Bound::Included((recipient, u64::MAX)))),
+            page,
+            |_, notification| Some(notification),
+        )
+    });
+    NotificationPage {
+        notifications,
+        next: next.map(|(_, inverted)| u64::MAX - inverted),
+    }
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_inbox_v2(after: Option<u64>, limit: u64) -> NotificationPage {
+    inbox_page(after, config::page_limit(limit))
+}
+
+// Counted among the newest `max_scan_per_call` notifications.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_unread_count() -> u64 {
+    let recipient = recipient_key(&env::caller());
+    let (unread, _) = INBOX.with(|i| {
+        config::scan_page(
+            i.borrow().range((recipient, 0)..=(recipient, u64::MAX)),
+            usize::MAX,
+            |_, notification| (!notification.read).then_some(()),
+        )
+    });
+    unread.len() as u64
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn mark_read(ids: Vec<u64>) -> u64 {
+    // Nothing is ever delivered to the anonymous principal.
+    let Ok(caller) = env::authenticated_caller() else {
+        return 0;
+    };
+    if config::get().paused {
+        return 0;
+    }
+    let mut marked = 0;
+    INBOX.with(|i| {
+        let mut inbox = i.borrow_mut();
+        for id in ids {
+            let key = inbox_key(&caller, id);
+            if let Some(mut notification) = inbox.get(&key) {
+                if !notification.read {
+                    notification.read = true;
+                    inbox.insert(key, notification);
+                    marked += 1;
+                }
+            }
+        }
+    });
+    marked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_proposal;
+    use crate::testing::{as_caller, open_proposal, principal};
+
+    fn delivered() -> u64 {
+        INBOX.with(|i| i.borrow().len())
+    }
+
+    #[test]
+    fn outcomes_reach_every_voter_a_slice_at_a_time() {
+        as_caller(1);
+        config::update(|config| config.max_scan_per_call = 2);
+        let key = open_proposal("outcome");
+        for id in 2..5 {
+            ballots::add_voter(key, &principal(id));
+        }
+        notify_outcome(key, &load_proposal(key).unwrap());
+        assert_eq!(delivered(), 0);
+        fan_out();
+        assert_eq!(delivered(), 2);
+        fan_out();
+        assert_eq!(delivered(), 3);
+        assert!(PENDING_OUTCOMES.with(|p| p.borrow().is_empty()));
+    }
+
+    #[test]
+    fn a_linked_principal_reads_the_canonical_inbox() {
+        let alternate = as_caller(1);
+        identity::link(&alternate, &principal(2));
+        notify(
+            principal(2),
+            0,
+            NotificationKind::Mention {
+                comment: 0,
+                author: principal(3),
+            },
+        );
+        let page = inbox_page(None, 10);
+        assert_eq!(page.notifications.len(), 1);
+        assert_eq!(get_unread_count(), 1);
+        assert_eq!(mark_read(vec![page.notifications[0].id]), 1);
+        assert_eq!(get_unread_count(), 0);
+    }
+}
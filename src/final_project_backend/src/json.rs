@@ -0,0 +1,293 @@
+use candid::parser::value::{IDLField, IDLValue, VariantValue};
+use candid::types::Label;
+use candid::{CandidType, Encode, IDLArgs, TypeEnv};
+
+use crate::weights::WeightedTally;
+use crate::{http, load_proposal, ErrorCode, Proposal, VoteError};
+
+#[derive(CandidType)]
+struct ProposalResults {
+    key: u64,
+    is_active: bool,
+    created_at: Option<u64>,
+    closed_at: Option<u64>,
+    total: u32,
+    choices: Vec<ChoiceResult>,
+    weights: Option<WeightedTally>,
+}
+
+#[derive(CandidType)]
+struct ListedProposal {
+    key: u64,
+    proposal: Proposal,
+}
+
+#[derive(CandidType)]
+struct ErrorBody {
+    error: String,
+}
+
+#[derive(CandidType)]
+struct ChoiceResult {
+    choice: String,
+    votes: u32,
+    percentage: String,
+}
+
+fn results(key: u64, proposal: &Proposal) -> ProposalResults {
+    let total = proposal.approve + proposal.reject + proposal.pass;
+    let choices = [
+        ("approve", proposal.approve),
+        ("reject", proposal.reject),
+        ("pass", proposal.pass),
+    ]
+    .into_iter()
+    .map(|(choice, votes)| ChoiceResult {
+        choice: choice.to_string(),
+        votes,
+        percentage: http::percentage(votes, total),
+    })
+    .collect();
+    ProposalResults {
+        key,
+        is_active: proposal.is_active,
+        created_at: proposal.created_at,
+        closed_at: proposal.closed_at,
+        total,
+        choices,
+        weights: proposal.weights,
+    }
+}
+
+pub fn proposals_json(proposals: Vec<(u64, Proposal)>) -> Result<String, VoteError> {
+    let listed: Vec<ListedProposal> = proposals
+        .into_iter()
+        .map(|(key, proposal)| ListedProposal { key, proposal })
+        .collect();
+    to_json(&listed)
+}
+
+pub fn results_json(key: u64, proposal: &Proposal) -> Result<String, VoteError> {
+    to_json(&results(key, proposal))
+}
+
+pub fn error_json(message: &str) -> String {
+    let body = ErrorBody {
+        error: message.to_string(),
+    };
+    to_json(&body).unwrap_or_default()
+}
+
+// Goes through the Candid encoding so every type with a Candid interface gets the same
+// JSON shape. Object keys are sorted and 64-bit or wider integers are written as strings,
+// since JSON numbers lose precision past 2^53 and timestamps are in nanoseconds.
+pub fn to_json<T: CandidType>(value: &T) -> Result<String, VoteError> {
+    let bytes = Encode!(value).map_err(|_| ErrorCode::UpdateError)?;
+    let args = IDLArgs::from_bytes_with_types(&bytes, &TypeEnv::new(), &[T::ty()])
+        .map_err(|_| ErrorCode::CorruptedRecord)?;
+    let mut out = String::new();
+    if let Some(value) = args.args.first() {
+        write_value(&mut out, value);
+    }
+    Ok(out)
+}
+
+fn write_value(out: &mut String, value: &IDLValue) {
+    match value {
+        IDLValue::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        IDLValue::Null | IDLValue::None | IDLValue::Reserved => out.push_str("null"),
+        IDLValue::Text(text) => write_string(out, text),
+        IDLValue::Number(n) => out.push_str(n),
+        IDLValue::Float64(f) => write_float(out, *f),
+        IDLValue::Float32(f) => write_float(out, *f as f64),
+        IDLValue::Opt(inner) => write_value(out, inner),
+        IDLValue::Vec(items) => write_array(out, items.iter()),
+        IDLValue::Record(fields) if is_tuple(fields) => {
+            write_array(out, fields.iter().map(|field| &field.val))
+        }
+        IDLValue::Record(fields) => {
+            let mut fields: Vec<(String, &IDLValue)> = fields
+                .iter()
+                .map(|field| (label(&field.id), &field.val))
+                .collect();
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+            write_object(out, fields);
+        }
+        // Variants without a payload read better as plain strings.
+        IDLValue::Variant(VariantValue(field, _)) => match field.val {
+            IDLValue::Null => write_string(out, &label(&field.id)),
+            _ => write_object(out, vec![(label(&field.id), &field.val)]),
+        },
+        IDLValue::Principal(principal) | IDLValue::Service(principal) => {
+            write_string(out, &principal.to_text())
+        }
+        IDLValue::Func(principal, method) => {
+            write_object(
+                out,
+                vec![
+                    ("method".to_string(), &IDLValue::Text(method.clone())),
+                    ("principal".to_string(), &IDLValue::Principal(*principal)),
+                ],
+            );
+        }
+        IDLValue::Int(n) => write_string(out, &n.to_string()),
+        IDLValue::Nat(n) => write_string(out, &n.to_string()),
+        IDLValue::Nat64(n) => write_string(out, &n.to_string()),
+        IDLValue::Int64(n) => write_string(out, &n.to_string()),
+        IDLValue::Nat8(n) => out.push_str(&n.to_string()),
+        IDLValue::Nat16(n) => out.push_str(&n.to_string()),
+        IDLValue::Nat32(n) => out.push_str(&n.to_string()),
+        IDLValue::Int8(n) => out.push_str(&n.to_string()),
+        IDLValue::Int16(n) => out.push_str(&n.to_string()),
+        IDLValue::Int32(n) => out.push_str(&n.to_string()),
+    }
+}
+
+fn is_tuple(fields: &[IDLField]) -> bool {
+    !fields.is_empty()
+        && fields.iter().enumerate().all(
+            |(i, field)| matches!(field.id, Label::Id(n) | Label::Unnamed(n) if n as usize == i),
+        )
+}
+
+fn label(id: &Label) -> String {
+    match id {
+        Label::Named(name) => name.clone(),
+        Label::Id(n) | Label::Unnamed(n) => n.to_string(),
+    }
+}
+
+fn write_array<'a>(out: &mut String, items: impl Iterator<Item = &'a IDLValue>) {
+    out.push('[');
+    for (i, item) in items.enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_value(out, item);
+    }
+    out.push(']');
+}
+
+fn write_object(out: &mut String, fields: Vec<(String, &IDLValue)>) {
+    out.push('{');
+    for (i, (name, value)) in fields.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_string(out, &name);
+        out.push(':');
+        write_value(out, value);
+    }
+    out.push('}');
+}
+
+fn write_float(out: &mut String, f: f64) {
+    if f.is_finite() {
+        out.push_str(&f.to_string());
+    } else {
+        out.push_str("null");
+    }
+}
+
+fn write_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_proposal_json(key: u64) -> Result<String, VoteError> {
+    to_json(&load_proposal(key)?)
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_results_json(key: u64) -> Result<String, VoteError> {
+    let proposal = load_proposal(key)?;
+    results_json(key, &proposal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::{Int, Nat, Principal};
+
+    #[derive(CandidType)]
+    struct Sample {
+        zebra: bool,
+        alpha: u32,
+        big: u64,
+        signed: Int,
+        wide: Nat,
+        missing: Option<u8>,
+        present: Option<String>,
+        pair: (u8, String),
+        list: Vec<i8>,
+        state: State,
+        owner: Principal,
+    }
+
+    #[derive(CandidType)]
+    enum State {
+        Closed,
+    }
+
+    #[derive(CandidType)]
+    enum Outcome {
+        Failed { reason: String },
+    }
+
+    #[test]
+    fn records_become_objects_with_sorted_keys() {
+        let sample = Sample {
+            zebra: true,
+            alpha: 7,
+            big: u64::MAX,
+            signed: Int::from(-5),
+            wide: Nat::from(12u8),
+            missing: None,
+            present: Some("here".to_string()),
+            pair: (1, "one".to_string()),
+            list: vec![-1, 2],
+            state: State::Closed,
+            owner: Principal::anonymous(),
+        };
+        assert_eq!(
+            to_json(&sample).unwrap(),
+            "{\"alpha\":7,\"big\":\"18446744073709551615\",\"list\":[-1,2],\"missing\":null,\
+             \"owner\":\"2vxsx-fae\",\"pair\":[1,\"one\"],\"present\":\"here\",\"signed\":\"-5\",\
+             \"state\":\"Closed\",\"wide\":\"12\",\"zebra\":true}"
+        );
+    }
+
+    #[test]
+    fn variants_with_a_payload_become_single_key_objects() {
+        let failed = Outcome::Failed {
+            reason: "trapped".to_string(),
+        };
+        assert_eq!(
+            to_json(&failed).unwrap(),
+            "{\"Failed\":{\"reason\":\"trapped\"}}"
+        );
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(
+            to_json(&"a \"quote\"\\\n\t\u{1}".to_string()).unwrap(),
+            "\"a \\\"quote\\\"\\\\\\n\\t\\u0001\""
+        );
+        assert_eq!(error_json("no"), "{\"error\":\"no\"}");
+    }
+}
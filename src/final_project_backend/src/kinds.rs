@@ -0,0 +1,314 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use serde_bytes::ByteBuf;
+
+use crate::config::ConfigDiff;
+use crate::constitution::MAX_CONSTITUTION_BYTES;
+use crate::elections::MAX_SEATS;
+use crate::grants;
+use crate::icrc1::Account;
+use crate::parameters::{self, ParameterValue};
+
+const MAX_CALL_ARG_BYTES: usize = 2048;
+const MAX_METHOD_NAME_BYTES: usize = 100;
+const MAX_MEMO_BYTES: usize = 32;
+const MAX_BUNDLE_ACTIONS: usize = 8;
+const MAX_SUBDAO_NAME_BYTES: usize = 64;
+const MAX_SUBDAO_ADMINS: usize = 10;
+const MAX_MILESTONES: usize = 10;
+const MAX_MILESTONE_DESCRIPTION_BYTES: usize = 200;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ProposalKind {
+    Motion,
+    ExecuteCall(CanisterCall),
+    Transfer(Transfer),
+    ConfigChange(Box<ConfigDiff>),
+    Upgrade(UpgradeCanister),
+    Election(Election),
+    Amendment(Amendment),
+    Omnibus(Vec<ProposalKind>),
+    CreateSubDao(SubDao),
+    Grant(Grant),
+    ReleaseMilestone(ReleaseMilestone),
+    SetParameter(SetParameter),
+    Signaling,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CanisterCall {
+    pub canister_id: Principal,
+    pub method: String,
+    pub args: ByteBuf,
+    pub cycles: u128,
+}
+
+// The short form of an `ExecuteCall` that attaches no cycles.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ExecutionPayload {
+    pub canister_id: Principal,
+    pub method: String,
+    pub arg_blob: ByteBuf,
+}
+
+impl From<ExecutionPayload> for ProposalKind {
+    fn from(payload: ExecutionPayload) -> Self {
+        ProposalKind::ExecuteCall(CanisterCall {
+            canister_id: payload.canister_id,
+            method: payload.method,
+            args: payload.arg_blob,
+            cycles: 0,
+        })
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Transfer {
+    pub ledger: Principal,
+    pub to: Account,
+    pub amount: Nat,
+    pub memo: Option<ByteBuf>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct UpgradeCanister {
+    pub canister_id: Principal,
+    pub wasm_hash: ByteBuf,
+    pub arg: Option<ByteBuf>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Election {
+    pub seats: u8,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Amendment {
+    pub text: String,
+    pub base_version: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SubDao {
+    pub name: String,
+    pub wasm_hash: ByteBuf,
+    pub cycles: u128,
+    pub admins: Vec<Principal>,
+    pub event_retention_days: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Milestone {
+    pub description: String,
+    pub amount: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Grant {
+    pub ledger: Principal,
+    pub recipient: Account,
+    pub milestones: Vec<Milestone>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ReleaseMilestone {
+    pub grant: u64,
+    pub milestone: u32,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SetParameter {
+    pub key: String,
+    pub value: Option<ParameterValue>,
+}
+
+impl ProposalKind {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            ProposalKind::Motion | ProposalKind::Signaling => Ok(()),
+            ProposalKind::ExecuteCall(call) => {
+                if call.method.is_empty() || call.method.len() > MAX_METHOD_NAME_BYTES {
+                    return Err("ExecuteCall method name is empty or too long".to_string());
+                }
+                if call.args.len() > MAX_CALL_ARG_BYTES {
+                    return Err(format!(
+                        "ExecuteCall args exceed {} bytes",
+                        MAX_CALL_ARG_BYTES
+                    ));
+                }
+                Ok(())
+            }
+            ProposalKind::Transfer(transfer) => {
+                if transfer.amount == 0u64 {
+                    return Err("Transfer amount must be positive".to_string());
+                }
+                if transfer
+                    .to
+                    .subaccount
+                    .as_ref()
+                    .is_some_and(|s| s.len() != 32)
+                {
+                    return Err("Transfer subaccount must be 32 bytes".to_string());
+                }
+                if transfer
+                    .memo
+                    .as_ref()
+                    .is_some_and(|m| m.len() > MAX_MEMO_BYTES)
+                {
+                    return Err(format!("Transfer memo exceeds {} bytes", MAX_MEMO_BYTES));
+                }
+                Ok(())
+            }
+            ProposalKind::ConfigChange(diff) => diff.validate(),
+            ProposalKind::Upgrade(upgrade) => {
+                if upgrade.wasm_hash.len() != 32 {
+                    return Err("Upgrade wasm_hash must be a 32-byte sha256".to_string());
+                }
+                if upgrade.canister_id == ic_cdk::id() {
+                    return Err("Upgrade cannot target this canister".to_string());
+                }
+                if upgrade
+                    .arg
+                    .as_ref()
+                    .is_some_and(|arg| arg.len() > MAX_CALL_ARG_BYTES)
+                {
+                    return Err(format!("Upgrade arg exceeds {} bytes", MAX_CALL_ARG_BYTES));
+                }
+                Ok(())
+            }
+            ProposalKind::Election(election) => {
+                if election.seats == 0 || election.seats > MAX_SEATS {
+                    return Err(format!(
+                        "Election seats must be between 1 and {}",
+                        MAX_SEATS
+                    ));
+                }
+                Ok(())
+            }
+            ProposalKind::Amendment(amendment) => {
+                if amendment.text.trim().is_empty() || amendment.text.len() > MAX_CONSTITUTION_BYTES
+                {
+                    return Err(format!(
+                        "Amendment text must be between 1 and {} bytes",
+                        MAX_CONSTITUTION_BYTES
+                    ));
+                }
+                Ok(())
+            }
+            ProposalKind::Omnibus(actions) => {
+                if actions.is_empty() || actions.len() > MAX_BUNDLE_ACTIONS {
+                    return Err(format!(
+                        "Omnibus must bundle between 1 and {} actions",
+                        MAX_BUNDLE_ACTIONS
+                    ));
+                }
+                if actions.iter().any(|action| {
+                    !action.is_executable()
+                        || matches!(
+                            action,
+                            ProposalKind::Omnibus(_)
+                                | ProposalKind::Grant(_)
+                                | ProposalKind::ReleaseMilestone(_)
+                        )
+                }) {
+                    return Err(
+                        "Omnibus can only bundle executable actions other than bundles and grants"
+                            .to_string(),
+                    );
+                }
+                let count = |kind: fn(&ProposalKind) -> bool| {
+                    actions.iter().filter(|action| kind(action)).count()
+                };
+                if count(|a| matches!(a, ProposalKind::Amendment(_))) > 1
+                    || count(|a| matches!(a, ProposalKind::CreateSubDao(_))) > 1
+                {
+                    return Err(
+                        "Omnibus can contain at most one Amendment and one CreateSubDao"
+                            .to_string(),
+                    );
+                }
+                actions.iter().try_for_each(ProposalKind::validate)
+            }
+            ProposalKind::CreateSubDao(subdao) => {
+                if subdao.name.trim().is_empty() || subdao.name.len() > MAX_SUBDAO_NAME_BYTES {
+                    return Err(format!(
+                        "CreateSubDao name must be between 1 and {} bytes",
+                        MAX_SUBDAO_NAME_BYTES
+                    ));
+                }
+                if subdao.wasm_hash.len() != 32 {
+                    return Err("CreateSubDao wasm_hash must be a 32-byte sha256".to_string());
+                }
+                if subdao.admins.is_empty() || subdao.admins.len() > MAX_SUBDAO_ADMINS {
+                    return Err(format!(
+                        "CreateSubDao needs between 1 and {} admins",
+                        MAX_SUBDAO_ADMINS
+                    ));
+                }
+                if subdao.event_retention_days == Some(0) {
+                    return Err("CreateSubDao event_retention_days must be at least 1".to_string());
+                }
+                Ok(())
+            }
+            ProposalKind::Grant(grant) => {
+                if grant.milestones.is_empty() || grant.milestones.len() > MAX_MILESTONES {
+                    return Err(format!(
+                        "Grant needs between 1 and {} milestones",
+                        MAX_MILESTONES
+                    ));
+                }
+                if grant
+                    .recipient
+                    .subaccount
+                    .as_ref()
+                    .is_some_and(|s| s.len() != 32)
+                {
+                    return Err("Grant recipient subaccount must be 32 bytes".to_string());
+                }
+                for milestone in &grant.milestones {
+                    if milestone.amount == 0u64 {
+                        return Err("Grant milestone amounts must be positive".to_string());
+                    }
+                    if milestone.description.trim().is_empty()
+                        || milestone.description.len() > MAX_MILESTONE_DESCRIPTION_BYTES
+                    {
+                        return Err(format!(
+                            "Grant milestone descriptions must be between 1 and {} bytes",
+                            MAX_MILESTONE_DESCRIPTION_BYTES
+                        ));
+                    }
+                }
+                Ok(())
+            }
+            ProposalKind::ReleaseMilestone(release) => grants::check_release(release),
+            ProposalKind::SetParameter(change) => parameters::validate(change),
+        }
+    }
+
+    pub fn requires_supermajority(&self) -> bool {
+        match self {
+            ProposalKind::Amendment(_) => true,
+            ProposalKind::Omnibus(actions) => {
+                actions.iter().any(ProposalKind::requires_supermajority)
+            }
+            _ => false,
+        }
+    }
+
+    // Actions that only change this canister's own state.
+    pub fn is_local(&self) -> bool {
+        matches!(
+            self,
+            ProposalKind::ConfigChange(_)
+                | ProposalKind::Amendment(_)
+                | ProposalKind::Grant(_)
+                | ProposalKind::SetParameter(_)
+        )
+    }
+
+    pub fn is_executable(&self) -> bool {
+        !matches!(
+            self,
+            ProposalKind::Motion | ProposalKind::Election(_) | ProposalKind::Signaling
+        )
+    }
+}
@@ -0,0 +1,309 @@
+use candid::{CandidType, Deserialize, Principal};
+
+use crate::archive::{self, ArchivedProposal};
+use crate::ballots::MyVote;
+use crate::comments::{self, Comment, ReportedComment};
+use crate::constitution::{self, ConstitutionVersion};
+use crate::events::{self, Event};
+use crate::follows::{self, Followed};
+use crate::inbox::{self, Notification};
+use crate::listing::ProposalFilter;
+use crate::tags::{self, TagCount};
+use crate::webhooks::{self, Delivery};
+use crate::{
+    ballots, config, create_proposal_v3, edit_proposal_v2, end_proposal_v2, env, identity, listing,
+    load_proposal, vote_v2, Choice, CreateProposal, CreatedProposal, Proposal, ProposalSummary,
+    VoteError,
+};
+
+// The error type of API version 1, before errors carried a code and message.
+#[derive(CandidType, Deserialize, Debug)]
+pub enum LegacyVoteError {
+    AlreadyVoted,
+    ProposalIsNotActive,
+    NoSuchProposal,
+    AccessRejected,
+    UpdateError,
+}
+
+impl From<VoteError> for LegacyVoteError {
+    fn from(error: VoteError) -> Self {
+        match error {
+            VoteError::AlreadyVoted(_) => LegacyVoteError::AlreadyVoted,
+            VoteError::ProposalIsNotActive(_)
+            | VoteError::ProposalClosed(_)
+            | VoteError::ProposalIsDraft(_) => LegacyVoteError::ProposalIsNotActive,
+            VoteError::NoSuchProposal(_) => LegacyVoteError::NoSuchProposal,
+            VoteError::UpdateError(_) | VoteError::CorruptedRecord(_) => {
+                LegacyVoteError::UpdateError
+            }
+            _ => LegacyVoteError::AccessRejected,
+        }
+    }
+}
+
+// The proposal record of API version 1, which listed its voters inline.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LegacyProposal {
+    pub description: String,
+    pub approve: u32,
+    pub reject: u32,
+    pub pass: u32,
+    pub is_active: bool,
+    pub voted: Vec<Principal>,
+    pub owner: Principal,
+}
+
+// Version 1 clients check `voted` to see whether their caller has voted. The full list
+// only goes to those who may list voters; everyone else finds at most themselves.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_proposal(key: u64) -> Option<LegacyProposal> {
+    let proposal = load_proposal(key).ok()?;
+    let caller = env::caller();
+    let voted = if ballots::may_list_voters(&proposal, &caller) {
+        ballots::voters(key)
+    } else if ballots::has_voted(key, &identity::canonical(&caller)) {
+        vec![caller]
+    } else {
+        Vec::new()
+    };
+    Some(LegacyProposal {
+        description: proposal.description,
+        approve: proposal.approve,
+        reject: proposal.reject,
+        pass: proposal.pass,
+        is_active: proposal.is_active,
+        voted,
+        owner: proposal.owner,
+    })
+}
+
+// Version 1 paged by offset, so it only ever sees the first `max_scan_per_call`
+// proposals.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_active_proposals(offset: u64, limit: u64) -> Vec<(u64, Proposal)> {
+    let offset = offset as usize;
+    listing::active_page(None, offset.saturating_add(config::page_limit(limit)))
+        .proposals
+        .into_iter()
+        .skip(offset)
+        .collect()
+}
+
+// Capped the same way.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn search_proposals(
+    query: String,
+    filter: ProposalFilter,
+    offset: u64,
+    limit: u64,
+) -> Vec<ProposalSummary> {
+    let offset = offset as usize;
+    let page = offset.saturating_add(config::page_limit(limit));
+    listing::search_page(&query, &filter, None, page)
+        .proposals
+        .into_iter()
+        .skip(offset)
+        .collect()
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_comments(key: u64, offset: u64, limit: u64) -> Vec<Comment> {
+    let offset = offset as usize;
+    comments::comment_page(key, None, offset.saturating_add(config::page_limit(limit)))
+        .comments
+        .into_iter()
+        .skip(offset)
+        .collect()
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_moderation_queue(offset: u64, limit: u64) -> Result<Vec<ReportedComment>, VoteError> {
+    let offset = offset as usize;
+    let page = offset.saturating_add(config::page_limit(limit));
+    Ok(comments::moderation_page(None, page)?
+        .reported
+        .into_iter()
+        .skip(offset)
+        .collect())
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_tags() -> Vec<TagCount> {
+    tags::tag_page(None, config::max_scan())
+        .map(|page| page.tags)
+        .unwrap_or_default()
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_events_for_proposal(key: u64) -> Vec<Event> {
+    events::proposal_event_page(key, None, config::max_scan()).events
+}
+
+// The offset listings below are capped the same way as `list_active_proposals`.
+fn offset_page(offset: u64, limit: u64) -> (usize, usize) {
+    let offset = offset as usize;
+    (offset, offset.saturating_add(config::page_limit(limit)))
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_proposals(offset: u64, limit: u64) -> Vec<(u64, Proposal)> {
+    let (offset, page) = offset_page(offset, limit);
+    listing::proposal_page(None, page)
+        .proposals
+        .into_iter()
+        .skip(offset)
+        .collect()
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_events(offset: u64, limit: u64) -> Vec<Event> {
+    let (offset, page) = offset_page(offset, limit);
+    events::event_page(None, page)
+        .events
+        .into_iter()
+        .skip(offset)
+        .collect()
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_voters(key: u64, offset: u64, limit: u64) -> Result<Vec<Principal>, VoteError> {
+    let (offset, page) = offset_page(offset, limit);
+    Ok(ballots::voter_page(key, None, page)?
+        .voters
+        .into_iter()
+        .skip(offset)
+        .collect())
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_my_votes(offset: u64, limit: u64) -> Vec<MyVote> {
+    let (offset, page) = offset_page(offset, limit);
+    ballots::my_vote_page(None, page)
+        .votes
+        .into_iter()
+        .skip(offset)
+        .collect()
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_inbox(offset: u64, limit: u64) -> Vec<Notification> {
+    let (offset, page) = offset_page(offset, limit);
+    inbox::inbox_page(None, page)
+        .notifications
+        .into_iter()
+        .skip(offset)
+        .collect()
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_followed(offset: u64, limit: u64) -> Vec<Followed> {
+    let (offset, page) = offset_page(offset, limit);
+    follows::followed_page(None, page)
+        .map(|page| page.followed)
+        .unwrap_or_default()
+        .into_iter()
+        .skip(offset)
+        .collect()
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_archived(offset: u64, limit: u64) -> Vec<ArchivedProposal> {
+    let (offset, page) = offset_page(offset, limit);
+    archive::archive_page(None, page)
+        .archived
+        .into_iter()
+        .skip(offset)
+        .collect()
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_constitution_history(offset: u64, limit: u64) -> Vec<ConstitutionVersion> {
+    let (offset, page) = offset_page(offset, limit);
+    constitution::history_page(None, page)
+        .versions
+        .into_iter()
+        .skip(offset)
+        .collect()
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_proposals_by_tag(
+    tag: String,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<(u64, Proposal)>, VoteError> {
+    let (offset, page) = offset_page(offset, limit);
+    Ok(tags::tagged_page(&tag, None, page)?
+        .proposals
+        .into_iter()
+        .skip(offset)
+        .collect())
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_dead_letters(offset: u64, limit: u64) -> Result<Vec<Delivery>, VoteError> {
+    let (offset, page) = offset_page(offset, limit);
+    Ok(webhooks::dead_letter_page(None, page)?
+        .deliveries
+        .into_iter()
+        .skip(offset)
+        .collect())
+}
+
+// Version 1 returned whatever the insert replaced, which is always nothing now that keys
+// cannot be overwritten. Its callers had no way to see a failure, so one is a reject.
+// Since version 3 the key is assigned by the canister and the one passed in is ignored.
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+async fn create_proposal(_key: u64, proposal: CreateProposal) -> Option<Proposal> {
+    if let Err(mut error) = create_proposal_v3(proposal).await {
+        ic_cdk::trap(&error.info_mut().message);
+    }
+    None
+}
+
+// The key is ignored here too; callers find the assigned one in the result.
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+async fn create_proposal_v2(
+    _key: u64,
+    proposal: CreateProposal,
+) -> Result<CreatedProposal, VoteError> {
+    create_proposal_v3(proposal).await
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn edit_proposal(key: u64, proposal: CreateProposal) -> Result<(), LegacyVoteError> {
+    Ok(edit_proposal_v2(key, proposal)?)
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn end_proposal(key: u64) -> Result<(), LegacyVoteError> {
+    Ok(end_proposal_v2(key)?)
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+async fn vote(key: u64, choice: Choice) -> Result<(), LegacyVoteError> {
+    Ok(vote_v2(key, choice).await?)
+}
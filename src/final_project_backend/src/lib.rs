@@ -1,56 +1,588 @@
-use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use candid::{CandidType, Deserialize, Encode, Principal};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{BoundedStorable, DefaultMemoryImpl, StableBTreeMap, Storable};
-use std::{borrow::Cow, cell::RefCell};
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{
+    BoundedStorable, DefaultMemoryImpl, StableBTreeMap, StableCell, Storable,
+};
+use std::{borrow::Cow, cell::RefCell, ops::Bound};
+
+use kinds::{ExecutionPayload, ProposalKind};
+
+// Types named in endpoint signatures of other modules; `export_service!` resolves them
+// from here when generating the interface.
+use api::ApiVersion;
+use archive::{ArchivePage, ArchivedProposal};
+use backup::ExportChunk;
+use ballots::{MyVote, MyVotePage, VoterPage};
+use certification::{EventProof, ProposalCertificate};
+use challenges::Challenge;
+use comments::{Comment, CommentPage, ModerationPage, ReportedComment};
+use config::Config;
+use constitution::{ConstitutionPage, ConstitutionVersion};
+use display::ProposalDisplay;
+use elections::ElectionResults;
+use events::{ChangeFeed, DailySummary, Event, EventPage};
+use follows::{Followed, FollowedPage};
+use history::Revision;
+use http::{
+    CsvChunk, HttpRequest, HttpResponse, StreamingCallbackHttpResponse, StreamingCallbackToken,
+};
+use icrc1::Account;
+use identity::IdentityProof;
+use inbox::{Notification, NotificationPage};
+use legacy::{LegacyProposal, LegacyVoteError};
+use listing::{DateField, ProposalFilter, ProposalPage, SearchPage};
+use metrics::Metrics;
+use options::OptionBallot;
+use parameters::Parameter;
+use reactions::{ReactionCount, ReactionTarget};
+use roles::Role;
+use schema::MigrationReport;
+use serde_bytes::ByteBuf;
+use signaling::{BallotReport, SignedBallot};
+use subdaos::ChildDao;
+use tags::{TagCount, TagPage};
+use veto::PendingExecution;
+use wasm_store::UploadedWasm;
+use webhooks::{Delivery, DeliveryPage, Webhook, WebhookEvent};
+
+// Records stored before a type gained fields are completed from its `Default`, and one
+// that does not decode at all reads as that default. Every stored type keeps its default
+// inert (an anonymous author, a hidden comment, a delivery that is never due), so a bad
+// record shows up as a blank entry instead of trapping every call that walks its map.
+macro_rules! candid_storable {
+    ($type:ty) => {
+        impl ic_stable_structures::Storable for $type {
+            fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+                std::borrow::Cow::Owned(
+                    candid::encode_one(self).expect("derived Candid types always encode"),
+                )
+            }
+
+            fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+                crate::schema::decode_with_defaults(bytes.as_ref()).unwrap_or_default()
+            }
+        }
+    };
+    ($type:ty, $max_size:expr) => {
+        candid_storable!($type);
+
+        impl ic_stable_structures::BoundedStorable for $type {
+            const MAX_SIZE: u32 = $max_size;
+            const IS_FIXED_SIZE: bool = false;
+        }
+    };
+}
+
+mod api;
+mod archive;
+mod backup;
+mod ballots;
+mod certification;
+mod challenges;
+mod comments;
+mod config;
+mod constitution;
+mod display;
+mod ed25519;
+mod elections;
+mod eligibility;
+mod env;
+mod events;
+mod execution;
+mod follows;
+mod grants;
+mod history;
+mod http;
+mod icrc1;
+mod idempotency;
+mod identity;
+mod inbox;
+mod json;
+mod kinds;
+mod legacy;
+mod limits;
+mod listing;
+mod localization;
+mod metrics;
+mod options;
+mod parameters;
+mod reactions;
+mod roles;
+mod schema;
+mod seconding;
+mod secret;
+mod signaling;
+mod slugs;
+mod snapshot;
+mod subdaos;
+mod tags;
+#[cfg(test)]
+mod testing;
+mod tiebreak;
+mod tracks;
+mod treasury;
+mod validation;
+mod veto;
+mod wasm_store;
+mod webhooks;
+mod weights;
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
+type PrincipalKey = Blob<29>;
 
 const MAX_VALUE_SIZE: u32 = 5000;
+const MAX_BATCH_PROPOSALS: usize = 50;
+const MAX_BATCH_BALLOTS: usize = 50;
+const MAX_BATCH_READS: usize = 100;
 
-#[derive(CandidType, Deserialize, Debug)]
+const PROPOSALS_MEMORY_ID: MemoryId = MemoryId::new(0);
+const CONFIG_MEMORY_ID: MemoryId = MemoryId::new(1);
+const EVENTS_MEMORY_ID: MemoryId = MemoryId::new(2);
+const EVENT_SEQ_MEMORY_ID: MemoryId = MemoryId::new(3);
+const EVENT_SUMMARIES_MEMORY_ID: MemoryId = MemoryId::new(4);
+const COMMENTS_MEMORY_ID: MemoryId = MemoryId::new(5);
+const COMMENT_INDEX_MEMORY_ID: MemoryId = MemoryId::new(6);
+const COMMENT_SEQ_MEMORY_ID: MemoryId = MemoryId::new(7);
+const ROLES_MEMORY_ID: MemoryId = MemoryId::new(8);
+const COMMENT_REPORTS_MEMORY_ID: MemoryId = MemoryId::new(9);
+const REACTIONS_MEMORY_ID: MemoryId = MemoryId::new(10);
+const REACTION_COUNTS_MEMORY_ID: MemoryId = MemoryId::new(11);
+const INBOX_MEMORY_ID: MemoryId = MemoryId::new(12);
+const INBOX_SEQ_MEMORY_ID: MemoryId = MemoryId::new(13);
+const EVENT_SEGMENTS_MEMORY_ID: MemoryId = MemoryId::new(14);
+const WASM_CHUNKS_MEMORY_ID: MemoryId = MemoryId::new(15);
+const CANDIDATES_MEMORY_ID: MemoryId = MemoryId::new(16);
+const CONSTITUTION_MEMORY_ID: MemoryId = MemoryId::new(17);
+const CHILD_DAOS_MEMORY_ID: MemoryId = MemoryId::new(18);
+const PARAMETERS_MEMORY_ID: MemoryId = MemoryId::new(19);
+const CHALLENGES_MEMORY_ID: MemoryId = MemoryId::new(20);
+const SLUGS_MEMORY_ID: MemoryId = MemoryId::new(21);
+const FOLLOWS_MEMORY_ID: MemoryId = MemoryId::new(22);
+const FOLLOWERS_MEMORY_ID: MemoryId = MemoryId::new(23);
+const PROPOSAL_SEQ_MEMORY_ID: MemoryId = MemoryId::new(24);
+const BALLOTS_MEMORY_ID: MemoryId = MemoryId::new(25);
+const ELIGIBLE_VOTERS_MEMORY_ID: MemoryId = MemoryId::new(26);
+const VOTE_WEIGHTS_MEMORY_ID: MemoryId = MemoryId::new(27);
+const RANKED_BALLOTS_MEMORY_ID: MemoryId = MemoryId::new(28);
+const COMMITMENTS_MEMORY_ID: MemoryId = MemoryId::new(29);
+const SCHEMA_VERSION_MEMORY_ID: MemoryId = MemoryId::new(30);
+const VOTERS_MEMORY_ID: MemoryId = MemoryId::new(31);
+const PROPOSAL_EVENTS_MEMORY_ID: MemoryId = MemoryId::new(32);
+// 33 held the flat list of proposal leaves that the sparse tree in 49 replaced.
+const ARCHIVE_MEMORY_ID: MemoryId = MemoryId::new(34);
+const RECENT_PROPOSALS_MEMORY_ID: MemoryId = MemoryId::new(35);
+const PROPOSAL_DEPOSITS_MEMORY_ID: MemoryId = MemoryId::new(36);
+const VOTES_BY_VOTER_MEMORY_ID: MemoryId = MemoryId::new(37);
+const TAGS_MEMORY_ID: MemoryId = MemoryId::new(38);
+const TAG_COUNTS_MEMORY_ID: MemoryId = MemoryId::new(39);
+const HISTORY_MEMORY_ID: MemoryId = MemoryId::new(40);
+const ASSIGNED_WEIGHTS_MEMORY_ID: MemoryId = MemoryId::new(41);
+const IDENTITY_LINKS_MEMORY_ID: MemoryId = MemoryId::new(42);
+const LINKED_IDENTITIES_MEMORY_ID: MemoryId = MemoryId::new(43);
+const IDEMPOTENCY_KEYS_MEMORY_ID: MemoryId = MemoryId::new(44);
+const WEBHOOKS_MEMORY_ID: MemoryId = MemoryId::new(45);
+const WEBHOOK_QUEUE_MEMORY_ID: MemoryId = MemoryId::new(46);
+const DEAD_LETTERS_MEMORY_ID: MemoryId = MemoryId::new(47);
+const WEBHOOK_DELIVERY_SEQ_MEMORY_ID: MemoryId = MemoryId::new(48);
+const PROPOSAL_TREE_MEMORY_ID: MemoryId = MemoryId::new(49);
+const PENDING_OUTCOMES_MEMORY_ID: MemoryId = MemoryId::new(50);
+const ACTIVE_PROPOSALS_MEMORY_ID: MemoryId = MemoryId::new(51);
+const METRIC_COUNTERS_MEMORY_ID: MemoryId = MemoryId::new(52);
+const LAST_MIGRATION_MEMORY_ID: MemoryId = MemoryId::new(53);
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Default)]
 enum Choice {
     Approve,
     Reject,
+    #[default]
     Pass,
 }
 
-#[derive(CandidType, Deserialize, Debug)]
-enum VoteError {
-    AlreadyVoted,
-    ProposalIsNotActive,
-    NoSuchProposal,
-    AccessRejected,
-    UpdateError,
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct ErrorInfo {
+    code: u16,
+    message: String,
+    field: Option<String>,
+    context: Option<ErrorContext>,
 }
 
-#[derive(CandidType, Deserialize, Debug)]
+// Details a client would otherwise have to query for after the error.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+enum ErrorContext {
+    Closed { closed_at: u64 },
+    // Not known for secret, ranked and election ballots.
+    Voted { choice: Option<Choice> },
+    Created { key: u64 },
+}
+
+macro_rules! vote_errors {
+    ($($name:ident = $code:literal => $message:literal,)*) => {
+        // Retired errors keep their variant so their code is never reused.
+        #[allow(dead_code)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        enum ErrorCode {
+            $($name,)*
+        }
+
+        #[derive(CandidType, Deserialize, Debug)]
+        enum VoteError {
+            $($name(ErrorInfo),)*
+        }
+
+        impl ErrorCode {
+            fn code(self) -> u16 {
+                match self {
+                    $(ErrorCode::$name => $code,)*
+                }
+            }
+
+            fn message(self) -> &'static str {
+                match self {
+                    $(ErrorCode::$name => $message,)*
+                }
+            }
+        }
+
+        impl From<ErrorCode> for VoteError {
+            fn from(code: ErrorCode) -> Self {
+                let info = ErrorInfo {
+                    code: code.code(),
+                    message: code.message().to_string(),
+                    field: None,
+                    context: None,
+                };
+                match code {
+                    $(ErrorCode::$name => VoteError::$name(info),)*
+                }
+            }
+        }
+
+        impl VoteError {
+            fn info_mut(&mut self) -> &mut ErrorInfo {
+                match self {
+                    $(VoteError::$name(info) => info,)*
+                }
+            }
+        }
+    };
+}
+
+// Codes are part of the public interface: append new errors, never renumber.
+vote_errors! {
+    AlreadyVoted = 1 => "the caller has already voted on this proposal",
+    ProposalIsNotActive = 2 => "the proposal is not open for voting",
+    NoSuchProposal = 3 => "no proposal exists with this key",
+    AccessRejected = 4 => "the caller is not allowed to do this",
+    UpdateError = 5 => "the change could not be stored",
+    NoSuchComment = 6 => "no comment exists with this id",
+    InvalidComment = 7 => "the comment is empty or too long",
+    AlreadyReported = 8 => "the caller has already reported this comment",
+    InvalidReaction = 9 => "the reaction is not a single short emoji",
+    NotExecutable = 10 => "the proposal cannot be executed now",
+    CanisterPaused = 11 => "the canister is paused",
+    InvalidWasm = 12 => "the wasm hash or chunk is malformed",
+    InvalidReason = 13 => "the reason is empty or too long",
+    VetoWindowClosed = 14 => "the veto window has closed",
+    InvalidBallot = 15 => "the ballot is not valid for this proposal",
+    RegistrationClosed = 16 => "candidate registration has closed",
+    AlreadyRegistered = 17 => "the caller is already a candidate",
+    ProposalIsDraft = 18 => "the proposal is still waiting for seconds",
+    NotADraft = 19 => "the proposal is not a draft",
+    AlreadySeconded = 20 => "the caller has already seconded this proposal",
+    NotChallengeable = 21 => "the proposal cannot be challenged",
+    ChallengeWindowClosed = 22 => "the challenge window has closed",
+    AlreadyChallenged = 23 => "the caller has already challenged this proposal",
+    DepositFailed = 24 => "the deposit could not be collected",
+    CorruptedRecord = 25 => "a stored record could not be decoded",
+    KeyAlreadyExists = 26 => "a proposal already exists with this key",
+    InvalidProposal = 27 => "the proposal is not valid",
+    EmptyText = 28 => "a required text field is empty",
+    TextTooLong = 29 => "a text field is longer than allowed",
+    InvalidItemCount = 30 => "too few or too many items were given",
+    InvalidDeadline = 31 => "the deadline is in the past or too far ahead",
+    InvalidLanguage = 32 => "the language code is malformed or repeated",
+    InvalidTag = 33 => "tags are 1 to 32 letters, digits or dashes",
+    NotVoted = 34 => "the caller has no recorded vote on this proposal",
+    AnonymousNotAllowed = 35 => "the anonymous principal cannot call this method",
+    NotEligible = 36 => "the caller is not on this proposal's voter list",
+    LedgerCallFailed = 37 => "the ledger could not be asked for the voting weight",
+    InvalidReveal = 38 => "the revealed vote does not match the commitment",
+    RevealWindowClosed = 39 => "the reveal window is not open",
+    NotRemovable = 40 => "the proposal is still open, executing or inside its challenge window",
+    ValidationFailed = 41 => "the proposal content was rejected",
+    RateLimited = 42 => "the caller has created too many proposals recently",
+    EditLocked = 43 => "the description and title are locked once voting has started",
+    StillOpen = 44 => "the proposal is still open for voting",
+    RandomnessUnavailable = 45 => "the management canister did not return randomness",
+    InvalidIdentityProof = 46 => "the identity proof could not be verified",
+    AlreadyLinked = 47 => "the caller is already linked to another identity",
+    SnapshotFailed = 48 => "the holder snapshot could not be taken",
+    ProposalClosed = 49 => "voting on the proposal has closed",
+    IdempotencyConflict = 50 => "the idempotency key was already used for another request",
+    NoSuchDelivery = 51 => "no dead letter exists with this id",
+    NotPaused = 52 => "the canister must be paused first",
+    InvalidStateChunk = 53 => "the state chunk is malformed or from another format",
+}
+
+impl ErrorCode {
+    fn with_field(self, field: &str) -> VoteError {
+        VoteError::from(self).with_field(field)
+    }
+
+    fn with_message(self, message: impl Into<String>) -> VoteError {
+        VoteError::from(self).with_message(message)
+    }
+
+    fn with_context(self, context: ErrorContext) -> VoteError {
+        let mut error = VoteError::from(self);
+        error.info_mut().context = Some(context);
+        error
+    }
+}
+
+impl VoteError {
+    fn with_field(mut self, field: &str) -> Self {
+        self.info_mut().field = Some(field.to_string());
+        self
+    }
+
+    fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.info_mut().message = message.into();
+        self
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
 struct Proposal {
     description: String,
     approve: u32,
     reject: u32,
     pass: u32,
     is_active: bool,
-    voted: Vec<Principal>,
     owner: Principal,
+    kind: Option<ProposalKind>,
+    execution: Option<execution::ExecutionStatus>,
+    closed_at: Option<u64>,
+    track: Option<tracks::Track>,
+    voting_ends_at: Option<u64>,
+    milestones: Option<Vec<grants::MilestoneStatus>>,
+    electorate: Option<tracks::Electorate>,
+    council_size: Option<u32>,
+    is_draft: Option<bool>,
+    seconders: Option<Vec<Principal>>,
+    disputes: Option<Vec<challenges::Dispute>>,
+    language: Option<String>,
+    translations: Option<Vec<localization::Translation>>,
+    created_at: Option<u64>,
+    updated_at: Option<u64>,
+    slug: Option<String>,
+    deadline_ns: Option<u64>,
+    quorum: Option<u32>,
+    approval_threshold_percent: Option<u8>,
+    result: Option<ProposalResult>,
+    restricted: Option<bool>,
+    weight_ledger: Option<Principal>,
+    weights: Option<weights::WeightedTally>,
+    options: Option<Vec<String>>,
+    option_tallies: Option<Vec<u32>>,
+    runoff: Option<options::Runoff>,
+    reveal_window_ns: Option<u64>,
+    title: Option<String>,
+    tags: Option<Vec<String>>,
+    tie_break: Option<tiebreak::TieBreakDraw>,
+    snapshot: Option<snapshot::SnapshotInfo>,
+    // When voting last opened: on creation, once seconded, or on being reopened.
+    voting_opened_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+enum ProposalResult {
+    Passed,
+    Rejected,
+    QuorumNotReached,
+    #[default]
+    Open,
+    Tied,
+}
+
+impl Proposal {
+    // The owner's threshold can raise the bar for amendments but never lower it.
+    fn threshold_percent(&self) -> u8 {
+        let config = config::get();
+        let base = self
+            .approval_threshold_percent
+            .unwrap_or(config.default_approval_threshold_percent);
+        let threshold = if self
+            .kind
+            .as_ref()
+            .is_some_and(ProposalKind::requires_supermajority)
+        {
+            base.max(config.amendment_threshold_percent)
+        } else {
+            base
+        };
+        tracks::threshold_percent(self, threshold)
+    }
+
+    // Quorum counts voters; the threshold compares weights when there are any.
+    fn decisive_votes(&self) -> (u128, u128) {
+        match self.weights {
+            Some(weights) => (weights.approve, weights.reject),
+            None => (self.approve as u128, self.reject as u128),
+        }
+    }
+
+    fn passed(&self) -> bool {
+        let threshold = self.threshold_percent();
+        let (approve, reject) = self.decisive_votes();
+        let decisive = approve.saturating_add(reject);
+        if self.options.is_some() {
+            let has_winner = self.runoff.as_ref().is_some_and(|r| r.winner.is_some());
+            return !self.is_active && self.turnout() >= tracks::quorum(self) && has_winner;
+        }
+        if let Some(draw) = &self.tie_break {
+            return !self.is_active && draw.winner == tiebreak::TieCandidate::Approve;
+        }
+        !self.is_active
+            && self.turnout() >= tracks::quorum(self)
+            && decisive > 0
+            && approve.saturating_mul(100) > decisive.saturating_mul(threshold as u128)
+    }
+
+    fn turnout(&self) -> u32 {
+        let ranked: u32 = self.option_tallies.iter().flatten().sum();
+        self.approve + self.reject + self.pass + ranked
+    }
+
+    fn outcome(&self) -> ProposalResult {
+        if self.is_active || seconding::is_draft(self) {
+            ProposalResult::Open
+        } else if self.passed() {
+            ProposalResult::Passed
+        } else if self.turnout() < tracks::quorum(self) {
+            ProposalResult::QuorumNotReached
+        } else if tiebreak::is_tied(self) {
+            ProposalResult::Tied
+        } else {
+            ProposalResult::Rejected
+        }
+    }
+
+    // Fixed when the proposal closes, so later config changes do not rewrite history.
+    fn result(&self) -> ProposalResult {
+        self.result.unwrap_or_else(|| self.outcome())
+    }
 }
 
 #[derive(CandidType, Deserialize, Debug)]
+struct ProposalSummary {
+    key: u64,
+    description: String,
+    approve: u32,
+    reject: u32,
+    pass: u32,
+    is_active: bool,
+    owner: Principal,
+    comment_count: u64,
+    reactions: Vec<reactions::ReactionCount>,
+    track: tracks::Track,
+    electorate: tracks::Electorate,
+    voting_ends_at: Option<u64>,
+    is_draft: bool,
+    language: Option<String>,
+    created_at: Option<u64>,
+    updated_at: Option<u64>,
+    closed_at: Option<u64>,
+    slug: Option<String>,
+    title: Option<String>,
+    tags: Vec<String>,
+}
+
+// What was stored, so the caller can render it without another query.
+#[derive(CandidType, Deserialize, Debug)]
+struct CreatedProposal {
+    key: u64,
+    proposal: Proposal,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct VoteTally {
+    approve: u32,
+    reject: u32,
+    pass: u32,
+}
+
+#[derive(CandidType, Deserialize, Debug, Default)]
 struct CreateProposal {
     description: String,
     is_active: bool,
+    kind: Option<ProposalKind>,
+    track: Option<tracks::Track>,
+    electorate: Option<tracks::Electorate>,
+    language: Option<String>,
+    translations: Option<Vec<localization::Translation>>,
+    deadline_ns: Option<u64>,
+    quorum: Option<u32>,
+    approval_threshold_percent: Option<u8>,
+    eligible_voters: Option<Vec<Principal>>,
+    weight_ledger: Option<Principal>,
+    options: Option<Vec<String>>,
+    secret_ballot: Option<bool>,
+    title: Option<String>,
+    tags: Option<Vec<String>>,
+    snapshot: Option<snapshot::SnapshotSource>,
+    // Becomes an `ExecuteCall` kind, so it cannot be combined with one.
+    execution_payload: Option<kinds::ExecutionPayload>,
+    // Repeating a call with the same key and proposal returns what the first one created.
+    idempotency_key: Option<String>,
+}
+
+// A proposal decided elsewhere, carried over with its final tally.
+#[derive(CandidType, Deserialize, Debug)]
+struct ImportedProposal {
+    key: u64,
+    description: String,
+    owner: Principal,
+    approve: u32,
+    reject: u32,
+    pass: u32,
+    voted: Vec<Principal>,
+    created_at: Option<u64>,
+    closed_at: u64,
+}
+
+// Proposals are kept encoded and decoded on access, so an unreadable record comes back
+// as an error for that proposal instead of trapping every call that touches the map.
+struct StoredProposal(Vec<u8>);
+
+impl StoredProposal {
+    fn encode(proposal: &Proposal) -> Result<Self, VoteError> {
+        let candid =
+            Encode!(proposal).map_err(|e| ErrorCode::UpdateError.with_message(e.to_string()))?;
+        let bytes = schema::encode_proposal(candid);
+        if bytes.len() > MAX_VALUE_SIZE as usize {
+            return Err(ErrorCode::UpdateError.with_message("the proposal is too large to store"));
+        }
+        Ok(StoredProposal(bytes))
+    }
+
+    fn decode(&self) -> Result<Proposal, VoteError> {
+        schema::decode_proposal(&self.0).map_err(|e| ErrorCode::CorruptedRecord.with_message(e))
+    }
 }
 
-impl Storable for Proposal {
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+impl Storable for StoredProposal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        StoredProposal(bytes.into_owned())
     }
 }
 
-impl BoundedStorable for Proposal {
+impl BoundedStorable for StoredProposal {
     const MAX_SIZE: u32 = MAX_VALUE_SIZE;
     const IS_FIXED_SIZE: bool = false;
 }
@@ -58,109 +590,1019 @@ impl BoundedStorable for Proposal {
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
 
-    static PROPOSAL_MAP: RefCell<StableBTreeMap<u64, Proposal, Memory>> = RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0)))));
+    static PROPOSAL_MAP: RefCell<StableBTreeMap<u64, StoredProposal, Memory>> = RefCell::new(StableBTreeMap::init(memory(PROPOSALS_MEMORY_ID)));
+
+    static NEXT_PROPOSAL_ID: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(memory(PROPOSAL_SEQ_MEMORY_ID), 0).expect("failed to initialize proposal ids")
+    );
+}
+
+trait ProposalStore {
+    fn load(&self, key: &u64) -> Result<Option<Proposal>, VoteError>;
+    fn store(&mut self, key: u64, proposal: &Proposal)
+        -> Result<Option<StoredProposal>, VoteError>;
+    // Skips records that no longer decode; point reads report them instead.
+    fn decoded(&self) -> impl Iterator<Item = (u64, Proposal)> + '_;
+    // Records that no longer decode come back as None, so a cursor still moves past them.
+    fn decoded_after(
+        &self,
+        after: Option<u64>,
+    ) -> impl Iterator<Item = (u64, Option<Proposal>)> + '_;
+}
+
+impl ProposalStore for StableBTreeMap<u64, StoredProposal, Memory> {
+    fn load(&self, key: &u64) -> Result<Option<Proposal>, VoteError> {
+        self.get(key).map(|stored| stored.decode()).transpose()
+    }
+
+    fn store(
+        &mut self,
+        key: u64,
+        proposal: &Proposal,
+    ) -> Result<Option<StoredProposal>, VoteError> {
+        Ok(self.insert(key, StoredProposal::encode(proposal)?))
+    }
+
+    fn decoded(&self) -> impl Iterator<Item = (u64, Proposal)> + '_ {
+        self.iter()
+            .filter_map(|(key, stored)| Some((key, stored.decode().ok()?)))
+    }
+
+    fn decoded_after(
+        &self,
+        after: Option<u64>,
+    ) -> impl Iterator<Item = (u64, Option<Proposal>)> + '_ {
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+        self.range((start, Bound::Unbounded))
+            .map(|(key, stored)| (key, stored.decode().ok()))
+    }
+}
+
+fn load_proposal(key: u64) -> Result<Proposal, VoteError> {
+    PROPOSAL_MAP
+        .with(|p| p.borrow().load(&key))?
+        .ok_or_else(|| ErrorCode::NoSuchProposal.into())
+}
+
+fn store_proposal(key: u64, proposal: &Proposal) -> Result<(), VoteError> {
+    PROPOSAL_MAP.with(|p| p.borrow_mut().store(key, proposal))?;
+    certification::certify_proposal(key, proposal);
+    metrics::proposal_stored(key, proposal.is_active);
+    Ok(())
+}
+
+// Every write goes through here: nothing is stored unless `change` succeeds, and a
+// failed store is reported rather than mistaken for a missing previous value.
+fn update_proposal<T>(
+    key: u64,
+    change: impl FnOnce(&mut Proposal) -> Result<T, VoteError>,
+) -> Result<T, VoteError> {
+    let mut proposal = load_proposal(key)?;
+    let result = change(&mut proposal)?;
+    store_proposal(key, &proposal)?;
+    Ok(result)
+}
+
+fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+fn principal_key(principal: &Principal) -> PrincipalKey {
+    PrincipalKey::try_from(principal.as_slice()).expect("principal is at most 29 bytes")
+}
+
+fn principal_from_key(key: &PrincipalKey) -> Principal {
+    Principal::from_slice(key.as_slice())
+}
+
+#[ic_cdk::init]
+#[candid::candid_method(init)]
+fn init(args: Option<config::InitArgs>) {
+    schema::mark_current();
+    metrics::mark_counted();
+    if let Err(message) = config::apply_init_args(args) {
+        ic_cdk::trap(&message);
+    }
+    roles::ensure_admin(env::caller());
+    events::start_pruning_timer();
+    certification::start_sealing_timer();
+    idempotency::start_expiry_timer();
+    inbox::start_fanout_timer();
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade(args: Option<config::InitArgs>) {
+    // Everything lives in stable structures, so there is no `pre_upgrade` to save state;
+    // records written by the previous release are brought up to date here instead.
+    schema::migrate();
+    events::index_unindexed();
+    ballots::index_unindexed();
+    metrics::count_once();
+    if let Err(message) = config::apply_init_args(args) {
+        ic_cdk::trap(&message);
+    }
+    roles::ensure_admin(env::caller());
+    // Certified data does not survive an upgrade, so it is recomputed from the stored roots.
+    certification::rehash_proposals();
+    certification::refresh_certified_data();
+    // Timers are dropped on upgrade as well.
+    rearm_timers();
+    events::start_pruning_timer();
+    certification::start_sealing_timer();
+    idempotency::start_expiry_timer();
+    inbox::start_fanout_timer();
+}
+
+// Pausing leaves the work of the timers undone, so they are set again on unpause.
+fn rearm_timers() {
+    execution::reschedule_all();
+    tracks::reschedule_all();
+    webhooks::reschedule();
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_proposal_v2(key: u64) -> Option<Proposal> {
+    load_proposal(key).ok()
+}
+
+// Keys past the limit come back as `None`, like keys with no proposal.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_proposals(keys: Vec<u64>) -> Vec<Option<Proposal>> {
+    keys.into_iter()
+        .enumerate()
+        .map(|(i, key)| {
+            (i < MAX_BATCH_READS)
+                .then(|| load_proposal(key).ok())
+                .flatten()
+        })
+        .collect()
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_proposal_summary(key: u64) -> Option<ProposalSummary> {
+    let proposal = load_proposal(key).ok()?;
+    Some(summarize(key, proposal))
+}
+
+fn summarize(key: u64, proposal: Proposal) -> ProposalSummary {
+    ProposalSummary {
+        key,
+        track: tracks::track_of(&proposal),
+        electorate: tracks::electorate_of(&proposal),
+        voting_ends_at: proposal.voting_ends_at,
+        is_draft: seconding::is_draft(&proposal),
+        language: proposal.language,
+        created_at: proposal.created_at,
+        updated_at: proposal.updated_at,
+        closed_at: proposal.closed_at,
+        slug: proposal.slug,
+        title: proposal.title,
+        tags: proposal.tags.unwrap_or_default(),
+        description: proposal.description,
+        approve: proposal.approve,
+        reject: proposal.reject,
+        pass: proposal.pass,
+        is_active: proposal.is_active,
+        owner: proposal.owner,
+        comment_count: comments::count(key),
+        reactions: reactions::counts(reactions::ReactionTarget::Proposal(key)),
+    }
 }
 
 #[ic_cdk::query]
-fn get_proposal(key: u64) -> Option<Proposal> {
-    PROPOSAL_MAP.with(|p| p.borrow().get(&key))
+#[candid::candid_method(query)]
+fn get_result(key: u64) -> Result<ProposalResult, VoteError> {
+    Ok(load_proposal(key)?.result())
 }
 
 #[ic_cdk::query]
+#[candid::candid_method(query)]
 fn get_proposal_count() -> u64 {
-    PROPOSAL_MAP.with(|p| p.borrow().len() as u64)
+    PROPOSAL_MAP.with(|p| p.borrow().len())
 }
 
 #[ic_cdk::update]
-fn create_proposal(key: u64, proposal: CreateProposal) -> Option<Proposal> {
-    PROPOSAL_MAP.with(|p| {
-        p.borrow_mut().insert(
+#[candid::candid_method(update)]
+async fn create_proposal_v3(proposal: CreateProposal) -> Result<CreatedProposal, VoteError> {
+    config::ensure_not_paused()?;
+    let caller = env::authenticated_caller()?;
+    // The cheap checks run before a deposit is taken; `create` repeats them.
+    validation::description(&proposal.description)?;
+    let Some(idempotency_key) = proposal.idempotency_key.clone() else {
+        return create_with_deposit(caller, proposal).await;
+    };
+    if let Some(key) = idempotency::claim(&caller, &idempotency_key, &proposal)? {
+        return Ok(CreatedProposal {
             key,
-            Proposal {
-                description: proposal.description,
-                approve: 0u32,
-                reject: 0u32,
-                pass: 0u32,
-                is_active: proposal.is_active,
-                voted: Vec::new(),
-                owner: ic_cdk::caller(),
-            },
-        )
-    })
+            proposal: load_proposal(key)?,
+        });
+    }
+    let result = create_with_deposit(caller, proposal).await;
+    match &result {
+        Ok(created) => idempotency::complete(&caller, &idempotency_key, created.key),
+        Err(_) => idempotency::release(&caller, &idempotency_key),
+    }
+    result
 }
 
+async fn create_with_deposit(
+    caller: Principal,
+    proposal: CreateProposal,
+) -> Result<CreatedProposal, VoteError> {
+    limits::check_rate(&caller)?;
+    // Taken first, so a source that cannot be read costs no deposit.
+    let snapshot = match &proposal.snapshot {
+        Some(source) => Some(snapshot::take(source).await?),
+        None => None,
+    };
+    let deposit = treasury::collect_deposit(caller, config::get().proposal_deposit).await?;
+    // Other calls may have used up the allowance while the deposit was taken.
+    match limits::check_rate(&caller).and_then(|_| create(proposal, snapshot)) {
+        Ok(created) => {
+            limits::record(&caller, created.key, deposit);
+            Ok(created)
+        }
+        Err(error) => {
+            treasury::refund_deposit(caller, deposit).await;
+            Err(error)
+        }
+    }
+}
+
+// Each item is validated and created on its own, so one bad entry in a migrated backlog
+// does not hold back the rest.
 #[ic_cdk::update]
-fn edit_proposal(key: u64, proposal: CreateProposal) -> Result<(), VoteError> {
-    PROPOSAL_MAP.with(|p| {
-        if let Some(old_proposal) = p.borrow().get(&key) {
-            if old_proposal.owner == ic_cdk::caller() {
-                match p.borrow_mut().insert(
-                    key,
-                    Proposal {
-                        description: proposal.description,
-                        approve: old_proposal.approve,
-                        reject: old_proposal.reject,
-                        pass: old_proposal.pass,
-                        is_active: proposal.is_active,
-                        voted: old_proposal.voted,
-                        owner: old_proposal.owner,
-                    },
-                ) {
-                    Some(_) => Ok(()),
-                    None => Err(VoteError::UpdateError),
-                }
-            } else {
-                Err(VoteError::AccessRejected)
+#[candid::candid_method(update)]
+fn create_proposals(proposals: Vec<CreateProposal>) -> Vec<Result<CreatedProposal, VoteError>> {
+    let is_admin = roles::has_role(&env::caller(), roles::Role::Admin);
+    let count = proposals.len();
+    proposals
+        .into_iter()
+        .map(|proposal| {
+            if !is_admin {
+                return Err(ErrorCode::AccessRejected.into());
             }
-        } else {
-            Err(VoteError::NoSuchProposal)
-        }
-    })
+            validation::item_count("proposals", count, MAX_BATCH_PROPOSALS)?;
+            create(proposal, None)
+        })
+        .collect()
 }
 
 #[ic_cdk::update]
-fn end_proposal(key: u64) -> Result<(), VoteError> {
-    PROPOSAL_MAP.with(|p| {
-        if let Some(mut old_proposal) = p.borrow_mut().get(&key) {
-            if old_proposal.owner == ic_cdk::caller() {
-                old_proposal.is_active = false;
-                match p.borrow_mut().insert(key, old_proposal) {
-                    Some(_) => Ok(()),
-                    None => Err(VoteError::UpdateError),
-                }
-            } else {
-                Err(VoteError::AccessRejected)
+#[candid::candid_method(update)]
+fn import_proposals(proposals: Vec<ImportedProposal>) -> Vec<Result<u64, VoteError>> {
+    let is_admin = roles::has_role(&env::caller(), roles::Role::Admin);
+    let count = proposals.len();
+    proposals
+        .into_iter()
+        .map(|proposal| {
+            if !is_admin {
+                return Err(ErrorCode::AccessRejected.into());
             }
-        } else {
-            Err(VoteError::NoSuchProposal)
+            validation::item_count("proposals", count, MAX_BATCH_PROPOSALS)?;
+            import(proposal)
+        })
+        .collect()
+}
+
+// Imported proposals arrive closed and without a kind, so history is never executed
+// or reopened for voting here.
+fn import(imported: ImportedProposal) -> Result<u64, VoteError> {
+    config::ensure_not_paused()?;
+    let key = imported.key;
+    if PROPOSAL_MAP.with(|p| p.borrow().contains_key(&key)) || archive::is_archived(key) {
+        return Err(ErrorCode::KeyAlreadyExists.with_field("key"));
+    }
+    validation::description(&imported.description)?;
+    let total = imported.approve as u64 + imported.reject as u64 + imported.pass as u64;
+    if imported.voted.len() as u64 > total {
+        return Err(ErrorCode::InvalidProposal
+            .with_message("more voters than votes in the tally")
+            .with_field("voted"));
+    }
+    if imported
+        .created_at
+        .is_some_and(|at| at > imported.closed_at)
+    {
+        return Err(ErrorCode::InvalidProposal
+            .with_message("an imported proposal cannot close before it was created")
+            .with_field("created_at"));
+    }
+    if imported.closed_at > env::time() {
+        return Err(ErrorCode::InvalidProposal
+            .with_message("an imported proposal must have closed in the past")
+            .with_field("closed_at"));
+    }
+    let slug = slugs::unique(&imported.description);
+    let mut proposal = Proposal {
+        description: imported.description,
+        approve: imported.approve,
+        reject: imported.reject,
+        pass: imported.pass,
+        is_active: false,
+        owner: imported.owner,
+        kind: None,
+        execution: None,
+        closed_at: Some(imported.closed_at),
+        track: None,
+        voting_ends_at: None,
+        milestones: None,
+        electorate: None,
+        council_size: None,
+        is_draft: None,
+        seconders: None,
+        disputes: None,
+        language: None,
+        translations: None,
+        created_at: imported.created_at,
+        updated_at: imported.created_at,
+        slug: Some(slug.clone()),
+        deadline_ns: None,
+        quorum: None,
+        approval_threshold_percent: None,
+        result: None,
+        restricted: None,
+        weight_ledger: None,
+        weights: None,
+        options: None,
+        option_tallies: None,
+        runoff: None,
+        reveal_window_ns: None,
+        title: None,
+        tags: None,
+        tie_break: None,
+        snapshot: None,
+        voting_opened_at: None,
+    };
+    // Fixed now, like the result of a proposal closed here, so it does not follow later
+    // changes to the default quorum or threshold.
+    proposal.result = Some(proposal.outcome());
+    store_proposal(key, &proposal)?;
+    for voter in &imported.voted {
+        ballots::add_voter(key, voter);
+    }
+    slugs::index(&slug, key);
+    events::record(key, events::EventKind::ProposalImported);
+    Ok(key)
+}
+
+// Never below the highest stored key, so proposals from before the counter existed
+// and imported ones keep their ids.
+fn next_key() -> u64 {
+    let after_last = PROPOSAL_MAP.with(|p| {
+        p.borrow()
+            .last_key_value()
+            .map_or(0, |(key, _)| key.saturating_add(1))
+    });
+    NEXT_PROPOSAL_ID.with(|n| {
+        let key = after_last.max(*n.borrow().get());
+        n.borrow_mut()
+            .set(key.saturating_add(1))
+            .expect("failed to advance proposal ids");
+        key
+    })
+}
+
+fn create(
+    mut proposal: CreateProposal,
+    snapshot: Option<snapshot::Snapshot>,
+) -> Result<CreatedProposal, VoteError> {
+    config::ensure_not_paused()?;
+    let caller = env::authenticated_caller()?;
+    if let Some(payload) = proposal.execution_payload.take() {
+        if proposal.kind.is_some() {
+            return Err(ErrorCode::InvalidProposal
+                .with_message("a proposal carries either a kind or an execution payload")
+                .with_field("execution_payload"));
         }
+        proposal.kind = Some(payload.into());
+    }
+    validation::description(&proposal.description)?;
+    if let Some(title) = &proposal.title {
+        validation::title(title)?;
+    }
+    let proposal_tags = proposal
+        .tags
+        .as_deref()
+        .map(tags::normalize_all)
+        .transpose()?;
+    localization::validate(
+        proposal.language.as_deref(),
+        proposal.translations.as_deref().unwrap_or_default(),
+    )?;
+    if let Some(Err(message)) = proposal.kind.as_ref().map(ProposalKind::validate) {
+        return Err(ErrorCode::InvalidProposal
+            .with_message(message)
+            .with_field("kind"));
+    }
+    let track = proposal.track.unwrap_or_default();
+    if !tracks::may_create(&caller, track) {
+        return Err(ErrorCode::AccessRejected
+            .with_message("only council members and admins can open emergency proposals"));
+    }
+    let now = env::time();
+    let is_draft = seconding::starts_as_draft(track);
+    if let Some(deadline) = proposal.deadline_ns {
+        validation::deadline("deadline_ns", deadline)?;
+    }
+    let voting_ends_at = if is_draft {
+        None
+    } else {
+        tracks::ends_at(track, proposal.deadline_ns, now)
+    };
+    if let Some(ends_at) = voting_ends_at {
+        validation::deadline("voting_ends_at", ends_at)?;
+    }
+    if proposal
+        .approval_threshold_percent
+        .is_some_and(|percent| percent > 100)
+    {
+        return Err(ErrorCode::InvalidProposal
+            .with_message("approval_threshold_percent must be at most 100")
+            .with_field("approval_threshold_percent"));
+    }
+    if let Some(voters) = &proposal.eligible_voters {
+        eligibility::validate(voters)?;
+    }
+    // Elections and signed ballots keep their own one-per-voter counts.
+    if proposal.weight_ledger.is_some()
+        && matches!(
+            proposal.kind,
+            Some(ProposalKind::Election(_) | ProposalKind::Signaling)
+        )
+    {
+        return Err(ErrorCode::InvalidProposal
+            .with_message("elections and signaling proposals cannot be token-weighted")
+            .with_field("weight_ledger"));
+    }
+    if let Some(options) = &proposal.options {
+        options::validate(options, proposal.kind.as_ref())?;
+        if proposal.weight_ledger.is_some() {
+            return Err(ErrorCode::InvalidProposal
+                .with_message("multi-option proposals cannot be token-weighted")
+                .with_field("weight_ledger"));
+        }
+    }
+    let secret = proposal.secret_ballot == Some(true);
+    if proposal.snapshot.is_some() != snapshot.is_some() {
+        return Err(ErrorCode::InvalidProposal
+            .with_message("snapshot proposals are created one at a time with create_proposal_v3")
+            .with_field("snapshot"));
+    }
+    if snapshot.is_some()
+        && (proposal.eligible_voters.is_some()
+            || proposal.weight_ledger.is_some()
+            || proposal.options.is_some()
+            || secret
+            || matches!(
+                proposal.kind,
+                Some(ProposalKind::Election(_) | ProposalKind::Signaling)
+            ))
+    {
+        return Err(ErrorCode::InvalidProposal
+            .with_message("snapshot proposals take their voters and weights from the snapshot")
+            .with_field("snapshot"));
+    }
+    if secret {
+        if tracks::ends_at(track, proposal.deadline_ns, now).is_none() {
+            return Err(ErrorCode::InvalidProposal
+                .with_message("secret ballots need a deadline to reveal after")
+                .with_field("deadline_ns"));
+        }
+        if proposal.options.is_some()
+            || proposal.weight_ledger.is_some()
+            || matches!(
+                proposal.kind,
+                Some(ProposalKind::Election(_) | ProposalKind::Signaling)
+            )
+        {
+            return Err(ErrorCode::InvalidProposal
+                .with_message("secret ballots only take approve, reject or pass")
+                .with_field("secret_ballot"));
+        }
+    }
+    let electorate = proposal.electorate.unwrap_or_default();
+    let council_size = (electorate == tracks::Electorate::Council).then(tracks::council_size);
+    if council_size == Some(0) {
+        return Err(
+            ErrorCode::InvalidProposal.with_message("council proposals need a seated council")
+        );
+    }
+    let slug = slugs::unique(proposal.title.as_deref().unwrap_or(&proposal.description));
+    let created = Proposal {
+        description: proposal.description,
+        approve: 0u32,
+        reject: 0u32,
+        pass: 0u32,
+        is_active: proposal.is_active,
+        owner: caller,
+        kind: proposal.kind,
+        execution: None,
+        closed_at: None,
+        track: Some(track),
+        voting_ends_at,
+        milestones: None,
+        electorate: Some(electorate),
+        council_size,
+        is_draft: Some(is_draft),
+        seconders: None,
+        disputes: None,
+        language: proposal.language,
+        translations: proposal.translations,
+        created_at: Some(now),
+        updated_at: Some(now),
+        slug: Some(slug.clone()),
+        deadline_ns: proposal.deadline_ns,
+        quorum: proposal.quorum,
+        approval_threshold_percent: proposal.approval_threshold_percent,
+        result: None,
+        restricted: (proposal.eligible_voters.is_some() || snapshot.is_some()).then_some(true),
+        weight_ledger: proposal.weight_ledger,
+        weights: (proposal.weight_ledger.is_some() || snapshot.is_some())
+            .then(weights::WeightedTally::default),
+        option_tallies: proposal.options.as_ref().map(|o| vec![0; o.len()]),
+        options: proposal.options,
+        runoff: None,
+        reveal_window_ns: secret.then(secret::reveal_window),
+        title: proposal.title,
+        tags: proposal_tags,
+        tie_break: None,
+        snapshot: snapshot.as_ref().map(|s| s.info.clone()),
+        voting_opened_at: (!is_draft).then_some(now),
+    };
+    let key = next_key();
+    store_proposal(key, &created)?;
+    if let Some(voters) = &proposal.eligible_voters {
+        eligibility::add_all(key, voters);
+    }
+    if let Some(snapshot) = &snapshot {
+        snapshot::store(key, snapshot);
+    }
+    slugs::index(&slug, key);
+    tags::index(key, created.tags.as_deref().unwrap_or_default());
+    events::record(key, events::EventKind::ProposalCreated);
+    webhooks::notify(key, webhooks::WebhookEvent::ProposalCreated);
+    follows::notify_tag_followers(key, created.tags.as_deref().unwrap_or_default());
+    tracks::schedule_close(key, &created);
+    Ok(CreatedProposal {
+        key,
+        proposal: created,
     })
 }
 
 #[ic_cdk::update]
-fn vote(key: u64, choice: Choice) -> Result<(), VoteError> {
-    PROPOSAL_MAP.with(|p| {
-        if let Some(mut old_proposal) = p.borrow_mut().get(&key) {
-            if old_proposal.is_active {
-                if !old_proposal.voted.contains(&ic_cdk::caller()) {
-                    match choice {
-                        Choice::Approve => old_proposal.approve += 1,
-                        Choice::Reject => old_proposal.reject += 1,
-                        Choice::Pass => old_proposal.pass += 1,
-                    }
-                    old_proposal.voted.push(ic_cdk::caller());
-                    match p.borrow_mut().insert(key, old_proposal) {
-                        Some(_) => Ok(()),
-                        None => Err(VoteError::UpdateError),
-                    }
-                } else {
-                    Err(VoteError::AlreadyVoted)
-                }
-            } else {
-                Err(VoteError::ProposalIsNotActive)
-            }
-        } else {
-            Err(VoteError::NoSuchProposal)
+#[candid::candid_method(update)]
+fn edit_proposal_v2(key: u64, proposal: CreateProposal) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let caller = env::authenticated_caller()?;
+    let old_proposal = load_proposal(key)?;
+    if old_proposal.owner != caller {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    // Closing has to settle the result, deposit and timers, so it only happens through
+    // `end_proposal_v2` or the deadline, and a closed proposal stays closed.
+    if proposal.is_active != old_proposal.is_active {
+        return Err(ErrorCode::InvalidProposal
+            .with_message("an edit cannot open or close the proposal")
+            .with_field("is_active"));
+    }
+    validation::description(&proposal.description)?;
+    if let Some(title) = &proposal.title {
+        validation::title(title)?;
+    }
+    let proposal_tags = proposal
+        .tags
+        .as_deref()
+        .map(tags::normalize_all)
+        .transpose()?;
+    localization::validate(
+        proposal.language.as_deref(),
+        proposal.translations.as_deref().unwrap_or_default(),
+    )?;
+    // Votes were cast on the text as it stands; admins can still `force_amend` it.
+    let amended = history::is_amendment(
+        &old_proposal,
+        &proposal.description,
+        proposal.title.as_deref(),
+    );
+    if amended && ballots::has_voters(key) {
+        return Err(ErrorCode::EditLocked.into());
+    }
+    let revision = amended.then(|| history::revision_of(&old_proposal, false));
+    let old_tags = old_proposal.tags.clone().unwrap_or_default();
+    let edited = Proposal {
+        description: proposal.description,
+        title: proposal.title,
+        tags: proposal_tags,
+        language: proposal.language,
+        translations: proposal.translations,
+        updated_at: Some(env::time()),
+        // What a proposal does when it passes is fixed at creation.
+        ..old_proposal
+    };
+    store_proposal(key, &edited)?;
+    if let Some(revision) = revision {
+        history::record(key, revision);
+    }
+    tags::unindex(key, &old_tags);
+    tags::index(key, edited.tags.as_deref().unwrap_or_default());
+    events::record(key, events::EventKind::ProposalEdited);
+    follows::notify_followers(key, events::EventKind::ProposalEdited);
+    Ok(())
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn end_proposal_v2(key: u64) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let caller = env::authenticated_caller()?;
+    let mut proposal = load_proposal(key)?;
+    let is_admin = roles::has_role(&caller, roles::Role::Admin);
+    if proposal.owner != caller && !is_admin {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    // Ending early would throw away every commitment not yet revealed.
+    if secret::is_secret(&proposal) && !is_admin {
+        return Err(ErrorCode::AccessRejected
+            .with_message("secret ballots close on their own once the reveal window ends"));
+    }
+    close(key, &mut proposal);
+    store_proposal(key, &proposal)?;
+    after_close(key, &proposal);
+    Ok(())
+}
+
+fn close(key: u64, proposal: &mut Proposal) {
+    proposal.is_active = false;
+    proposal.closed_at = Some(env::time());
+    proposal.runoff = options::tally(key, proposal);
+    proposal.result = Some(proposal.outcome());
+    execution::queue(proposal);
+}
+
+fn after_close(key: u64, ended: &Proposal) {
+    events::record(key, events::EventKind::ProposalEnded);
+    inbox::notify_outcome(key, ended);
+    follows::notify_followers(key, events::EventKind::ProposalEnded);
+    elections::finalize(key, ended);
+    execution::schedule(key, ended);
+    limits::settle_deposit(key, ended);
+    webhooks::notify(key, webhooks::WebhookEvent::ProposalClosed);
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+async fn vote_v2(key: u64, choice: Choice) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let caller = identity::voter()?;
+    let mut proposal = load_proposal(key)?;
+    let weight = match proposal.weight_ledger {
+        Some(ledger) => {
+            // Fail before paying for the ledger call, then check again on what is
+            // stored once it returns.
+            cast(key, &mut proposal.clone(), caller, choice.clone())?;
+            let weight = weights::balance_of(ledger, caller).await?;
+            proposal = load_proposal(key)?;
+            Some(weight)
         }
+        None => proposal
+            .weights
+            .is_some()
+            .then(|| weights::assigned(key, &caller)),
+    };
+    let turnout_before = proposal.turnout();
+    cast(key, &mut proposal, caller, choice.clone())?;
+    if let Some(weight) = weight {
+        weights::add(&mut proposal, &choice, weight);
+    }
+    store_proposal(key, &proposal)?;
+    ballots::record(key, &caller, choice);
+    if let Some(weight) = weight {
+        weights::record(key, &caller, weight);
+    }
+    events::record(key, events::EventKind::VoteCast);
+    webhooks::notify_quorum(key, turnout_before, &proposal);
+    Ok(())
+}
+
+// Ballots are cast one after another, so a later one sees the earlier ones; a repeated
+// key gets `AlreadyVoted`.
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+async fn vote_many(ballots: Vec<(u64, Choice)>) -> Vec<Result<(), VoteError>> {
+    let count = ballots.len();
+    let mut results = Vec::with_capacity(count);
+    for (key, choice) in ballots {
+        let result = match validation::item_count("ballots", count, MAX_BATCH_BALLOTS) {
+            Ok(()) => vote_v2(key, choice).await,
+            Err(error) => Err(error),
+        };
+        results.push(result);
+    }
+    results
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn change_vote(key: u64, new_choice: Choice) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let caller = identity::voter()?;
+    let old_choice = ballots::of(key, &caller).ok_or(ErrorCode::NotVoted)?;
+    // Checked even when nothing changes, so a closed proposal does not report success.
+    check_votable(key, &load_proposal(key)?, &caller)?;
+    if old_choice == new_choice {
+        return Ok(());
+    }
+    update_proposal(key, |proposal| {
+        *tally_of(proposal, &old_choice) -= 1;
+        *tally_of(proposal, &new_choice) += 1;
+        if let Some(weight) = weights::of(key, &caller) {
+            weights::subtract(proposal, &old_choice, weight);
+            weights::add(proposal, &new_choice, weight);
+        }
+        Ok(())
+    })?;
+    ballots::record(key, &caller, new_choice);
+    events::record(key, events::EventKind::VoteChanged);
+    Ok(())
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn retract_vote(key: u64) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let caller = identity::voter()?;
+    let choice = ballots::of(key, &caller).ok_or(ErrorCode::NotVoted)?;
+    update_proposal(key, |proposal| {
+        check_votable(key, proposal, &caller)?;
+        *tally_of(proposal, &choice) -= 1;
+        if let Some(weight) = weights::of(key, &caller) {
+            weights::subtract(proposal, &choice, weight);
+        }
+        Ok(())
+    })?;
+    ballots::remove(key, &caller);
+    weights::remove(key, &caller);
+    events::record(key, events::EventKind::VoteRetracted);
+    Ok(())
+}
+
+// Runs every check `vote` does against a copy, so UIs can show the exact error before
+// asking the user to sign an update call.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn simulate_vote(key: u64, choice: Choice) -> Result<VoteTally, VoteError> {
+    config::ensure_not_paused()?;
+    let caller = identity::voter()?;
+    let mut proposal = load_proposal(key)?;
+    cast(key, &mut proposal, caller, choice)?;
+    Ok(VoteTally {
+        approve: proposal.approve,
+        reject: proposal.reject,
+        pass: proposal.pass,
     })
 }
+
+fn check_votable(key: u64, proposal: &Proposal, caller: &Principal) -> Result<(), VoteError> {
+    if elections::is_election(proposal) {
+        return Err(ErrorCode::InvalidBallot.into());
+    }
+    if !tracks::may_vote(proposal, caller) {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    eligibility::check(key, proposal, caller)?;
+    if seconding::is_draft(proposal) {
+        return Err(ErrorCode::ProposalIsDraft.into());
+    }
+    ensure_open(proposal)
+}
+
+// Proposals that were closed say when; ones that were never opened have no such time.
+fn ensure_open(proposal: &Proposal) -> Result<(), VoteError> {
+    if proposal.is_active && !tracks::voting_closed(proposal) {
+        return Ok(());
+    }
+    let closed_at = if proposal.is_active {
+        proposal.voting_ends_at
+    } else {
+        proposal.closed_at
+    };
+    match closed_at {
+        Some(closed_at) => {
+            Err(ErrorCode::ProposalClosed.with_context(ErrorContext::Closed { closed_at }))
+        }
+        None => Err(ErrorCode::ProposalIsNotActive.into()),
+    }
+}
+
+fn already_voted(choice: Option<Choice>) -> VoteError {
+    ErrorCode::AlreadyVoted.with_context(ErrorContext::Voted { choice })
+}
+
+fn tally_of<'a>(proposal: &'a mut Proposal, choice: &Choice) -> &'a mut u32 {
+    match choice {
+        Choice::Approve => &mut proposal.approve,
+        Choice::Reject => &mut proposal.reject,
+        Choice::Pass => &mut proposal.pass,
+    }
+}
+
+fn cast(
+    key: u64,
+    proposal: &mut Proposal,
+    caller: Principal,
+    choice: Choice,
+) -> Result<(), VoteError> {
+    if proposal.options.is_some() {
+        return Err(ErrorCode::InvalidBallot
+            .with_message("the proposal has its own options; use vote_options")
+            .with_field("choice"));
+    }
+    if secret::is_secret(proposal) {
+        return Err(ErrorCode::InvalidBallot
+            .with_message("the proposal is a secret ballot; use commit_vote")
+            .with_field("choice"));
+    }
+    check_votable(key, proposal, &caller)?;
+    if ballots::has_voted(key, &caller) {
+        return Err(already_voted(ballots::of(key, &caller)));
+    }
+    *tally_of(proposal, &choice) += 1;
+    Ok(())
+}
+
+// What `ic_cdk::export_candid!()` expands to in later ic-cdk releases: candid-extractor
+// reads the interface back out of the built wasm, see `npm run generate:did`.
+// Declared last so every annotated endpoint above has been registered.
+candid::export_service!();
+
+fn candid_interface() -> String {
+    api::annotate(__export_service())
+}
+
+#[no_mangle]
+pub fn get_candid_pointer() -> *mut std::os::raw::c_char {
+    let interface = std::ffi::CString::new(candid_interface()).unwrap();
+    interface.into_raw()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{as_caller, block_on, imported, open_proposal, principal, proposal};
+
+    fn closed_with(approve: u32, reject: u32, pass: u32, quorum: Option<u32>) -> Proposal {
+        as_caller(1);
+        let mut proposal = load_proposal(open_proposal("tally")).unwrap();
+        proposal.is_active = false;
+        proposal.approve = approve;
+        proposal.reject = reject;
+        proposal.pass = pass;
+        proposal.quorum = quorum;
+        proposal
+    }
+
+    #[test]
+    fn a_majority_of_decisive_votes_passes() {
+        assert_eq!(closed_with(3, 2, 0, None).outcome(), ProposalResult::Passed);
+        assert_eq!(
+            closed_with(2, 3, 0, None).outcome(),
+            ProposalResult::Rejected
+        );
+        assert_eq!(
+            closed_with(2, 2, 0, None).outcome(),
+            ProposalResult::Rejected
+        );
+    }
+
+    #[test]
+    fn pass_votes_count_towards_quorum_only() {
+        let proposal = closed_with(1, 0, 4, Some(5));
+        assert_eq!(proposal.turnout(), 5);
+        assert_eq!(proposal.outcome(), ProposalResult::Passed);
+        assert_eq!(
+            closed_with(0, 0, 5, Some(5)).outcome(),
+            ProposalResult::Rejected
+        );
+    }
+
+    #[test]
+    fn quorum_is_checked_before_the_threshold() {
+        let proposal = closed_with(3, 0, 0, Some(4));
+        assert!(!proposal.passed());
+        assert_eq!(proposal.outcome(), ProposalResult::QuorumNotReached);
+    }
+
+    #[test]
+    fn the_owner_threshold_raises_the_bar() {
+        let mut proposal = closed_with(3, 2, 0, None);
+        proposal.approval_threshold_percent = Some(60);
+        assert_eq!(proposal.outcome(), ProposalResult::Rejected);
+        proposal.approve = 4;
+        assert_eq!(proposal.outcome(), ProposalResult::Passed);
+    }
+
+    #[test]
+    fn an_open_proposal_has_no_result_yet() {
+        let mut proposal = closed_with(3, 0, 0, None);
+        proposal.is_active = true;
+        assert!(!proposal.passed());
+        assert_eq!(proposal.result(), ProposalResult::Open);
+    }
+
+    #[test]
+    fn a_voter_votes_once() {
+        as_caller(1);
+        let key = open_proposal("vote once");
+        as_caller(2);
+        block_on(vote_v2(key, Choice::Approve)).unwrap();
+        let again = block_on(vote_v2(key, Choice::Reject));
+        assert!(matches!(again, Err(VoteError::AlreadyVoted(_))));
+        let proposal = load_proposal(key).unwrap();
+        assert_eq!((proposal.approve, proposal.reject), (1, 0));
+        assert_eq!(ballots::of(key, &env::caller()), Some(Choice::Approve));
+    }
+
+    #[test]
+    fn the_anonymous_principal_cannot_vote() {
+        as_caller(1);
+        let key = open_proposal("anonymous");
+        env::set_caller(Principal::anonymous());
+        let vote = block_on(vote_v2(key, Choice::Approve));
+        assert!(matches!(vote, Err(VoteError::AnonymousNotAllowed(_))));
+    }
+
+    #[test]
+    fn an_ended_proposal_takes_no_votes() {
+        as_caller(1);
+        let key = open_proposal("ended");
+        end_proposal_v2(key).unwrap();
+        as_caller(2);
+        let vote = block_on(vote_v2(key, Choice::Approve));
+        assert!(matches!(vote, Err(VoteError::ProposalClosed(_))));
+    }
+
+    #[test]
+    fn only_the_owner_edits_or_ends() {
+        as_caller(1);
+        let key = open_proposal("owned");
+        as_caller(2);
+        let edit = edit_proposal_v2(key, proposal("taken over"));
+        assert!(matches!(edit, Err(VoteError::AccessRejected(_))));
+        assert!(matches!(
+            end_proposal_v2(key),
+            Err(VoteError::AccessRejected(_))
+        ));
+        as_caller(1);
+        edit_proposal_v2(key, proposal("edited")).unwrap();
+        assert_eq!(load_proposal(key).unwrap().description, "edited");
+    }
+
+    #[test]
+    fn a_changed_vote_moves_between_tallies() {
+        as_caller(1);
+        let key = open_proposal("change");
+        as_caller(2);
+        block_on(vote_v2(key, Choice::Approve)).unwrap();
+        change_vote(key, Choice::Reject).unwrap();
+        let proposal = load_proposal(key).unwrap();
+        assert_eq!((proposal.approve, proposal.reject), (0, 1));
+        as_caller(3);
+        assert!(matches!(
+            change_vote(key, Choice::Reject),
+            Err(VoteError::NotVoted(_))
+        ));
+    }
+
+    #[test]
+    fn created_proposals_get_fresh_keys() {
+        as_caller(1);
+        let first = open_proposal("first");
+        let second = open_proposal("second");
+        assert_ne!(first, second);
+        assert_eq!(load_proposal(first).unwrap().description, "first");
+        assert_eq!(load_proposal(second).unwrap().description, "second");
+    }
+
+    #[test]
+    fn an_import_never_replaces_a_proposal() {
+        as_caller(1);
+        let key = open_proposal("here first");
+        let import = import(imported(key));
+        assert!(matches!(import, Err(VoteError::KeyAlreadyExists(_))));
+        assert_eq!(load_proposal(key).unwrap().description, "here first");
+    }
+
+    #[test]
+    fn keys_continue_after_imported_ones() {
+        as_caller(1);
+        assert_eq!(import(imported(10)).unwrap(), 10);
+        let proposal = load_proposal(10).unwrap();
+        assert_eq!(proposal.result, Some(ProposalResult::Passed));
+        assert!(ballots::has_voted(10, &principal(8)));
+        assert_eq!(open_proposal("next"), 11);
+    }
+}
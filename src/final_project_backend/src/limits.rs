@@ -0,0 +1,98 @@
+use candid::Principal;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::roles::{self, Role};
+use crate::{
+    config, env, memory, principal_key, treasury, ErrorCode, Memory, PrincipalKey, Proposal,
+    ProposalResult, VoteError, PROPOSAL_DEPOSITS_MEMORY_ID, RECENT_PROPOSALS_MEMORY_ID,
+};
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+thread_local! {
+    // (owner, proposal) -> created_at, for proposals created in roughly the last day.
+    static RECENT: RefCell<StableBTreeMap<(PrincipalKey, u64), u64, Memory>> = RefCell::new(StableBTreeMap::init(memory(RECENT_PROPOSALS_MEMORY_ID)));
+
+    // Deposits still held for open proposals.
+    static DEPOSITS: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(StableBTreeMap::init(memory(PROPOSAL_DEPOSITS_MEMORY_ID)));
+}
+
+fn recent(owner: &Principal) -> Vec<(u64, u64)> {
+    let owner = principal_key(owner);
+    RECENT.with(|r| {
+        r.borrow()
+            .range((owner, 0)..=(owner, u64::MAX))
+            .map(|((_, key), created_at)| (key, created_at))
+            .collect()
+    })
+}
+
+// A limit of 0 turns the check off.
+pub fn check_rate(owner: &Principal) -> Result<(), VoteError> {
+    let limit = config::get().max_proposals_per_day;
+    if limit == 0 {
+        return Ok(());
+    }
+    let cutoff = env::time().saturating_sub(NANOS_PER_DAY);
+    let mut in_window: Vec<u64> = recent(owner)
+        .into_iter()
+        .map(|(_, created_at)| created_at)
+        .filter(|created_at| *created_at > cutoff)
+        .collect();
+    if in_window.len() < limit as usize {
+        return Ok(());
+    }
+    in_window.sort_unstable();
+    let retry_at = in_window[in_window.len() - limit as usize] + NANOS_PER_DAY;
+    Err(ErrorCode::RateLimited.with_message(format!(
+        "at most {} proposals per day; the next one can be created at {}",
+        limit, retry_at
+    )))
+}
+
+// Also forgets the owner's entries that have left the window.
+pub fn record(owner: &Principal, key: u64, deposit: u64) {
+    let now = env::time();
+    let cutoff = now.saturating_sub(NANOS_PER_DAY);
+    let owner_key = principal_key(owner);
+    RECENT.with(|r| {
+        let mut recent_map = r.borrow_mut();
+        for (old, created_at) in recent(owner) {
+            if created_at <= cutoff {
+                recent_map.remove(&(owner_key, old));
+            }
+        }
+        recent_map.insert((owner_key, key), now);
+    });
+    if deposit > 0 {
+        DEPOSITS.with(|d| d.borrow_mut().insert(key, deposit));
+    }
+}
+
+// Rejected proposals forfeit their deposit to the treasury; every other outcome gets it back.
+pub fn settle_deposit(key: u64, ended: &Proposal) {
+    let Some(deposit) = DEPOSITS.with(|d| d.borrow_mut().remove(&key)) else {
+        return;
+    };
+    if ended.result() == ProposalResult::Rejected {
+        return;
+    }
+    let owner = ended.owner;
+    ic_cdk::spawn(async move {
+        treasury::refund_deposit(owner, deposit).await;
+    });
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn set_proposal_limits(max_proposals_per_day: u32, proposal_deposit: u64) -> Result<(), VoteError> {
+    if !roles::has_role(&env::caller(), Role::Admin) {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    config::update(|config| {
+        config.max_proposals_per_day = max_proposals_per_day;
+        config.proposal_deposit = proposal_deposit;
+    });
+    Ok(())
+}
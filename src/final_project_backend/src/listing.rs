@@ -0,0 +1,191 @@
+use candid::{CandidType, Deserialize, Principal};
+
+use crate::{config, summarize, tags, Proposal, ProposalStore, ProposalSummary, PROPOSAL_MAP};
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug)]
+pub enum DateField {
+    Created,
+    Updated,
+    Closed,
+}
+
+impl DateField {
+    // Proposals from before timestamps were recorded have none and never match.
+    fn of(self, proposal: &Proposal) -> Option<u64> {
+        match self {
+            DateField::Created => proposal.created_at,
+            DateField::Updated => proposal.updated_at,
+            DateField::Closed => proposal.closed_at,
+        }
+    }
+}
+
+// Every set field has to match; an empty filter matches everything.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct ProposalFilter {
+    pub is_active: Option<bool>,
+    pub owner: Option<Principal>,
+    pub created_from: Option<u64>,
+    pub created_to: Option<u64>,
+    pub tag: Option<String>,
+}
+
+impl ProposalFilter {
+    fn matches(&self, proposal: &Proposal) -> bool {
+        let created = proposal.created_at;
+        self.is_active
+            .is_none_or(|active| proposal.is_active == active)
+            && self.owner.is_none_or(|owner| proposal.owner == owner)
+            && self
+                .created_from
+                .is_none_or(|from| created.is_some_and(|at| at >= from))
+            && self
+                .created_to
+                .is_none_or(|to| created.is_some_and(|at| at <= to))
+            && self
+                .tag
+                .as_deref()
+                .is_none_or(|tag| tags::has_tag(proposal, &tag.trim().to_ascii_lowercase()))
+    }
+}
+
+fn contains_text(proposal: &Proposal, needle: &str) -> bool {
+    needle.is_empty()
+        || proposal.description.to_lowercase().contains(needle)
+        || proposal
+            .title
+            .as_ref()
+            .is_some_and(|title| title.to_lowercase().contains(needle))
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_proposals_between(
+    field: DateField,
+    from: u64,
+    to: u64,
+    newest_first: bool,
+    limit: u64,
+) -> Vec<ProposalSummary> {
+    let mut matching: Vec<(u64, u64, Proposal)> = PROPOSAL_MAP.with(|p| {
+        p.borrow()
+            .decoded()
+            .take(config::max_scan())
+            .filter_map(|(key, proposal)| {
+                let at = field.of(&proposal)?;
+                (from..=to).contains(&at).then_some((at, key, proposal))
+            })
+            .collect()
+    });
+    matching.sort_by_key(|(at, key, _)| (*at, *key));
+    if newest_first {
+        matching.reverse();
+    }
+    matching
+        .into_iter()
+        .take(config::page_limit(limit))
+        .map(|(_, key, proposal)| summarize(key, proposal))
+        .collect()
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProposalPage {
+    pub proposals: Vec<(u64, Proposal)>,
+    // Pass back as `after` to continue; None once every proposal has been looked at.
+    pub next: Option<u64>,
+}
+
+pub fn proposal_page(after: Option<u64>, page: usize) -> ProposalPage {
+    let (proposals, next) = PROPOSAL_MAP.with(|p| {
+        config::scan_page(p.borrow().decoded_after(after), page, |key, proposal| {
+            proposal.map(|proposal| (*key, proposal))
+        })
+    });
+    ProposalPage { proposals, next }
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_proposals_v2(after: Option<u64>, limit: u64) -> ProposalPage {
+    proposal_page(after, config::page_limit(limit))
+}
+
+pub fn active_page(after: Option<u64>, page: usize) -> ProposalPage {
+    let (proposals, next) = PROPOSAL_MAP.with(|p| {
+        config::scan_page(p.borrow().decoded_after(after), page, |key, proposal| {
+            proposal
+                .filter(|proposal| proposal.is_active)
+                .map(|proposal| (*key, proposal))
+        })
+    });
+    ProposalPage { proposals, next }
+}
+
+// Each call looks at no more than `max_scan_per_call` proposals, so a page can come back
+// short, or empty, with `next` still set.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_active_proposals_v2(after: Option<u64>, limit: u64) -> ProposalPage {
+    active_page(after, config::page_limit(limit))
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct SearchPage {
+    pub proposals: Vec<ProposalSummary>,
+    pub next: Option<u64>,
+}
+
+pub fn search_page(
+    query: &str,
+    filter: &ProposalFilter,
+    after: Option<u64>,
+    page: usize,
+) -> SearchPage {
+    let needle = query.trim().to_lowercase();
+    let (matching, next) = PROPOSAL_MAP.with(|p| {
+        config::scan_page(p.borrow().decoded_after(after), page, |key, proposal| {
+            proposal
+                .filter(|proposal| filter.matches(proposal) && contains_text(proposal, &needle))
+                .map(|proposal| (*key, proposal))
+        })
+    });
+    SearchPage {
+        proposals: matching
+            .into_iter()
+            .map(|(key, proposal)| summarize(key, proposal))
+            .collect(),
+        next,
+    }
+}
+
+// A case-insensitive substring match on the title and description, paged like
+// `list_active_proposals_v2`.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn search_proposals_v2(
+    query: String,
+    filter: ProposalFilter,
+    after: Option<u64>,
+    limit: u64,
+) -> SearchPage {
+    search_page(&query, &filter, after, config::page_limit(limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{as_caller, open_proposal};
+
+    #[test]
+    fn the_proposal_list_resumes_past_the_scan_cap() {
+        as_caller(1);
+        config::update(|config| config.max_scan_per_call = 2);
+        let keys: Vec<u64> = (0..3).map(|_| open_proposal("listed")).collect();
+        let first = proposal_page(None, 10);
+        assert_eq!(first.proposals.len(), 2);
+        assert_eq!(first.next, Some(keys[1]));
+        let second = proposal_page(first.next, 10);
+        assert_eq!(second.proposals[0].0, keys[2]);
+        assert_eq!(second.next, None);
+    }
+}
@@ -0,0 +1,94 @@
+use candid::{CandidType, Deserialize};
+
+use crate::{
+    load_proposal, summarize, validation, ErrorCode, Proposal, ProposalSummary, VoteError,
+};
+
+const MAX_LANGUAGE_BYTES: usize = 16;
+const MAX_TRANSLATIONS: usize = 8;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Translation {
+    pub language: String,
+    pub description: String,
+}
+
+// Loose BCP 47 shape: "en", "pt-BR", "zh-Hant".
+fn language_code(field: &str, code: &str) -> Result<(), VoteError> {
+    let well_formed = !code.is_empty()
+        && code.len() <= MAX_LANGUAGE_BYTES
+        && code
+            .split('-')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()));
+    if !well_formed {
+        return Err(ErrorCode::InvalidLanguage.with_field(field));
+    }
+    Ok(())
+}
+
+pub fn validate(language: Option<&str>, translations: &[Translation]) -> Result<(), VoteError> {
+    if let Some(language) = language {
+        language_code("language", language)?;
+    }
+    if translations.len() > MAX_TRANSLATIONS {
+        return Err(ErrorCode::InvalidItemCount
+            .with_message(format!(
+                "at most {} translations are allowed",
+                MAX_TRANSLATIONS
+            ))
+            .with_field("translations"));
+    }
+    for (i, translation) in translations.iter().enumerate() {
+        language_code("translations", &translation.language)?;
+        validation::text(
+            "translations",
+            &translation.description,
+            validation::MAX_DESCRIPTION_BYTES,
+        )?;
+        let repeated = language.is_some_and(|l| l.eq_ignore_ascii_case(&translation.language))
+            || translations[..i]
+                .iter()
+                .any(|other| other.language.eq_ignore_ascii_case(&translation.language));
+        if repeated {
+            return Err(ErrorCode::InvalidLanguage
+                .with_message(format!("{} is given more than once", translation.language))
+                .with_field("translations"));
+        }
+    }
+    Ok(())
+}
+
+// Tries the exact tag, then its primary language ("pt-BR" falls back to "pt"), and
+// otherwise keeps the default description.
+pub fn localize(proposal: &mut Proposal, preferred: &str) {
+    let primary = preferred.split('-').next().unwrap_or_default();
+    let translations = proposal.translations.as_deref().unwrap_or_default();
+    let found = translations
+        .iter()
+        .find(|t| t.language.eq_ignore_ascii_case(preferred))
+        .or_else(|| {
+            translations
+                .iter()
+                .find(|t| t.language.eq_ignore_ascii_case(primary))
+        });
+    if let Some(translation) = found {
+        proposal.description = translation.description.clone();
+        proposal.language = Some(translation.language.clone());
+    }
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_localized_proposal(key: u64, language: String) -> Option<Proposal> {
+    let mut proposal = load_proposal(key).ok()?;
+    localize(&mut proposal, &language);
+    Some(proposal)
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_localized_proposal_summary(key: u64, language: String) -> Option<ProposalSummary> {
+    let mut proposal = load_proposal(key).ok()?;
+    localize(&mut proposal, &language);
+    Some(summarize(key, proposal))
+}
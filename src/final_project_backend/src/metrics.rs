@@ -0,0 +1,205 @@
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use std::cell::RefCell;
+
+use crate::{
+    ballots, env, memory, Memory, ProposalStore, ACTIVE_PROPOSALS_MEMORY_ID,
+    METRIC_COUNTERS_MEMORY_ID, PROPOSAL_MAP,
+};
+
+const WASM_PAGE_BYTES: u64 = 64 * 1024;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Metrics {
+    pub total_proposals: u64,
+    pub active_proposals: u64,
+    pub votes_cast: u64,
+    pub unique_voters: u64,
+    pub stable_memory_bytes: u64,
+    pub heap_bytes: u64,
+    pub cycles: u128,
+    pub timestamp: u64,
+}
+
+// Kept up to date on every write, so a scrape does not have to walk the maps.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct Counters {
+    // False on canisters installed before the counters, until `count_once` has run.
+    counted: bool,
+    unique_voters: u64,
+}
+
+candid_storable!(Counters);
+
+thread_local! {
+    // A set rather than a count, so a write does not need to know what it replaced.
+    static ACTIVE: RefCell<StableBTreeMap<u64, (), Memory>> = RefCell::new(StableBTreeMap::init(memory(ACTIVE_PROPOSALS_MEMORY_ID)));
+
+    static COUNTERS: RefCell<StableCell<Counters, Memory>> = RefCell::new(
+        StableCell::init(memory(METRIC_COUNTERS_MEMORY_ID), Counters::default()).expect("failed to initialize metric counters")
+    );
+}
+
+fn update_counters(f: impl FnOnce(&mut Counters)) {
+    COUNTERS.with(|c| {
+        let mut counters = c.borrow().get().clone();
+        f(&mut counters);
+        c.borrow_mut()
+            .set(counters)
+            .expect("failed to write metric counters");
+    });
+}
+
+pub fn proposal_stored(key: u64, is_active: bool) {
+    ACTIVE.with(|a| {
+        if is_active {
+            a.borrow_mut().insert(key, ());
+        } else {
+            a.borrow_mut().remove(&key);
+        }
+    });
+}
+
+pub fn proposal_removed(key: u64) {
+    ACTIVE.with(|a| a.borrow_mut().remove(&key));
+}
+
+pub fn voter_added() {
+    update_counters(|c| c.unique_voters += 1);
+}
+
+pub fn voter_removed() {
+    update_counters(|c| c.unique_voters = c.unique_voters.saturating_sub(1));
+}
+
+// A fresh install starts from zero.
+pub fn mark_counted() {
+    update_counters(|c| c.counted = true);
+}
+
+// Runs in `post_upgrade`, and only walks the maps on the first upgrade to a release that
+// keeps the counters.
+pub fn count_once() {
+    if COUNTERS.with(|c| c.borrow().get().counted) {
+        return;
+    }
+    let active: Vec<u64> = PROPOSAL_MAP.with(|p| {
+        p.borrow()
+            .decoded()
+            .filter(|(_, proposal)| proposal.is_active)
+            .map(|(key, _)| key)
+            .collect()
+    });
+    for key in active {
+        proposal_stored(key, true);
+    }
+    let unique_voters = ballots::unique_voter_count();
+    update_counters(|c| {
+        c.unique_voters = unique_voters;
+        c.counted = true;
+    });
+}
+
+fn heap_bytes() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_BYTES
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        0
+    }
+}
+
+pub fn collect() -> Metrics {
+    Metrics {
+        total_proposals: PROPOSAL_MAP.with(|p| p.borrow().len()),
+        active_proposals: ACTIVE.with(|a| a.borrow().len()),
+        votes_cast: ballots::vote_count(),
+        unique_voters: COUNTERS.with(|c| c.borrow().get().unique_voters),
+        stable_memory_bytes: ic_cdk::api::stable::stable64_size() * WASM_PAGE_BYTES,
+        heap_bytes: heap_bytes(),
+        cycles: ic_cdk::api::canister_balance128(),
+        timestamp: env::time(),
+    }
+}
+
+// Prometheus text exposition format, served at `/metrics`.
+pub fn prometheus(metrics: &Metrics) -> String {
+    let millis = metrics.timestamp / 1_000_000;
+    let gauges: [(&str, &str, u128); 7] = [
+        (
+            "vote_proposals_total",
+            "Proposals currently stored.",
+            metrics.total_proposals.into(),
+        ),
+        (
+            "vote_proposals_active",
+            "Proposals open for voting.",
+            metrics.active_proposals.into(),
+        ),
+        (
+            "vote_votes_cast",
+            "Ballots recorded across all stored proposals.",
+            metrics.votes_cast.into(),
+        ),
+        (
+            "vote_unique_voters",
+            "Principals with at least one recorded ballot.",
+            metrics.unique_voters.into(),
+        ),
+        (
+            "vote_stable_memory_bytes",
+            "Size of stable memory in bytes.",
+            metrics.stable_memory_bytes.into(),
+        ),
+        (
+            "vote_heap_bytes",
+            "Size of the wasm heap in bytes.",
+            metrics.heap_bytes.into(),
+        ),
+        (
+            "vote_cycles_balance",
+            "Cycles held by the canister.",
+            metrics.cycles,
+        ),
+    ];
+    let mut body = String::new();
+    for (name, help, value) in gauges {
+        body.push_str(&format!(
+            "# HELP {0} {1}\n# TYPE {0} gauge\n{0} {2} {3}\n",
+            name, help, value, millis
+        ));
+    }
+    body
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_metrics() -> Metrics {
+    collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::end_proposal_v2;
+    use crate::testing::{as_caller, open_proposal, principal};
+
+    #[test]
+    fn counters_follow_writes() {
+        as_caller(1);
+        let first = open_proposal("first");
+        let second = open_proposal("second");
+        ballots::add_voter(first, &principal(2));
+        ballots::add_voter(second, &principal(2));
+        ballots::add_voter(second, &principal(3));
+        end_proposal_v2(first).unwrap();
+        assert_eq!(ACTIVE.with(|a| a.borrow().len()), 1);
+        assert_eq!(COUNTERS.with(|c| c.borrow().get().unique_voters), 2);
+
+        ballots::remove(second, &principal(3));
+        ballots::remove(second, &principal(2));
+        assert_eq!(COUNTERS.with(|c| c.borrow().get().unique_voters), 1);
+    }
+}
@@ -0,0 +1,236 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::kinds::ProposalKind;
+use crate::{
+    already_voted, ballots, check_votable, config, events, identity, load_proposal, memory,
+    principal_key, store_proposal, validation, webhooks, ErrorCode, Memory, PrincipalKey, Proposal,
+    VoteError, RANKED_BALLOTS_MEMORY_ID,
+};
+
+const MAX_OPTIONS: usize = 16;
+const MAX_OPTION_BYTES: usize = 100;
+
+// Either one option, or options in order of preference; indices into `Proposal::options`.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum OptionBallot {
+    Single(u8),
+    Ranked(Vec<u8>),
+}
+
+// First-preference counts for every round of instant runoff, until one option holds a
+// majority of the ballots still in play. No winner means the last options were tied.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Runoff {
+    pub winner: Option<u8>,
+    pub rounds: Vec<Vec<u32>>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct RankedBallot(Vec<u8>);
+
+candid_storable!(RankedBallot, 64);
+
+thread_local! {
+    static RANKED_BALLOTS: RefCell<StableBTreeMap<(u64, PrincipalKey), RankedBallot, Memory>> = RefCell::new(StableBTreeMap::init(memory(RANKED_BALLOTS_MEMORY_ID)));
+}
+
+// Multi-option proposals have no yes or no to execute, so they only carry motions.
+pub fn validate(options: &[String], kind: Option<&ProposalKind>) -> Result<(), VoteError> {
+    if options.len() < 2 || options.len() > MAX_OPTIONS {
+        return Err(ErrorCode::InvalidItemCount
+            .with_message(format!(
+                "options must have between 2 and {} items",
+                MAX_OPTIONS
+            ))
+            .with_field("options"));
+    }
+    for (index, option) in options.iter().enumerate() {
+        validation::text("options", option, MAX_OPTION_BYTES)?;
+        if options[..index].contains(option) {
+            return Err(ErrorCode::InvalidProposal
+                .with_message("options must be distinct")
+                .with_field("options"));
+        }
+    }
+    if !matches!(kind, None | Some(ProposalKind::Motion)) {
+        return Err(ErrorCode::InvalidProposal
+            .with_message("only motions can have their own options")
+            .with_field("kind"));
+    }
+    Ok(())
+}
+
+fn ranking(ballot: OptionBallot, option_count: usize) -> Result<Vec<u8>, VoteError> {
+    let ranking = match ballot {
+        OptionBallot::Single(option) => vec![option],
+        OptionBallot::Ranked(ranking) => ranking,
+    };
+    let well_formed = !ranking.is_empty()
+        && ranking.iter().enumerate().all(|(index, option)| {
+            (*option as usize) < option_count && !ranking[..index].contains(option)
+        });
+    if !well_formed {
+        return Err(ErrorCode::InvalidBallot
+            .with_message("a ballot ranks distinct options of the proposal")
+            .with_field("ballot"));
+    }
+    Ok(ranking)
+}
+
+fn ballots_of(key: u64) -> Vec<Vec<u8>> {
+    RANKED_BALLOTS.with(|b| {
+        b.borrow()
+            .range((key, PrincipalKey::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|(_, ballot)| ballot.0)
+            .collect()
+    })
+}
+
+pub fn clear(key: u64) {
+    RANKED_BALLOTS.with(|b| {
+        let mut ballots = b.borrow_mut();
+        let voters: Vec<PrincipalKey> = ballots
+            .range((key, PrincipalKey::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|((_, voter), _)| voter)
+            .collect();
+        for voter in voters {
+            ballots.remove(&(key, voter));
+        }
+    });
+}
+
+// Each round drops every option tied for the fewest votes, unless that would drop them all.
+pub fn instant_runoff(option_count: usize, ballots: &[Vec<u8>]) -> Runoff {
+    let mut remaining = vec![true; option_count];
+    let mut rounds = Vec::new();
+    loop {
+        let mut counts = vec![0u32; option_count];
+        for ballot in ballots {
+            if let Some(top) = ballot.iter().find(|option| remaining[**option as usize]) {
+                counts[*top as usize] += 1;
+            }
+        }
+        rounds.push(counts.clone());
+        let in_play: u32 = counts.iter().sum();
+        let standing: Vec<usize> = (0..option_count).filter(|o| remaining[*o]).collect();
+        if let Some(leader) = standing
+            .iter()
+            .find(|o| counts[**o] as u64 * 2 > in_play as u64)
+        {
+            return Runoff {
+                winner: Some(*leader as u8),
+                rounds,
+            };
+        }
+        let fewest = standing.iter().map(|o| counts[*o]).min().unwrap_or(0);
+        let trailing: Vec<usize> = standing
+            .iter()
+            .copied()
+            .filter(|o| counts[*o] == fewest)
+            .collect();
+        if trailing.len() == standing.len() {
+            return Runoff {
+                winner: None,
+                rounds,
+            };
+        }
+        for option in trailing {
+            remaining[option] = false;
+        }
+    }
+}
+
+pub fn tally(key: u64, proposal: &Proposal) -> Option<Runoff> {
+    let options = proposal.options.as_ref()?;
+    Some(instant_runoff(options.len(), &ballots_of(key)))
+}
+
+fn cast(
+    key: u64,
+    proposal: &mut Proposal,
+    caller: Principal,
+    ballot: OptionBallot,
+) -> Result<Vec<u8>, VoteError> {
+    let Some(options) = &proposal.options else {
+        return Err(ErrorCode::InvalidBallot
+            .with_message("the proposal has no options; use vote_v2")
+            .with_field("ballot"));
+    };
+    let ranking = ranking(ballot, options.len())?;
+    check_votable(key, proposal, &caller)?;
+    if ballots::has_voted(key, &caller) {
+        return Err(already_voted(None));
+    }
+    let tallies = proposal
+        .option_tallies
+        .get_or_insert_with(|| vec![0; options.len()]);
+    tallies[ranking[0] as usize] += 1;
+    Ok(ranking)
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn vote_options(key: u64, ballot: OptionBallot) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let caller = identity::voter()?;
+    let mut proposal = load_proposal(key)?;
+    let turnout_before = proposal.turnout();
+    let ranking = cast(key, &mut proposal, caller, ballot)?;
+    store_proposal(key, &proposal)?;
+    ballots::add_voter(key, &caller);
+    RANKED_BALLOTS.with(|b| {
+        b.borrow_mut()
+            .insert((key, principal_key(&caller)), RankedBallot(ranking))
+    });
+    events::record(key, events::EventKind::VoteCast);
+    webhooks::notify_quorum(key, turnout_before, &proposal);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_first_round_majority_wins_outright() {
+        let runoff = instant_runoff(3, &[vec![0], vec![0, 1], vec![1], vec![0, 2]]);
+        assert_eq!(runoff.winner, Some(0));
+        assert_eq!(runoff.rounds, vec![vec![3, 1, 0]]);
+    }
+
+    #[test]
+    fn the_last_option_is_dropped_and_its_ballots_move_on() {
+        let ballots = [vec![0], vec![0], vec![1], vec![1], vec![2, 1]];
+        let runoff = instant_runoff(3, &ballots);
+        assert_eq!(runoff.winner, Some(1));
+        assert_eq!(runoff.rounds, vec![vec![2, 2, 1], vec![2, 3, 0]]);
+    }
+
+    #[test]
+    fn exhausted_ballots_leave_the_count() {
+        let ballots = [vec![0], vec![0], vec![1], vec![2]];
+        let runoff = instant_runoff(3, &ballots);
+        assert_eq!(runoff.winner, Some(0));
+        assert_eq!(runoff.rounds, vec![vec![2, 1, 1], vec![2, 0, 0]]);
+    }
+
+    #[test]
+    fn options_tied_to_the_end_have_no_winner() {
+        let runoff = instant_runoff(2, &[vec![0, 1], vec![1, 0]]);
+        assert_eq!(runoff.winner, None);
+        assert_eq!(runoff.rounds, vec![vec![1, 1]]);
+        assert_eq!(instant_runoff(3, &[]).winner, None);
+    }
+
+    #[test]
+    fn rankings_are_checked_against_the_options() {
+        assert_eq!(ranking(OptionBallot::Single(1), 3).unwrap(), vec![1]);
+        assert!(ranking(OptionBallot::Single(3), 3).is_err());
+        assert!(ranking(OptionBallot::Ranked(vec![0, 0]), 3).is_err());
+        assert!(ranking(OptionBallot::Ranked(vec![]), 3).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use serde_bytes::ByteBuf;
+use std::cell::RefCell;
+
+use crate::kinds::SetParameter;
+use crate::{env, memory, Memory, PARAMETERS_MEMORY_ID};
+
+const MAX_KEY_BYTES: usize = 64;
+const MAX_VALUE_BYTES: usize = 1000;
+
+type ParameterKey = Blob<MAX_KEY_BYTES>;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum ParameterValue {
+    Bool(bool),
+    Int(i64),
+    Nat(u64),
+    Text(String),
+    Principal(Principal),
+    Blob(ByteBuf),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Parameter {
+    pub key: String,
+    pub value: ParameterValue,
+    pub proposal: u64,
+    pub updated_at: u64,
+}
+
+impl Default for Parameter {
+    fn default() -> Self {
+        Parameter {
+            key: String::new(),
+            value: ParameterValue::Bool(false),
+            proposal: 0,
+            updated_at: 0,
+        }
+    }
+}
+
+candid_storable!(Parameter, 1200);
+
+thread_local! {
+    static PARAMETERS: RefCell<StableBTreeMap<ParameterKey, Parameter, Memory>> = RefCell::new(StableBTreeMap::init(memory(PARAMETERS_MEMORY_ID)));
+}
+
+fn parameter_key(key: &str) -> Option<ParameterKey> {
+    ParameterKey::try_from(key.as_bytes()).ok()
+}
+
+pub fn validate(change: &SetParameter) -> Result<(), String> {
+    let valid_key = !change.key.is_empty()
+        && change.key.len() <= MAX_KEY_BYTES
+        && change
+            .key
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"._-".contains(&b));
+    if !valid_key {
+        return Err(format!(
+            "SetParameter key must be 1 to {} bytes of [a-z0-9._-]",
+            MAX_KEY_BYTES
+        ));
+    }
+    let value_bytes = match &change.value {
+        Some(ParameterValue::Text(text)) => text.len(),
+        Some(ParameterValue::Blob(blob)) => blob.len(),
+        _ => 0,
+    };
+    if value_bytes > MAX_VALUE_BYTES {
+        return Err(format!(
+            "SetParameter value exceeds {} bytes",
+            MAX_VALUE_BYTES
+        ));
+    }
+    Ok(())
+}
+
+pub fn apply(proposal: u64, change: SetParameter) -> Result<(), String> {
+    validate(&change)?;
+    let key = parameter_key(&change.key).ok_or_else(|| "invalid parameter key".to_string())?;
+    PARAMETERS.with(|p| {
+        let mut parameters = p.borrow_mut();
+        match change.value {
+            Some(value) => parameters.insert(
+                key,
+                Parameter {
+                    key: change.key,
+                    value,
+                    proposal,
+                    updated_at: env::time(),
+                },
+            ),
+            None => parameters.remove(&key),
+        };
+    });
+    Ok(())
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_parameter(key: String) -> Option<Parameter> {
+    let key = parameter_key(&key)?;
+    PARAMETERS.with(|p| p.borrow().get(&key))
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_parameters() -> Vec<Parameter> {
+    PARAMETERS.with(|p| p.borrow().iter().map(|(_, parameter)| parameter).collect())
+}
@@ -0,0 +1,179 @@
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::{
+    comments, config, env, memory, principal_key, ErrorCode, Memory, PrincipalKey, VoteError,
+    PROPOSAL_MAP, REACTIONS_MEMORY_ID, REACTION_COUNTS_MEMORY_ID,
+};
+
+const MAX_EMOJI_BYTES: usize = 16;
+const MAX_DISTINCT_REACTIONS: usize = 20;
+
+type EmojiKey = Blob<MAX_EMOJI_BYTES>;
+type TargetKey = (u8, u64);
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ReactionTarget {
+    Proposal(u64),
+    Comment(u64),
+}
+
+impl ReactionTarget {
+    fn key(self) -> TargetKey {
+        match self {
+            ReactionTarget::Proposal(id) => (0, id),
+            ReactionTarget::Comment(id) => (1, id),
+        }
+    }
+
+    fn check_exists(self) -> Result<(), VoteError> {
+        match self {
+            ReactionTarget::Proposal(id)
+                if !PROPOSAL_MAP.with(|p| p.borrow().contains_key(&id)) =>
+            {
+                Err(ErrorCode::NoSuchProposal.into())
+            }
+            ReactionTarget::Comment(id) if comments::get(id).is_none_or(|c| c.hidden) => {
+                Err(ErrorCode::NoSuchComment.into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: u64,
+}
+
+thread_local! {
+    static REACTIONS: RefCell<StableBTreeMap<(TargetKey, PrincipalKey), EmojiKey, Memory>> = RefCell::new(StableBTreeMap::init(memory(REACTIONS_MEMORY_ID)));
+
+    static COUNTS: RefCell<StableBTreeMap<(TargetKey, EmojiKey), u64, Memory>> = RefCell::new(StableBTreeMap::init(memory(REACTION_COUNTS_MEMORY_ID)));
+}
+
+fn emoji_key(emoji: &str) -> Result<EmojiKey, VoteError> {
+    if emoji.is_empty() || emoji.chars().any(char::is_whitespace) {
+        return Err(ErrorCode::InvalidReaction.with_field("emoji"));
+    }
+    EmojiKey::try_from(emoji.as_bytes()).map_err(|_| ErrorCode::InvalidReaction.with_field("emoji"))
+}
+
+fn adjust_count(target: TargetKey, emoji: EmojiKey, delta: i64) {
+    COUNTS.with(|c| {
+        let mut counts = c.borrow_mut();
+        let count = counts.get(&(target, emoji)).unwrap_or(0);
+        match count.saturating_add_signed(delta) {
+            0 => counts.remove(&(target, emoji)),
+            count => counts.insert((target, emoji), count),
+        };
+    });
+}
+
+fn distinct_emojis(target: TargetKey) -> Vec<(EmojiKey, u64)> {
+    COUNTS.with(|c| {
+        c.borrow()
+            .range((target, EmojiKey::default())..)
+            .take_while(|((t, _), _)| *t == target)
+            .map(|((_, emoji), count)| (emoji, count))
+            .collect()
+    })
+}
+
+pub fn counts(target: ReactionTarget) -> Vec<ReactionCount> {
+    distinct_emojis(target.key())
+        .into_iter()
+        .map(|(emoji, count)| ReactionCount {
+            emoji: String::from_utf8_lossy(emoji.as_slice()).into_owned(),
+            count,
+        })
+        .collect()
+}
+
+// For a proposal or comment that is going away.
+pub fn clear(target: ReactionTarget) {
+    let target = target.key();
+    REACTIONS.with(|r| {
+        let mut reactions = r.borrow_mut();
+        let reactors: Vec<PrincipalKey> = reactions
+            .range((target, PrincipalKey::default())..)
+            .take_while(|((t, _), _)| *t == target)
+            .map(|((_, reactor), _)| reactor)
+            .collect();
+        for reactor in reactors {
+            reactions.remove(&(target, reactor));
+        }
+    });
+    let emojis = distinct_emojis(target);
+    COUNTS.with(|c| {
+        let mut counts = c.borrow_mut();
+        for (emoji, _) in emojis {
+            counts.remove(&(target, emoji));
+        }
+    });
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn react(target: ReactionTarget, emoji: String) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let caller = env::authenticated_caller()?;
+    target.check_exists()?;
+    let emoji = emoji_key(&emoji)?;
+    let target_key = target.key();
+    let reaction_key = (target_key, principal_key(&caller));
+
+    let distinct = distinct_emojis(target_key);
+    if distinct.len() >= MAX_DISTINCT_REACTIONS && !distinct.iter().any(|(e, _)| *e == emoji) {
+        return Err(ErrorCode::InvalidReaction.into());
+    }
+
+    if let Some(previous) = REACTIONS.with(|r| r.borrow_mut().insert(reaction_key, emoji)) {
+        adjust_count(target_key, previous, -1);
+    }
+    adjust_count(target_key, emoji, 1);
+    Ok(())
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn remove_reaction(target: ReactionTarget) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let caller = env::authenticated_caller()?;
+    let target_key = target.key();
+    let reaction_key = (target_key, principal_key(&caller));
+    if let Some(previous) = REACTIONS.with(|r| r.borrow_mut().remove(&reaction_key)) {
+        adjust_count(target_key, previous, -1);
+    }
+    Ok(())
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_reactions(target: ReactionTarget) -> Vec<ReactionCount> {
+    counts(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{as_caller, open_proposal};
+
+    #[test]
+    fn clearing_a_target_drops_its_reactions_and_counts() {
+        as_caller(1);
+        let key = open_proposal("reacted");
+        let target = ReactionTarget::Proposal(key);
+        react(target, "👍".to_string()).unwrap();
+        as_caller(2);
+        react(target, "🎉".to_string()).unwrap();
+        assert_eq!(counts(target).len(), 2);
+
+        clear(target);
+        assert!(counts(target).is_empty());
+        assert!(REACTIONS.with(|r| r.borrow().is_empty()));
+    }
+}
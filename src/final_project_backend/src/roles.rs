@@ -0,0 +1,123 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::{
+    env, memory, principal_from_key, principal_key, ErrorCode, Memory, PrincipalKey, VoteError,
+    ROLES_MEMORY_ID,
+};
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    Admin,
+    Moderator,
+    Council,
+}
+
+impl Role {
+    fn code(self) -> u8 {
+        match self {
+            Role::Admin => 0,
+            Role::Moderator => 1,
+            Role::Council => 2,
+        }
+    }
+}
+
+thread_local! {
+    static ROLES: RefCell<StableBTreeMap<(u8, PrincipalKey), (), Memory>> = RefCell::new(StableBTreeMap::init(memory(ROLES_MEMORY_ID)));
+}
+
+pub fn has_role(principal: &Principal, role: Role) -> bool {
+    ROLES.with(|r| {
+        r.borrow()
+            .contains_key(&(role.code(), principal_key(principal)))
+    })
+}
+
+pub fn is_moderator(principal: &Principal) -> bool {
+    has_role(principal, Role::Moderator) || has_role(principal, Role::Admin)
+}
+
+pub fn members(role: Role) -> Vec<Principal> {
+    let code = role.code();
+    ROLES.with(|r| {
+        r.borrow()
+            .range((code, PrincipalKey::default())..)
+            .take_while(|((c, _), _)| *c == code)
+            .map(|((_, key), _)| principal_from_key(&key))
+            .collect()
+    })
+}
+
+pub fn grant(principal: Principal, role: Role) {
+    ROLES.with(|r| {
+        r.borrow_mut()
+            .insert((role.code(), principal_key(&principal)), ())
+    });
+}
+
+pub fn revoke(principal: &Principal, role: Role) {
+    ROLES.with(|r| {
+        r.borrow_mut()
+            .remove(&(role.code(), principal_key(principal)))
+    });
+}
+
+// Whoever installs or upgrades a canister with no admins becomes its first admin.
+pub fn ensure_admin(installer: Principal) {
+    if members(Role::Admin).is_empty() {
+        grant(installer, Role::Admin);
+    }
+}
+
+pub(crate) fn require_admin() -> Result<(), VoteError> {
+    if has_role(&env::caller(), Role::Admin) {
+        Ok(())
+    } else {
+        Err(ErrorCode::AccessRejected.into())
+    }
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn grant_role(principal: Principal, role: Role) -> Result<(), VoteError> {
+    require_admin()?;
+    grant(principal, role);
+    Ok(())
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn revoke_role(principal: Principal, role: Role) -> Result<(), VoteError> {
+    require_admin()?;
+    if role == Role::Admin && members(Role::Admin) == [principal] {
+        return Err(ErrorCode::AccessRejected.with_message("the last admin cannot be removed"));
+    }
+    revoke(&principal, role);
+    Ok(())
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn add_admin(principal: Principal) -> Result<(), VoteError> {
+    grant_role(principal, Role::Admin)
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn remove_admin(principal: Principal) -> Result<(), VoteError> {
+    revoke_role(principal, Role::Admin)
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_admins() -> Vec<Principal> {
+    members(Role::Admin)
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_role_members(role: Role) -> Vec<Principal> {
+    members(role)
+}
@@ -0,0 +1,323 @@
+use candid::parser::value::{IDLField, IDLValue};
+use candid::{CandidType, Deserialize, IDLArgs, Principal, TypeEnv};
+use ic_stable_structures::StableCell;
+use std::cell::RefCell;
+
+use crate::{
+    ballots, env, memory, Memory, Proposal, ProposalStore, LAST_MIGRATION_MEMORY_ID, PROPOSAL_MAP,
+    SCHEMA_VERSION_MEMORY_ID,
+};
+
+// Version 1 stored bare Candid proposals; version 2 prefixes each record with its schema
+// version; version 3 moves `voted` out into the voters map. Records keep decoding from
+// every earlier version, and `migrate` rewrites them in the current one after an upgrade.
+const PROPOSAL_SCHEMA_VERSION: u8 = 3;
+const FIRST_PREFIXED_VERSION: u8 = 2;
+const CANDID_MAGIC: &[u8] = b"DIDL";
+
+// What the last migration did, so it can be checked after an upgrade.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct MigrationReport {
+    pub from_version: u8,
+    pub to_version: u8,
+    pub migrated: u64,
+    pub unreadable: u64,
+    pub migrated_at: u64,
+}
+
+candid_storable!(MigrationReport);
+
+thread_local! {
+    static STORED_VERSION: RefCell<StableCell<u8, Memory>> = RefCell::new(
+        StableCell::init(memory(SCHEMA_VERSION_MEMORY_ID), 1).expect("failed to initialize schema version")
+    );
+
+    static LAST_MIGRATION: RefCell<StableCell<MigrationReport, Memory>> = RefCell::new(
+        StableCell::init(memory(LAST_MIGRATION_MEMORY_ID), MigrationReport::default()).expect("failed to initialize migration report")
+    );
+}
+
+pub fn encode_proposal(candid: Vec<u8>) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(candid.len() + 1);
+    bytes.push(PROPOSAL_SCHEMA_VERSION);
+    bytes.extend(candid);
+    bytes
+}
+
+// The Candid part of a record of any version.
+fn candid_of(bytes: &[u8]) -> Result<&[u8], String> {
+    match bytes.first() {
+        Some(version) if (FIRST_PREFIXED_VERSION..=PROPOSAL_SCHEMA_VERSION).contains(version) => {
+            Ok(&bytes[1..])
+        }
+        _ if bytes.starts_with(CANDID_MAGIC) => Ok(bytes),
+        Some(version) => Err(format!("unknown proposal schema version {}", version)),
+        None => Err("empty proposal record".to_string()),
+    }
+}
+
+// Every field added to `Proposal` since version 1 is optional and removed ones are
+// skipped, so all versions decode into the current struct. Data that moved elsewhere is
+// carried over by `migrate`.
+pub fn decode_proposal(bytes: &[u8]) -> Result<Proposal, String> {
+    candid::decode_one(candid_of(bytes)?).map_err(|e| e.to_string())
+}
+
+// Reads only the field that version 3 moved out of `Proposal`.
+#[derive(CandidType, Deserialize)]
+struct VotedBeforeV3 {
+    voted: Option<Vec<Principal>>,
+}
+
+fn move_voters(key: u64, bytes: &[u8]) {
+    let voted = candid_of(bytes)
+        .ok()
+        .and_then(|candid| candid::decode_one::<VotedBeforeV3>(candid).ok())
+        .and_then(|old| old.voted)
+        .unwrap_or_default();
+    for voter in voted {
+        ballots::add_voter(key, &voter);
+    }
+}
+
+fn is_current(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&PROPOSAL_SCHEMA_VERSION)
+}
+
+// Decodes a record stored before fields were added to `T`, taking the missing ones from
+// `T::default()`. Candid itself only fills in missing `opt` fields.
+pub fn decode_with_defaults<T>(bytes: &[u8]) -> Result<T, String>
+where
+    T: CandidType + for<'de> Deserialize<'de> + Default,
+{
+    if let Ok(value) = candid::decode_one(bytes) {
+        return Ok(value);
+    }
+    let env = TypeEnv::new();
+    let stored = IDLArgs::from_bytes(bytes).map_err(|e| e.to_string())?;
+    let defaults = candid::encode_one(T::default()).map_err(|e| e.to_string())?;
+    let defaults =
+        IDLArgs::from_bytes_with_types(&defaults, &env, &[T::ty()]).map_err(|e| e.to_string())?;
+    let (Some(stored), Some(defaults)) = (stored.args.first(), defaults.args.first()) else {
+        return Err("no value to decode".to_string());
+    };
+    let merged = IDLArgs::new(&[merge(stored, defaults)])
+        .to_bytes_with_types(&env, &[T::ty()])
+        .map_err(|e| e.to_string())?;
+    candid::decode_one(&merged).map_err(|e| e.to_string())
+}
+
+fn merge(stored: &IDLValue, defaults: &IDLValue) -> IDLValue {
+    let (IDLValue::Record(stored), IDLValue::Record(defaults)) = (stored, defaults) else {
+        return stored.clone();
+    };
+    let fields = defaults
+        .iter()
+        .map(|default| {
+            let val = match stored.iter().find(|f| f.id.get_id() == default.id.get_id()) {
+                Some(field) => merge(&field.val, &default.val),
+                None => default.val.clone(),
+            };
+            IDLField {
+                id: default.id.clone(),
+                val,
+            }
+        })
+        .collect();
+    IDLValue::Record(fields)
+}
+
+// Runs in `post_upgrade`. Unreadable records are left alone; point reads keep reporting
+// them as `CorruptedRecord`.
+pub fn migrate() {
+    let stored_version = STORED_VERSION.with(|v| *v.borrow().get());
+    if stored_version >= PROPOSAL_SCHEMA_VERSION {
+        return;
+    }
+    let (mut migrated, mut unreadable) = (0u64, 0u64);
+    PROPOSAL_MAP.with(|p| {
+        let mut proposals = p.borrow_mut();
+        let outdated: Vec<(u64, Vec<u8>)> = proposals
+            .iter()
+            .filter(|(_, stored)| !is_current(&stored.0))
+            .map(|(key, stored)| (key, stored.0))
+            .collect();
+        for (key, bytes) in outdated {
+            if stored_version < 3 {
+                move_voters(key, &bytes);
+            }
+            let Some(Ok(proposal)) = proposals.load(&key).transpose() else {
+                unreadable += 1;
+                continue;
+            };
+            match proposals.store(key, &proposal) {
+                Ok(_) => migrated += 1,
+                Err(_) => unreadable += 1,
+            }
+        }
+    });
+    let report = MigrationReport {
+        from_version: stored_version,
+        to_version: PROPOSAL_SCHEMA_VERSION,
+        migrated,
+        unreadable,
+        migrated_at: env::time(),
+    };
+    LAST_MIGRATION.with(|m| {
+        m.borrow_mut()
+            .set(report)
+            .expect("failed to record migration report")
+    });
+    STORED_VERSION.with(|v| {
+        v.borrow_mut()
+            .set(PROPOSAL_SCHEMA_VERSION)
+            .expect("failed to record schema version")
+    });
+}
+
+// A fresh install has nothing to migrate.
+pub fn mark_current() {
+    STORED_VERSION.with(|v| {
+        v.borrow_mut()
+            .set(PROPOSAL_SCHEMA_VERSION)
+            .expect("failed to record schema version")
+    });
+}
+
+// None until an upgrade has had records to migrate.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_last_migration() -> Option<MigrationReport> {
+    let report = LAST_MIGRATION.with(|m| m.borrow().get().clone());
+    (report.to_version != 0).then_some(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{as_caller, open_proposal, principal};
+    use crate::StoredProposal;
+
+    // The proposal as version 1 stored it.
+    #[derive(CandidType)]
+    struct ProposalV1 {
+        description: String,
+        approve: u32,
+        reject: u32,
+        pass: u32,
+        is_active: bool,
+        voted: Vec<Principal>,
+        owner: Principal,
+    }
+
+    fn v1_bytes(voted: Vec<Principal>) -> Vec<u8> {
+        candid::encode_one(ProposalV1 {
+            description: "from version 1".to_string(),
+            approve: 2,
+            reject: 0,
+            pass: 0,
+            is_active: false,
+            voted,
+            owner: principal(1),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn version_1_records_still_decode() {
+        let proposal = decode_proposal(&v1_bytes(vec![principal(2)])).unwrap();
+        assert_eq!(proposal.description, "from version 1");
+        assert_eq!(proposal.approve, 2);
+        assert_eq!(proposal.owner, principal(1));
+        assert!(proposal.created_at.is_none());
+    }
+
+    #[test]
+    fn version_2_records_are_prefixed() {
+        let mut bytes = vec![FIRST_PREFIXED_VERSION];
+        bytes.extend(v1_bytes(Vec::new()));
+        assert_eq!(decode_proposal(&bytes).unwrap().approve, 2);
+        assert!(decode_proposal(&[PROPOSAL_SCHEMA_VERSION + 1, 0]).is_err());
+        assert!(decode_proposal(&[]).is_err());
+    }
+
+    #[test]
+    fn current_records_round_trip() {
+        as_caller(1);
+        let key = open_proposal("current");
+        let stored = PROPOSAL_MAP.with(|p| p.borrow().get(&key)).unwrap();
+        assert!(is_current(&stored.0));
+        assert_eq!(decode_proposal(&stored.0).unwrap().description, "current");
+    }
+
+    #[test]
+    fn migration_counts_are_reported() {
+        as_caller(1);
+        let bytes = v1_bytes(Vec::new());
+        PROPOSAL_MAP.with(|p| {
+            let mut proposals = p.borrow_mut();
+            proposals.insert(7, StoredProposal(bytes));
+            proposals.insert(8, StoredProposal(vec![0]));
+        });
+        migrate();
+        let report = get_last_migration().unwrap();
+        assert_eq!((report.from_version, report.to_version), (1, 3));
+        assert_eq!((report.migrated, report.unreadable), (1, 1));
+    }
+
+    #[test]
+    fn voters_move_out_of_old_records() {
+        as_caller(1);
+        let voters = vec![principal(2), principal(3)];
+        let bytes = v1_bytes(voters.clone());
+        PROPOSAL_MAP.with(|p| p.borrow_mut().insert(7, StoredProposal(bytes.clone())));
+        move_voters(7, &bytes);
+        assert_eq!(ballots::voters(7), voters);
+
+        let proposal = PROPOSAL_MAP.with(|p| p.borrow().load(&7)).unwrap().unwrap();
+        PROPOSAL_MAP
+            .with(|p| p.borrow_mut().store(7, &proposal))
+            .unwrap();
+        let stored = PROPOSAL_MAP.with(|p| p.borrow().get(&7)).unwrap();
+        assert!(is_current(&stored.0));
+    }
+
+    #[derive(CandidType, Deserialize)]
+    struct Before {
+        name: String,
+    }
+
+    #[derive(CandidType, Deserialize, Debug, PartialEq)]
+    struct After {
+        name: String,
+        count: u32,
+        enabled: bool,
+    }
+
+    impl Default for After {
+        fn default() -> Self {
+            After {
+                name: String::new(),
+                count: 3,
+                enabled: true,
+            }
+        }
+    }
+
+    #[test]
+    fn missing_fields_take_their_defaults() {
+        let bytes = candid::encode_one(Before {
+            name: "old".to_string(),
+        })
+        .unwrap();
+        assert_eq!(
+            decode_with_defaults::<After>(&bytes).unwrap(),
+            After {
+                name: "old".to_string(),
+                count: 3,
+                enabled: true,
+            }
+        );
+        assert!(decode_with_defaults::<After>(b"not candid").is_err());
+    }
+}
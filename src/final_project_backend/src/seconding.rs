@@ -0,0 +1,46 @@
+use crate::{config, env, identity, tracks, update_proposal, ErrorCode, Proposal, VoteError};
+
+pub fn is_draft(proposal: &Proposal) -> bool {
+    proposal.is_draft.unwrap_or(false)
+}
+
+// Emergencies are already limited to the council and cannot wait for sponsors.
+pub fn starts_as_draft(track: tracks::Track) -> bool {
+    track == tracks::Track::Standard && config::get().seconds_required > 0
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn second_proposal(key: u64) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let caller = identity::voter()?;
+    let opened = update_proposal(key, |proposal| {
+        if !is_draft(proposal) {
+            return Err(ErrorCode::NotADraft.into());
+        }
+        if identity::canonical(&proposal.owner) == caller {
+            return Err(ErrorCode::AccessRejected.into());
+        }
+        let seconders = proposal.seconders.get_or_insert_with(Vec::new);
+        if seconders.contains(&caller) {
+            return Err(ErrorCode::AlreadySeconded.into());
+        }
+        seconders.push(caller);
+        let opened = seconders.len() as u32 >= config::get().seconds_required;
+        if opened {
+            proposal.is_draft = Some(false);
+            proposal.is_active = true;
+            proposal.voting_ends_at = tracks::ends_at(
+                tracks::track_of(proposal),
+                proposal.deadline_ns,
+                env::time(),
+            );
+            proposal.voting_opened_at = Some(env::time());
+        }
+        Ok(opened.then(|| proposal.clone()))
+    })?;
+    if let Some(proposal) = opened {
+        tracks::schedule_close(key, &proposal);
+    }
+    Ok(())
+}
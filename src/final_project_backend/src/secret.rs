@@ -0,0 +1,130 @@
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::StableBTreeMap;
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+use crate::{
+    already_voted, ballots, check_votable, config, env, events, identity, load_proposal, memory,
+    principal_key, tally_of, tracks, update_proposal, Choice, ErrorCode, Memory, PrincipalKey,
+    Proposal, VoteError, COMMITMENTS_MEMORY_ID,
+};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct Commitment {
+    hash: ByteBuf,
+    revealed: bool,
+}
+
+candid_storable!(Commitment, 64);
+
+thread_local! {
+    static COMMITMENTS: RefCell<StableBTreeMap<(u64, PrincipalKey), Commitment, Memory>> = RefCell::new(StableBTreeMap::init(memory(COMMITMENTS_MEMORY_ID)));
+}
+
+pub fn reveal_window() -> u64 {
+    config::get()
+        .reveal_window_secs
+        .saturating_mul(NANOS_PER_SEC)
+}
+
+// `sha256(choice || salt)` with the choice as one byte: 0 approve, 1 reject, 2 pass.
+fn commitment(choice: &Choice, salt: &[u8]) -> Vec<u8> {
+    let tag = match choice {
+        Choice::Approve => 0u8,
+        Choice::Reject => 1,
+        Choice::Pass => 2,
+    };
+    let mut hasher = Sha256::new();
+    hasher.update([tag]);
+    hasher.update(salt);
+    hasher.finalize().to_vec()
+}
+
+pub fn is_secret(proposal: &Proposal) -> bool {
+    proposal.reveal_window_ns.is_some()
+}
+
+// A draft has no voting period yet, so its reveal window has not started either.
+fn reveal_open(proposal: &Proposal) -> bool {
+    let now = env::time();
+    match (proposal.voting_ends_at, tracks::closes_at(proposal)) {
+        (Some(ends_at), Some(closes_at)) => now >= ends_at && now < closes_at,
+        _ => false,
+    }
+}
+
+pub fn clear(key: u64) {
+    COMMITMENTS.with(|c| {
+        let mut commitments = c.borrow_mut();
+        let voters: Vec<PrincipalKey> = commitments
+            .range((key, PrincipalKey::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|((_, voter), _)| voter)
+            .collect();
+        for voter in voters {
+            commitments.remove(&(key, voter));
+        }
+    });
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn commit_vote(key: u64, hash: ByteBuf) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let caller = identity::voter()?;
+    let proposal = load_proposal(key)?;
+    if !is_secret(&proposal) {
+        return Err(ErrorCode::InvalidBallot
+            .with_message("the proposal is not a secret ballot; use vote_v2")
+            .with_field("hash"));
+    }
+    if hash.len() != 32 {
+        return Err(ErrorCode::InvalidBallot
+            .with_message("the commitment must be a 32-byte SHA-256 hash")
+            .with_field("hash"));
+    }
+    check_votable(key, &proposal, &caller)?;
+    let voter = (key, principal_key(&caller));
+    if COMMITMENTS.with(|c| c.borrow().contains_key(&voter)) {
+        return Err(already_voted(None));
+    }
+    let commitment = Commitment {
+        hash,
+        revealed: false,
+    };
+    COMMITMENTS.with(|c| c.borrow_mut().insert(voter, commitment));
+    Ok(())
+}
+
+// Only revealed votes are counted, and only once voting has ended.
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn reveal_vote(key: u64, choice: Choice, salt: ByteBuf) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let caller = identity::voter()?;
+    let voter = (key, principal_key(&caller));
+    let mut committed = COMMITMENTS
+        .with(|c| c.borrow().get(&voter))
+        .ok_or(ErrorCode::NotVoted)?;
+    if committed.revealed {
+        return Err(already_voted(None));
+    }
+    if committed.hash.as_slice() != commitment(&choice, &salt) {
+        return Err(ErrorCode::InvalidReveal.into());
+    }
+    update_proposal(key, |proposal| {
+        if !proposal.is_active || !reveal_open(proposal) {
+            return Err(ErrorCode::RevealWindowClosed.into());
+        }
+        *tally_of(proposal, &choice) += 1;
+        Ok(())
+    })?;
+    committed.revealed = true;
+    COMMITMENTS.with(|c| c.borrow_mut().insert(voter, committed));
+    ballots::record(key, &caller, choice);
+    events::record(key, events::EventKind::VoteCast);
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde_bytes::ByteBuf;
+
+use crate::kinds::ProposalKind;
+use crate::{
+    config, ed25519, eligibility, ensure_open, events, identity, load_proposal, seconding, tracks,
+    update_proposal, validation, webhooks, Choice, ErrorCode, VoteError,
+};
+
+const MAX_BALLOTS_PER_CALL: usize = 100;
+const ED25519_DER_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SignedBallot {
+    pub public_key: ByteBuf,
+    pub choice: Choice,
+    pub signature: ByteBuf,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct BallotReport {
+    pub accepted: u32,
+    pub duplicates: u32,
+    pub rejected: Vec<u32>,
+}
+
+// Ballots are bound to this canister and proposal so a signature collected for one
+// campaign cannot be replayed into another.
+fn payload(key: u64, choice: &Choice) -> Vec<u8> {
+    let choice = match choice {
+        Choice::Approve => "approve",
+        Choice::Reject => "reject",
+        Choice::Pass => "pass",
+    };
+    format!("icp-vote-ballot:{}:{}:{}", ic_cdk::id(), key, choice).into_bytes()
+}
+
+// The voter is the self-authenticating principal of the signing key, the same
+// principal the key would have when calling the canister directly.
+fn voter_of(ballot: &SignedBallot, key: u64) -> Option<Principal> {
+    let public_key: [u8; 32] = ballot.public_key.as_slice().try_into().ok()?;
+    let signature: [u8; 64] = ballot.signature.as_slice().try_into().ok()?;
+    if !ed25519::verify(&public_key, &payload(key, &ballot.choice), &signature) {
+        return None;
+    }
+    Some(principal_of(&public_key))
+}
+
+pub fn principal_of(public_key: &[u8; 32]) -> Principal {
+    let der = [ED25519_DER_PREFIX.as_slice(), public_key.as_slice()].concat();
+    Principal::self_authenticating(der)
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_ballot_payload(key: u64, choice: Choice) -> ByteBuf {
+    ByteBuf::from(payload(key, &choice))
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn submit_signed_ballots(key: u64, ballots: Vec<SignedBallot>) -> Result<BallotReport, VoteError> {
+    config::ensure_not_paused()?;
+    validation::item_count("ballots", ballots.len(), MAX_BALLOTS_PER_CALL)?;
+    let (report, accepted, turnout_before) = update_proposal(key, |proposal| {
+        if !matches!(proposal.kind, Some(ProposalKind::Signaling)) {
+            return Err(ErrorCode::InvalidBallot.into());
+        }
+        if seconding::is_draft(proposal) {
+            return Err(ErrorCode::ProposalIsDraft.into());
+        }
+        ensure_open(proposal)?;
+        let turnout_before = proposal.turnout();
+        let mut report = BallotReport::default();
+        let mut accepted = Vec::new();
+        for (index, ballot) in ballots.iter().enumerate() {
+            let voter = voter_of(ballot, key).map(|v| identity::canonical(&v));
+            let Some(voter) = voter.filter(|v| {
+                tracks::may_vote(proposal, v) && eligibility::is_eligible(key, proposal, v)
+            }) else {
+                report.rejected.push(index as u32);
+                continue;
+            };
+            if crate::ballots::has_voted(key, &voter) || accepted.iter().any(|(v, _)| *v == voter) {
+                report.duplicates += 1;
+                continue;
+            }
+            match ballot.choice {
+                Choice::Approve => proposal.approve += 1,
+                Choice::Reject => proposal.reject += 1,
+                Choice::Pass => proposal.pass += 1,
+            }
+            accepted.push((voter, ballot.choice.clone()));
+            report.accepted += 1;
+        }
+        Ok((report, accepted, turnout_before))
+    })?;
+    for (voter, choice) in accepted {
+        crate::ballots::record(key, &voter, choice);
+        events::record(key, events::EventKind::VoteCast);
+    }
+    if let Ok(proposal) = load_proposal(key) {
+        webhooks::notify_quorum(key, turnout_before, &proposal);
+    }
+    Ok(report)
+}
@@ -0,0 +1,75 @@
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::{load_proposal, memory, Memory, Proposal, SLUGS_MEMORY_ID};
+
+const MAX_SLUG_BYTES: usize = 64;
+const MAX_BASE_BYTES: usize = 48;
+
+type SlugKey = Blob<MAX_SLUG_BYTES>;
+
+thread_local! {
+    static SLUGS: RefCell<StableBTreeMap<SlugKey, u64, Memory>> = RefCell::new(StableBTreeMap::init(memory(SLUGS_MEMORY_ID)));
+}
+
+// Lowercase ASCII words from the first line of the description, joined by dashes.
+fn base(description: &str) -> String {
+    let first_line = description.lines().next().unwrap_or_default();
+    let mut slug = String::new();
+    for word in first_line
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        if slug.len() + word.len() + 1 > MAX_BASE_BYTES {
+            break;
+        }
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        slug.push_str(&word.to_ascii_lowercase());
+    }
+    if slug.is_empty() {
+        slug.push_str("proposal");
+    }
+    slug
+}
+
+fn slug_key(slug: &str) -> Option<SlugKey> {
+    SlugKey::try_from(slug.as_bytes()).ok()
+}
+
+// The first proposal gets the bare slug, later ones with the same words get "-2", "-3", ...
+pub fn unique(description: &str) -> String {
+    let base = base(description);
+    SLUGS.with(|s| {
+        let slugs = s.borrow();
+        let mut slug = base.clone();
+        let mut suffix = 1u64;
+        while slug_key(&slug).is_some_and(|k| slugs.contains_key(&k)) {
+            suffix += 1;
+            slug = format!("{}-{}", base, suffix);
+        }
+        slug
+    })
+}
+
+// Called once the proposal is stored, so a failed write leaves no slug behind.
+pub fn index(slug: &str, key: u64) {
+    if let Some(k) = slug_key(slug) {
+        SLUGS.with(|s| s.borrow_mut().insert(k, key));
+    }
+}
+
+pub fn unindex(slug: &str) {
+    if let Some(k) = slug_key(slug) {
+        SLUGS.with(|s| s.borrow_mut().remove(&k));
+    }
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_proposal_by_slug(slug: String) -> Option<(u64, Proposal)> {
+    let key = slug_key(&slug).and_then(|k| SLUGS.with(|s| s.borrow().get(&k)))?;
+    load_proposal(key).ok().map(|proposal| (key, proposal))
+}
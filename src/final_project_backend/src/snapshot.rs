@@ -0,0 +1,181 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use serde_bytes::ByteBuf;
+use std::collections::BTreeMap;
+
+use crate::icrc1::Account;
+use crate::{eligibility, env, identity, weights, ErrorCode, VoteError};
+
+const MAX_SNAPSHOT_HOLDERS: usize = 10_000;
+const PAGE_SIZE: u32 = 100;
+// SNS neuron permission that only the controller holds; hotkeys only get to vote.
+const MANAGE_PRINCIPALS_PERMISSION: i32 = 2;
+
+// Where the holders of a snapshot proposal are read from when it is created.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum SnapshotSource {
+    // Every token an owner holds counts once.
+    Icrc7(Principal),
+    // Neuron stake in e8s goes to the neuron's controller, whatever its dissolve delay.
+    SnsGovernance(Principal),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SnapshotInfo {
+    pub source: SnapshotSource,
+    pub taken_at: u64,
+    pub holders: u32,
+    pub total_weight: u128,
+}
+
+// Only snapshotted principals may vote, with the weight they had when it was taken.
+pub struct Snapshot {
+    pub info: SnapshotInfo,
+    holders: Vec<(Principal, u64)>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct NeuronId {
+    id: ByteBuf,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ListNeurons {
+    of_principal: Option<Principal>,
+    limit: u32,
+    start_page_at: Option<NeuronId>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct NeuronPermission {
+    principal: Option<Principal>,
+    permission_type: Vec<i32>,
+}
+
+// Only the fields the snapshot needs; Candid skips the rest of the neuron.
+#[derive(CandidType, Deserialize)]
+struct Neuron {
+    id: Option<NeuronId>,
+    permissions: Vec<NeuronPermission>,
+    cached_neuron_stake_e8s: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ListNeuronsResponse {
+    neurons: Vec<Neuron>,
+}
+
+fn failed(message: impl ToString) -> VoteError {
+    ErrorCode::SnapshotFailed
+        .with_message(message.to_string())
+        .with_field("snapshot")
+}
+
+fn credit(balances: &mut BTreeMap<Principal, u64>, holder: Principal, weight: u64) {
+    let balance = balances.entry(identity::canonical(&holder)).or_default();
+    *balance = balance.saturating_add(weight);
+}
+
+fn check_size(balances: &BTreeMap<Principal, u64>) -> Result<(), VoteError> {
+    if balances.len() > MAX_SNAPSHOT_HOLDERS {
+        return Err(failed(format!(
+            "the source has more than {} holders",
+            MAX_SNAPSHOT_HOLDERS
+        )));
+    }
+    Ok(())
+}
+
+async fn icrc7_holders(ledger: Principal) -> Result<BTreeMap<Principal, u64>, VoteError> {
+    let mut balances = BTreeMap::new();
+    let mut prev: Option<Nat> = None;
+    loop {
+        let (tokens,): (Vec<Nat>,) = ic_cdk::call(
+            ledger,
+            "icrc7_tokens",
+            (prev.clone(), Some(Nat::from(PAGE_SIZE))),
+        )
+        .await
+        .map_err(|(_, message)| failed(message))?;
+        let Some(last) = tokens.last().cloned() else {
+            break;
+        };
+        let (owners,): (Vec<Option<Account>>,) =
+            ic_cdk::call(ledger, "icrc7_owner_of", (tokens.clone(),))
+                .await
+                .map_err(|(_, message)| failed(message))?;
+        for owner in owners.into_iter().flatten() {
+            credit(&mut balances, owner.owner, 1);
+        }
+        check_size(&balances)?;
+        if tokens.len() < PAGE_SIZE as usize {
+            break;
+        }
+        prev = Some(last);
+    }
+    Ok(balances)
+}
+
+async fn sns_holders(governance: Principal) -> Result<BTreeMap<Principal, u64>, VoteError> {
+    let mut balances = BTreeMap::new();
+    let mut start_page_at = None;
+    loop {
+        let request = ListNeurons {
+            of_principal: None,
+            limit: PAGE_SIZE,
+            start_page_at,
+        };
+        let (response,): (ListNeuronsResponse,) =
+            ic_cdk::call(governance, "list_neurons", (request,))
+                .await
+                .map_err(|(_, message)| failed(message))?;
+        let count = response.neurons.len();
+        start_page_at = None;
+        for neuron in response.neurons {
+            let controller = neuron
+                .permissions
+                .iter()
+                .find(|p| p.permission_type.contains(&MANAGE_PRINCIPALS_PERMISSION))
+                .and_then(|p| p.principal);
+            if let Some(controller) = controller.filter(|_| neuron.cached_neuron_stake_e8s > 0) {
+                credit(&mut balances, controller, neuron.cached_neuron_stake_e8s);
+            }
+            start_page_at = neuron.id;
+        }
+        check_size(&balances)?;
+        if count < PAGE_SIZE as usize || start_page_at.is_none() {
+            break;
+        }
+    }
+    Ok(balances)
+}
+
+pub async fn take(source: &SnapshotSource) -> Result<Snapshot, VoteError> {
+    let balances = match source {
+        SnapshotSource::Icrc7(ledger) => icrc7_holders(*ledger).await?,
+        SnapshotSource::SnsGovernance(governance) => sns_holders(*governance).await?,
+    };
+    let holders: Vec<(Principal, u64)> = balances
+        .into_iter()
+        .filter(|(_, weight)| *weight > 0)
+        .collect();
+    if holders.is_empty() {
+        return Err(failed("the source has no holders to vote"));
+    }
+    let total_weight = holders.iter().map(|(_, weight)| *weight as u128).sum();
+    Ok(Snapshot {
+        info: SnapshotInfo {
+            source: source.clone(),
+            taken_at: env::time(),
+            holders: holders.len() as u32,
+            total_weight,
+        },
+        holders,
+    })
+}
+
+// Called once the proposal is stored with `restricted` and an empty weighted tally.
+pub fn store(key: u64, snapshot: &Snapshot) {
+    let voters: Vec<Principal> = snapshot.holders.iter().map(|(holder, _)| *holder).collect();
+    eligibility::add_all(key, &voters);
+    weights::assign_all(key, &snapshot.holders);
+}
@@ -0,0 +1,49 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use serde_bytes::ByteBuf;
+use std::cell::RefCell;
+
+use crate::{memory, Memory, CHILD_DAOS_MEMORY_ID};
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ChildDao {
+    pub proposal: u64,
+    pub canister_id: Principal,
+    pub name: String,
+    pub wasm_hash: ByteBuf,
+    pub created_at: u64,
+    pub installed: bool,
+}
+
+impl Default for ChildDao {
+    fn default() -> Self {
+        ChildDao {
+            proposal: 0,
+            canister_id: Principal::anonymous(),
+            name: String::new(),
+            wasm_hash: ByteBuf::new(),
+            created_at: 0,
+            installed: false,
+        }
+    }
+}
+
+candid_storable!(ChildDao, 256);
+
+thread_local! {
+    static CHILDREN: RefCell<StableBTreeMap<u64, ChildDao, Memory>> = RefCell::new(StableBTreeMap::init(memory(CHILD_DAOS_MEMORY_ID)));
+}
+
+pub fn by_proposal(proposal: u64) -> Option<ChildDao> {
+    CHILDREN.with(|c| c.borrow().get(&proposal))
+}
+
+pub fn register(child: ChildDao) {
+    CHILDREN.with(|c| c.borrow_mut().insert(child.proposal, child));
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_child_daos() -> Vec<ChildDao> {
+    CHILDREN.with(|c| c.borrow().iter().map(|(_, child)| child).collect())
+}
@@ -0,0 +1,161 @@
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::ops::Bound;
+
+use crate::listing::ProposalPage;
+use crate::{
+    config, load_proposal, memory, ErrorCode, Memory, Proposal, VoteError, TAGS_MEMORY_ID,
+    TAG_COUNTS_MEMORY_ID,
+};
+
+pub const MAX_TAG_BYTES: usize = 32;
+const MAX_TAGS: usize = 5;
+
+type TagKey = Blob<MAX_TAG_BYTES>;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TagCount {
+    pub tag: String,
+    pub proposals: u64,
+}
+
+thread_local! {
+    static TAGGED: RefCell<StableBTreeMap<(TagKey, u64), (), Memory>> = RefCell::new(StableBTreeMap::init(memory(TAGS_MEMORY_ID)));
+
+    static COUNTS: RefCell<StableBTreeMap<TagKey, u64, Memory>> = RefCell::new(StableBTreeMap::init(memory(TAG_COUNTS_MEMORY_ID)));
+}
+
+// Tags are compared lowercased, made of ASCII letters, digits and dashes.
+pub fn normalize(tag: &str) -> Result<String, VoteError> {
+    let tag = tag.trim().to_ascii_lowercase();
+    let well_formed = !tag.is_empty()
+        && tag.len() <= MAX_TAG_BYTES
+        && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !well_formed {
+        return Err(ErrorCode::InvalidTag.with_field("tag"));
+    }
+    Ok(tag)
+}
+
+// Normalized, with repeats dropped.
+pub fn normalize_all(tags: &[String]) -> Result<Vec<String>, VoteError> {
+    let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags {
+        let tag = normalize(tag).map_err(|e| e.with_field("tags"))?;
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    if normalized.len() > MAX_TAGS {
+        return Err(ErrorCode::InvalidItemCount
+            .with_message(format!("a proposal can have at most {} tags", MAX_TAGS))
+            .with_field("tags"));
+    }
+    Ok(normalized)
+}
+
+fn tag_key(tag: &str) -> TagKey {
+    TagKey::try_from(tag.as_bytes()).expect("tags are normalized before indexing")
+}
+
+pub fn index(key: u64, tags: &[String]) {
+    for tag in tags {
+        let tag = tag_key(tag);
+        if TAGGED.with(|t| t.borrow_mut().insert((tag, key), ()).is_none()) {
+            COUNTS.with(|c| {
+                let count = c.borrow().get(&tag).unwrap_or(0);
+                c.borrow_mut().insert(tag, count + 1);
+            });
+        }
+    }
+}
+
+pub fn unindex(key: u64, tags: &[String]) {
+    for tag in tags {
+        let tag = tag_key(tag);
+        if TAGGED.with(|t| t.borrow_mut().remove(&(tag, key)).is_some()) {
+            COUNTS.with(|c| {
+                let count = c.borrow().get(&tag).unwrap_or(0);
+                if count <= 1 {
+                    c.borrow_mut().remove(&tag);
+                } else {
+                    c.borrow_mut().insert(tag, count - 1);
+                }
+            });
+        }
+    }
+}
+
+pub fn has_tag(proposal: &Proposal, tag: &str) -> bool {
+    proposal.tags.iter().flatten().any(|t| t == tag)
+}
+
+fn tag_text(tag: &TagKey) -> String {
+    String::from_utf8_lossy(tag.as_slice()).into_owned()
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TagPage {
+    pub tags: Vec<TagCount>,
+    // The last tag listed.
+    pub next: Option<String>,
+}
+
+pub fn tag_page(after: Option<String>, page: usize) -> Result<TagPage, VoteError> {
+    let start = match after {
+        Some(tag) => Bound::Excluded(tag_key(
+            &normalize(&tag).map_err(|e| e.with_field("after"))?,
+        )),
+        None => Bound::Unbounded,
+    };
+    let (tags, next) = COUNTS.with(|c| {
+        config::scan_page(
+            c.borrow().range((start, Bound::Unbounded)),
+            page,
+            |tag, proposals| {
+                Some(TagCount {
+                    tag: tag_text(tag),
+                    proposals,
+                })
+            },
+        )
+    });
+    Ok(TagPage {
+        tags,
+        next: next.as_ref().map(tag_text),
+    })
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_tags_v2(after: Option<String>, limit: u64) -> Result<TagPage, VoteError> {
+    tag_page(after, config::page_limit(limit))
+}
+
+pub fn tagged_page(tag: &str, after: Option<u64>, page: usize) -> Result<ProposalPage, VoteError> {
+    let tag = tag_key(&normalize(tag)?);
+    let start = after.map_or(Bound::Included((tag, 0)), |key| Bound::Excluded((tag, key)));
+    let (proposals, next) = TAGGED.with(|t| {
+        config::scan_page(
+            t.borrow().range((start, Bound::Included((tag, u64::MAX)))),
+            page,
+            |(_, key), _| load_proposal(*key).ok().map(|proposal| (*key, proposal)),
+        )
+    });
+    Ok(ProposalPage {
+        proposals,
+        next: next.map(|(_, key)| key),
+    })
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_proposals_by_tag_v2(
+    tag: String,
+    after: Option<u64>,
+    limit: u64,
+) -> Result<ProposalPage, VoteError> {
+    tagged_page(&tag, after, config::page_limit(limit))
+}
@@ -0,0 +1,75 @@
+// Helpers for the unit tests. Each test runs on its own thread, so it starts from
+// empty stable memory.
+use candid::Principal;
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+use crate::{create, env, CreateProposal, ImportedProposal};
+
+pub const NOW: u64 = 1_700_000_000_000_000_000;
+
+pub fn principal(id: u8) -> Principal {
+    Principal::from_slice(&[id; 29])
+}
+
+pub fn as_caller(id: u8) -> Principal {
+    let caller = principal(id);
+    env::set_time(NOW);
+    env::set_caller(caller);
+    caller
+}
+
+pub fn proposal(description: &str) -> CreateProposal {
+    CreateProposal {
+        description: description.to_string(),
+        is_active: true,
+        kind: None,
+        track: None,
+        electorate: None,
+        language: None,
+        translations: None,
+        deadline_ns: None,
+        quorum: None,
+        approval_threshold_percent: None,
+        eligible_voters: None,
+        weight_ledger: None,
+        options: None,
+        secret_ballot: None,
+        title: None,
+        tags: None,
+        snapshot: None,
+        execution_payload: None,
+        idempotency_key: None,
+    }
+}
+
+// Creates an open proposal owned by the current caller.
+pub fn open_proposal(description: &str) -> u64 {
+    create(proposal(description), None)
+        .expect("the proposal is valid")
+        .key
+}
+
+// A closed proposal with one recorded voter, ready for `import`.
+pub fn imported(key: u64) -> ImportedProposal {
+    ImportedProposal {
+        key,
+        description: "decided elsewhere".to_string(),
+        owner: principal(9),
+        approve: 2,
+        reject: 1,
+        pass: 0,
+        voted: vec![principal(8)],
+        created_at: Some(1),
+        closed_at: 2,
+    }
+}
+
+// Runs calls that only await inter-canister calls on paths the tests do not take.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("the call awaited another canister"),
+    }
+}
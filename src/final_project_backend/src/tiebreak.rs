@@ -0,0 +1,125 @@
+use candid::{CandidType, Deserialize};
+use serde_bytes::ByteBuf;
+
+use crate::kinds::ProposalKind;
+use crate::{
+    config, env, execution, inbox, load_proposal, store_proposal, ErrorCode, Proposal,
+    ProposalResult, VoteError,
+};
+
+// What happens when approve and reject are level under a simple majority, or when no
+// option is left standing after the runoff.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum TieBreak {
+    // The proposal fails, as it did before ties could be broken.
+    #[default]
+    Reject,
+    // The proposal ends as `Tied` until `finalize_proposal` draws the winner.
+    Random,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum TieCandidate {
+    Approve,
+    Reject,
+    Option(u8),
+}
+
+// Kept on the proposal so anyone can check the draw: the winner is
+// `candidates[u64::from_be_bytes(randomness[..8]) % candidates.len()]`.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TieBreakDraw {
+    pub randomness: ByteBuf,
+    pub candidates: Vec<TieCandidate>,
+    pub winner: TieCandidate,
+    pub drawn_at: u64,
+}
+
+// Elections already break their ties by principal.
+pub fn is_tied(proposal: &Proposal) -> bool {
+    if proposal.tie_break.is_some()
+        || config::get().tie_break != TieBreak::Random
+        || matches!(proposal.kind, Some(ProposalKind::Election(_)))
+    {
+        return false;
+    }
+    !candidates(proposal).is_empty()
+}
+
+fn candidates(proposal: &Proposal) -> Vec<TieCandidate> {
+    if proposal.options.is_some() {
+        let Some(runoff) = proposal.runoff.as_ref().filter(|r| r.winner.is_none()) else {
+            return Vec::new();
+        };
+        let last = runoff.rounds.last().cloned().unwrap_or_default();
+        let most = last.iter().copied().max().unwrap_or(0);
+        if most == 0 {
+            return Vec::new();
+        }
+        return (0..last.len())
+            .filter(|option| last[*option] == most)
+            .map(|option| TieCandidate::Option(option as u8))
+            .collect();
+    }
+    let (approve, reject) = proposal.decisive_votes();
+    if approve == 0 || approve != reject || proposal.threshold_percent() != 50 {
+        return Vec::new();
+    }
+    vec![TieCandidate::Approve, TieCandidate::Reject]
+}
+
+fn draw(proposal: &Proposal, randomness: Vec<u8>) -> Option<TieBreakDraw> {
+    let candidates = candidates(proposal);
+    if candidates.is_empty() {
+        return None;
+    }
+    let mut seed = [0u8; 8];
+    for (byte, random) in seed.iter_mut().zip(&randomness) {
+        *byte = *random;
+    }
+    let index = u64::from_be_bytes(seed) % candidates.len() as u64;
+    Some(TieBreakDraw {
+        winner: candidates[index as usize],
+        candidates,
+        randomness: ByteBuf::from(randomness),
+        drawn_at: env::time(),
+    })
+}
+
+// Closed proposals that are not tied already have their final result, which is
+// returned unchanged.
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+async fn finalize_proposal(key: u64) -> Result<ProposalResult, VoteError> {
+    config::ensure_not_paused()?;
+    env::authenticated_caller()?;
+    let proposal = load_proposal(key)?;
+    if proposal.is_active {
+        return Err(ErrorCode::StillOpen.into());
+    }
+    if proposal.result() != ProposalResult::Tied {
+        return Ok(proposal.result());
+    }
+    let randomness = env::random().await?;
+    // Someone else may have finalized it while we waited.
+    let mut proposal = load_proposal(key)?;
+    if proposal.result() != ProposalResult::Tied {
+        return Ok(proposal.result());
+    }
+    // Without candidates (the threshold changed since it closed) there is nothing to
+    // draw and the proposal takes its ordinary outcome.
+    if let Some(drawn) = draw(&proposal, randomness) {
+        if let (TieCandidate::Option(option), Some(runoff)) =
+            (drawn.winner, proposal.runoff.as_mut())
+        {
+            runoff.winner = Some(option);
+        }
+        proposal.tie_break = Some(drawn);
+    }
+    proposal.result = Some(proposal.outcome());
+    execution::queue(&mut proposal);
+    store_proposal(key, &proposal)?;
+    inbox::notify_outcome(key, &proposal);
+    execution::schedule(key, &proposal);
+    Ok(proposal.result())
+}
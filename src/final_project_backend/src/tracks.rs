@@ -0,0 +1,165 @@
+use candid::{CandidType, Deserialize, Principal};
+use std::time::Duration;
+
+use crate::roles::{self, Role};
+use crate::{
+    config, env, update_proposal, ErrorCode, Proposal, ProposalStore, ProposalSummary, PROPOSAL_MAP,
+};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Track {
+    #[default]
+    Standard,
+    Emergency,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Electorate {
+    #[default]
+    Referendum,
+    Council,
+}
+
+pub fn electorate_of(proposal: &Proposal) -> Electorate {
+    proposal.electorate.unwrap_or_default()
+}
+
+pub fn may_vote(proposal: &Proposal, voter: &Principal) -> bool {
+    match electorate_of(proposal) {
+        Electorate::Referendum => true,
+        Electorate::Council => roles::has_role(voter, Role::Council),
+    }
+}
+
+// Council quorums are a share of the council as it stood when the proposal opened,
+// so a later election cannot change whether an earlier vote passed.
+pub fn quorum(proposal: &Proposal) -> u32 {
+    let config = config::get();
+    match electorate_of(proposal) {
+        Electorate::Referendum => proposal.quorum.unwrap_or(config.default_quorum),
+        Electorate::Council => {
+            let members = proposal.council_size.unwrap_or(0) as u64;
+            let percent = config.council_quorum_percent as u64;
+            (members * percent).div_ceil(100).max(1) as u32
+        }
+    }
+}
+
+pub fn council_size() -> u32 {
+    roles::members(Role::Council).len() as u32
+}
+
+pub fn track_of(proposal: &Proposal) -> Track {
+    proposal.track.unwrap_or_default()
+}
+
+pub fn is_emergency(proposal: &Proposal) -> bool {
+    track_of(proposal) == Track::Emergency
+}
+
+pub fn may_create(principal: &Principal, track: Track) -> bool {
+    match track {
+        Track::Standard => true,
+        Track::Emergency => {
+            roles::has_role(principal, Role::Council) || roles::has_role(principal, Role::Admin)
+        }
+    }
+}
+
+pub fn threshold_percent(proposal: &Proposal, base: u8) -> u8 {
+    if is_emergency(proposal) {
+        base.max(config::get().emergency_threshold_percent)
+    } else {
+        base
+    }
+}
+
+fn voting_ends_at(track: Track, now: u64) -> Option<u64> {
+    match track {
+        Track::Standard => None,
+        Track::Emergency => Some(
+            now.saturating_add(
+                config::get()
+                    .emergency_voting_period_secs
+                    .saturating_mul(NANOS_PER_SEC),
+            ),
+        ),
+    }
+}
+
+// Whichever comes first of the track's voting period and the owner's own deadline.
+pub fn ends_at(track: Track, deadline: Option<u64>, now: u64) -> Option<u64> {
+    match (voting_ends_at(track, now), deadline) {
+        (Some(period_end), Some(deadline)) => Some(period_end.min(deadline)),
+        (period_end, deadline) => period_end.or(deadline),
+    }
+}
+
+// Secret ballots stay open past the voting period for their reveal window.
+pub fn closes_at(proposal: &Proposal) -> Option<u64> {
+    let ends_at = proposal.voting_ends_at?;
+    Some(ends_at.saturating_add(proposal.reveal_window_ns.unwrap_or(0)))
+}
+
+pub fn voting_closed(proposal: &Proposal) -> bool {
+    proposal
+        .voting_ends_at
+        .is_some_and(|ends_at| env::time() >= ends_at)
+}
+
+// Skipped while paused; unpausing sets the timer again.
+fn close_expired(key: u64) {
+    if config::get().paused {
+        return;
+    }
+    let ended = update_proposal(key, |proposal| {
+        if !proposal.is_active || closes_at(proposal).is_none_or(|at| env::time() < at) {
+            return Err(ErrorCode::ProposalIsNotActive.into());
+        }
+        crate::close(key, proposal);
+        Ok(proposal.clone())
+    });
+    if let Ok(ended) = ended {
+        crate::after_close(key, &ended);
+    }
+}
+
+pub fn schedule_close(key: u64, proposal: &Proposal) {
+    let Some(ends_at) = closes_at(proposal) else {
+        return;
+    };
+    if !proposal.is_active {
+        return;
+    }
+    let delay = ends_at.saturating_sub(env::time());
+    env::set_timer(Duration::from_nanos(delay), move || close_expired(key));
+}
+
+pub fn reschedule_all() {
+    let open: Vec<(u64, Proposal)> = PROPOSAL_MAP.with(|p| {
+        p.borrow()
+            .decoded()
+            .filter(|(_, proposal)| proposal.is_active && proposal.voting_ends_at.is_some())
+            .collect()
+    });
+    for (key, proposal) in open {
+        schedule_close(key, &proposal);
+    }
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_emergency_proposals() -> Vec<ProposalSummary> {
+    let emergencies: Vec<(u64, Proposal)> = PROPOSAL_MAP.with(|p| {
+        p.borrow()
+            .decoded()
+            .filter(|(_, proposal)| proposal.is_active && is_emergency(proposal))
+            .collect()
+    });
+    emergencies
+        .into_iter()
+        .map(|(key, proposal)| crate::summarize(key, proposal))
+        .collect()
+}
@@ -0,0 +1,77 @@
+use candid::{Nat, Principal};
+use serde_bytes::ByteBuf;
+
+use crate::icrc1::{self, Account, TransferArg, TransferFromArgs};
+use crate::{config, ErrorCode, VoteError};
+
+// Funds the DAO controls are kept apart from the canister's default account, which
+// also receives cycles-related and accidental deposits.
+pub const SUBACCOUNT: [u8; 32] = {
+    let mut subaccount = [0u8; 32];
+    subaccount[31] = 1;
+    subaccount
+};
+
+pub fn subaccount() -> ByteBuf {
+    ByteBuf::from(SUBACCOUNT.to_vec())
+}
+
+// Deposits are pulled from the payer's default account through an ICRC-2 approval and
+// held in the treasury subaccount. Nothing is taken while no deposit ledger is set.
+pub async fn collect_deposit(from: Principal, amount: u64) -> Result<u64, VoteError> {
+    let Some(ledger) = config::get().deposit_ledger else {
+        return Ok(0);
+    };
+    if amount == 0 {
+        return Ok(0);
+    }
+    let arg = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account {
+            owner: from,
+            subaccount: None,
+        },
+        to: Account {
+            owner: ic_cdk::id(),
+            subaccount: Some(subaccount()),
+        },
+        amount: Nat::from(amount),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    icrc1::transfer_from(ledger, arg)
+        .await
+        .map_err(|_| ErrorCode::DepositFailed)?;
+    Ok(amount)
+}
+
+pub async fn refund_deposit(to: Principal, amount: u64) {
+    let Some(ledger) = config::get().deposit_ledger else {
+        return;
+    };
+    if amount == 0 {
+        return;
+    }
+    let arg = TransferArg {
+        from_subaccount: Some(subaccount()),
+        to: Account {
+            owner: to,
+            subaccount: None,
+        },
+        amount: Nat::from(amount),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    let _ = icrc1::transfer(ledger, arg).await;
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_treasury_account() -> Account {
+    Account {
+        owner: ic_cdk::id(),
+        subaccount: Some(subaccount()),
+    }
+}
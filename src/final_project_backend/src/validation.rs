@@ -0,0 +1,54 @@
+use crate::{env, ErrorCode, VoteError};
+
+pub const MAX_DESCRIPTION_BYTES: usize = 2000;
+const MAX_TITLE_BYTES: usize = 120;
+const MAX_VOTING_DURATION_NANOS: u64 = 90 * 24 * 60 * 60 * 1_000_000_000;
+
+pub fn text(field: &str, value: &str, max_bytes: usize) -> Result<(), VoteError> {
+    if value.trim().is_empty() {
+        return Err(ErrorCode::EmptyText.with_field(field));
+    }
+    if value.len() > max_bytes {
+        return Err(ErrorCode::TextTooLong
+            .with_message(format!("{} must be at most {} bytes", field, max_bytes))
+            .with_field(field));
+    }
+    Ok(())
+}
+
+pub fn description(value: &str) -> Result<(), VoteError> {
+    text("description", value, MAX_DESCRIPTION_BYTES)
+}
+
+// Titles are shown on one line in lists, so they may not contain line breaks.
+pub fn title(value: &str) -> Result<(), VoteError> {
+    text("title", value, MAX_TITLE_BYTES)?;
+    if value.chars().any(char::is_control) {
+        return Err(ErrorCode::ValidationFailed
+            .with_message("title must be a single line without control characters")
+            .with_field("title"));
+    }
+    Ok(())
+}
+
+pub fn item_count(field: &str, count: usize, max: usize) -> Result<(), VoteError> {
+    if count == 0 || count > max {
+        return Err(ErrorCode::InvalidItemCount
+            .with_message(format!("{} must have between 1 and {} items", field, max))
+            .with_field(field));
+    }
+    Ok(())
+}
+
+pub fn deadline(field: &str, at: u64) -> Result<(), VoteError> {
+    let now = env::time();
+    if at <= now || at - now > MAX_VOTING_DURATION_NANOS {
+        return Err(ErrorCode::InvalidDeadline
+            .with_message(format!(
+                "{} must be in the future and at most 90 days away",
+                field
+            ))
+            .with_field(field));
+    }
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use candid::{CandidType, Deserialize};
+
+use crate::execution::{self, ExecutionStatus};
+use crate::roles::{self, Role};
+use crate::{
+    config, env, identity, update_proposal, validation, ErrorCode, Proposal, ProposalStore,
+    VoteError, PROPOSAL_MAP,
+};
+
+const MAX_VETO_REASON_BYTES: usize = 500;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PendingExecution {
+    pub key: u64,
+    pub closed_at: Option<u64>,
+    pub veto_deadline: u64,
+    pub executable_at: u64,
+}
+
+// None until the proposal has closed.
+pub fn deadline(proposal: &Proposal) -> Option<u64> {
+    let window = config::get().veto_window_secs.saturating_mul(NANOS_PER_SEC);
+    Some(proposal.closed_at?.saturating_add(window))
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn veto_proposal(key: u64, reason: String) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let caller = identity::voter()?;
+    if !roles::has_role(&caller, Role::Council) {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    validation::text("reason", &reason, MAX_VETO_REASON_BYTES)?;
+    update_proposal(key, |proposal| {
+        let Some(deadline) = deadline(proposal).filter(|_| execution::awaiting_execution(proposal))
+        else {
+            return Err(ErrorCode::NotExecutable.into());
+        };
+        if env::time() >= deadline {
+            return Err(ErrorCode::VetoWindowClosed.into());
+        }
+        proposal.execution = Some(ExecutionStatus::Vetoed {
+            by: caller,
+            reason,
+            vetoed_at: env::time(),
+        });
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_pending_executions() -> Vec<PendingExecution> {
+    let now = env::time();
+    PROPOSAL_MAP.with(|p| {
+        p.borrow()
+            .decoded()
+            .filter(|(_, proposal)| execution::awaiting_execution(proposal))
+            .filter_map(|(key, proposal)| {
+                Some(PendingExecution {
+                    key,
+                    closed_at: proposal.closed_at,
+                    veto_deadline: deadline(&proposal)?,
+                    executable_at: execution::executable_at(&proposal)?,
+                })
+            })
+            .filter(|pending| now < pending.veto_deadline)
+            .collect()
+    })
+}
@@ -0,0 +1,134 @@
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use crate::roles;
+use crate::{memory, ErrorCode, Memory, VoteError, WASM_CHUNKS_MEMORY_ID};
+
+const MAX_CHUNK_BYTES: usize = 256 * 1024;
+const MAX_CHUNKS: u32 = 64;
+
+pub type WasmHash = [u8; 32];
+
+struct WasmChunk(Vec<u8>);
+
+impl Storable for WasmChunk {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        WasmChunk(bytes.into_owned())
+    }
+}
+
+impl BoundedStorable for WasmChunk {
+    const MAX_SIZE: u32 = MAX_CHUNK_BYTES as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct UploadedWasm {
+    pub wasm_hash: ByteBuf,
+    pub chunks: u32,
+    pub size: u64,
+    pub hash_matches: bool,
+}
+
+thread_local! {
+    static CHUNKS: RefCell<StableBTreeMap<(WasmHash, u32), WasmChunk, Memory>> = RefCell::new(StableBTreeMap::init(memory(WASM_CHUNKS_MEMORY_ID)));
+}
+
+pub fn parse_hash(bytes: &[u8]) -> Option<WasmHash> {
+    bytes.try_into().ok()
+}
+
+fn chunks_of(hash: &WasmHash) -> Vec<(u32, Vec<u8>)> {
+    CHUNKS.with(|c| {
+        c.borrow()
+            .range((*hash, 0)..=(*hash, u32::MAX))
+            .map(|((_, index), chunk)| (index, chunk.0))
+            .collect()
+    })
+}
+
+// Returns the module only if the chunks are contiguous and hash to the expected value.
+pub fn assemble(hash: &WasmHash) -> Option<Vec<u8>> {
+    let mut module = Vec::new();
+    for (expected, (index, chunk)) in chunks_of(hash).into_iter().enumerate() {
+        if index as usize != expected {
+            return None;
+        }
+        module.extend_from_slice(&chunk);
+    }
+    let digest: WasmHash = Sha256::digest(&module).into();
+    (!module.is_empty() && digest == *hash).then_some(module)
+}
+
+pub fn module_for(wasm_hash: &[u8]) -> Result<(WasmHash, Vec<u8>), String> {
+    let hash = parse_hash(wasm_hash).ok_or_else(|| "wasm_hash must be 32 bytes".to_string())?;
+    let module =
+        assemble(&hash).ok_or_else(|| "no uploaded wasm matches the voted hash".to_string())?;
+    Ok((hash, module))
+}
+
+pub fn remove(hash: &WasmHash) {
+    let indices: Vec<u32> = chunks_of(hash)
+        .into_iter()
+        .map(|(index, _)| index)
+        .collect();
+    CHUNKS.with(|c| {
+        let mut chunks = c.borrow_mut();
+        for index in indices {
+            chunks.remove(&(*hash, index));
+        }
+    });
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn upload_wasm_chunk(wasm_hash: ByteBuf, index: u32, chunk: ByteBuf) -> Result<(), VoteError> {
+    roles::require_admin()?;
+    let hash =
+        parse_hash(&wasm_hash).ok_or_else(|| ErrorCode::InvalidWasm.with_field("wasm_hash"))?;
+    if index >= MAX_CHUNKS {
+        return Err(ErrorCode::InvalidWasm.with_field("index"));
+    }
+    if chunk.is_empty() || chunk.len() > MAX_CHUNK_BYTES {
+        return Err(ErrorCode::InvalidWasm.with_field("chunk"));
+    }
+    CHUNKS.with(|c| {
+        c.borrow_mut()
+            .insert((hash, index), WasmChunk(chunk.into_vec()))
+    });
+    Ok(())
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn delete_wasm(wasm_hash: ByteBuf) -> Result<(), VoteError> {
+    roles::require_admin()?;
+    let hash =
+        parse_hash(&wasm_hash).ok_or_else(|| ErrorCode::InvalidWasm.with_field("wasm_hash"))?;
+    remove(&hash);
+    Ok(())
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn get_uploaded_wasm(wasm_hash: ByteBuf) -> Option<UploadedWasm> {
+    let hash = parse_hash(&wasm_hash)?;
+    let chunks = chunks_of(&hash);
+    if chunks.is_empty() {
+        return None;
+    }
+    Some(UploadedWasm {
+        chunks: chunks.len() as u32,
+        size: chunks.iter().map(|(_, chunk)| chunk.len() as u64).sum(),
+        hash_matches: assemble(&hash).is_some(),
+        wasm_hash,
+    })
+}
@@ -0,0 +1,417 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
+    HttpResponse as OutcallResponse, TransformArgs, TransformContext,
+};
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use std::cell::{Cell, RefCell};
+use std::ops::Bound;
+use std::time::Duration;
+
+use crate::roles;
+use crate::{
+    config, env, json, load_proposal, memory, tracks, validation, ErrorCode, Memory, Proposal,
+    ProposalResult, VoteError, DEAD_LETTERS_MEMORY_ID, WEBHOOKS_MEMORY_ID,
+    WEBHOOK_DELIVERY_SEQ_MEMORY_ID, WEBHOOK_QUEUE_MEMORY_ID,
+};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const MAX_WEBHOOKS: u64 = 10;
+const MAX_URL_BYTES: usize = 256;
+const MAX_ERROR_BYTES: usize = 200;
+const MAX_QUEUED: u64 = 1000;
+const MAX_DEAD_LETTERS: u64 = 1000;
+const MAX_SENT_PER_RUN: usize = 10;
+const MAX_ATTEMPTS: u32 = 5;
+const RETRY_BACKOFF_SECS: u64 = 30;
+const MAX_RESPONSE_BYTES: u64 = 1024;
+const STALE_RUN_SECS: u64 = 10 * 60;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum WebhookEvent {
+    ProposalCreated,
+    ProposalClosed,
+    QuorumReached,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct Webhook {
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub registered_at: u64,
+}
+
+candid_storable!(Webhook, 512);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Delivery {
+    pub id: u64,
+    pub webhook: u64,
+    pub event: WebhookEvent,
+    pub proposal: u64,
+    pub occurred_at: u64,
+    pub attempts: u32,
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+}
+
+impl Default for Delivery {
+    fn default() -> Self {
+        Delivery {
+            id: 0,
+            webhook: 0,
+            event: WebhookEvent::ProposalCreated,
+            proposal: 0,
+            occurred_at: 0,
+            attempts: 0,
+            next_attempt_at: u64::MAX,
+            last_error: None,
+        }
+    }
+}
+
+candid_storable!(Delivery, 512);
+
+// Every replica of the subnet sends the request, so receivers should drop repeated
+// `delivery` ids.
+#[derive(CandidType)]
+struct WebhookPayload {
+    delivery: u64,
+    event: WebhookEvent,
+    proposal: u64,
+    occurred_at: u64,
+    title: Option<String>,
+    approve: Option<u32>,
+    reject: Option<u32>,
+    pass: Option<u32>,
+    result: Option<ProposalResult>,
+}
+
+thread_local! {
+    static WEBHOOKS: RefCell<StableBTreeMap<u64, Webhook, Memory>> = RefCell::new(StableBTreeMap::init(memory(WEBHOOKS_MEMORY_ID)));
+
+    static QUEUE: RefCell<StableBTreeMap<u64, Delivery, Memory>> = RefCell::new(StableBTreeMap::init(memory(WEBHOOK_QUEUE_MEMORY_ID)));
+
+    // Deliveries that ran out of attempts, oldest first, until an admin retries them.
+    static DEAD_LETTERS: RefCell<StableBTreeMap<u64, Delivery, Memory>> = RefCell::new(StableBTreeMap::init(memory(DEAD_LETTERS_MEMORY_ID)));
+
+    static NEXT_DELIVERY: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(memory(WEBHOOK_DELIVERY_SEQ_MEMORY_ID), 0).expect("failed to initialize webhook deliveries")
+    );
+
+    // The one pending delivery timer and when it fires; dropped with the timers on upgrade.
+    static TIMER: Cell<Option<(TimerId, u64)>> = const { Cell::new(None) };
+
+    // When the current run started; a run that trapped after an outcall is given up on
+    // once it is older than `STALE_RUN_SECS`.
+    static DELIVERING: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+fn validate_url(url: &str) -> Result<(), VoteError> {
+    validation::text("url", url, MAX_URL_BYTES)?;
+    if !url.starts_with("https://") || url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(ErrorCode::ValidationFailed
+            .with_message("webhooks need an https:// URL without spaces")
+            .with_field("url"));
+    }
+    Ok(())
+}
+
+fn next_delivery_id() -> u64 {
+    NEXT_DELIVERY.with(|s| {
+        let id = *s.borrow().get();
+        s.borrow_mut()
+            .set(id + 1)
+            .expect("failed to advance webhook deliveries");
+        id
+    })
+}
+
+fn bury(delivery: Delivery) {
+    DEAD_LETTERS.with(|d| {
+        let mut dead = d.borrow_mut();
+        while dead.len() >= MAX_DEAD_LETTERS {
+            let Some((oldest, _)) = dead.iter().next() else {
+                break;
+            };
+            dead.remove(&oldest);
+        }
+        dead.insert(delivery.id, delivery);
+    });
+}
+
+fn backoff(attempts: u32) -> u64 {
+    let secs = RETRY_BACKOFF_SECS << attempts.min(16);
+    secs.saturating_mul(NANOS_PER_SEC)
+}
+
+pub fn notify(proposal: u64, event: WebhookEvent) {
+    let subscribed: Vec<u64> = WEBHOOKS.with(|w| {
+        w.borrow()
+            .iter()
+            .filter(|(_, webhook)| webhook.events.contains(&event))
+            .map(|(id, _)| id)
+            .collect()
+    });
+    if subscribed.is_empty() {
+        return;
+    }
+    let now = env::time();
+    for webhook in subscribed {
+        let delivery = Delivery {
+            id: next_delivery_id(),
+            webhook,
+            event,
+            proposal,
+            occurred_at: now,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+        };
+        if QUEUE.with(|q| q.borrow().len()) >= MAX_QUEUED {
+            bury(Delivery {
+                last_error: Some("the delivery queue was full".to_string()),
+                ..delivery
+            });
+            continue;
+        }
+        QUEUE.with(|q| q.borrow_mut().insert(delivery.id, delivery));
+    }
+    schedule(now);
+}
+
+// Only crossings count, so a vote changed back and forth around the quorum can announce
+// it again but an ordinary vote after it does not.
+pub fn notify_quorum(key: u64, turnout_before: u32, proposal: &Proposal) {
+    let quorum = tracks::quorum(proposal);
+    if quorum > 0 && turnout_before < quorum && proposal.turnout() >= quorum {
+        notify(key, WebhookEvent::QuorumReached);
+    }
+}
+
+// Keeps a single timer, moved only when something falls due before it fires.
+fn schedule(at: u64) {
+    if let Some((timer, armed_at)) = TIMER.get() {
+        if armed_at <= at {
+            return;
+        }
+        ic_cdk_timers::clear_timer(timer);
+    }
+    let delay = at.saturating_sub(env::time());
+    let timer = env::set_timer(Duration::from_nanos(delay), || {
+        TIMER.set(None);
+        ic_cdk::spawn(deliver_due());
+    });
+    TIMER.set(Some((timer, at)));
+}
+
+pub fn reschedule() {
+    let earliest = QUEUE.with(|q| {
+        q.borrow()
+            .iter()
+            .map(|(_, delivery)| delivery.next_attempt_at)
+            .min()
+    });
+    if let Some(at) = earliest {
+        schedule(at);
+    }
+}
+
+fn payload(delivery: &Delivery) -> Result<String, VoteError> {
+    let proposal = load_proposal(delivery.proposal).ok();
+    json::to_json(&WebhookPayload {
+        delivery: delivery.id,
+        event: delivery.event,
+        proposal: delivery.proposal,
+        occurred_at: delivery.occurred_at,
+        title: proposal.as_ref().and_then(|p| p.title.clone()),
+        approve: proposal.as_ref().map(|p| p.approve),
+        reject: proposal.as_ref().map(|p| p.reject),
+        pass: proposal.as_ref().map(|p| p.pass),
+        result: proposal.as_ref().map(Proposal::result),
+    })
+}
+
+async fn send(delivery: &Delivery) -> Result<(), String> {
+    let url = WEBHOOKS
+        .with(|w| w.borrow().get(&delivery.webhook))
+        .map(|webhook| webhook.url)
+        .ok_or_else(|| "the webhook was unregistered".to_string())?;
+    let body = payload(delivery).map_err(|_| "the payload could not be encoded".to_string())?;
+    let request = CanisterHttpRequestArgument {
+        url,
+        max_response_bytes: Some(MAX_RESPONSE_BYTES),
+        method: HttpMethod::POST,
+        headers: vec![HttpHeader {
+            name: "Content-Type".to_string(),
+            value: "application/json".to_string(),
+        }],
+        body: Some(body.into_bytes()),
+        transform: Some(TransformContext::new(webhook_transform, vec![])),
+    };
+    let (response,) = http_request(request)
+        .await
+        .map_err(|(_, message)| message)?;
+    let status = u16::try_from(response.status.0).unwrap_or_default();
+    if (200..300).contains(&status) {
+        Ok(())
+    } else {
+        Err(format!("the webhook answered with status {}", status))
+    }
+}
+
+// Each delivery is pushed back before it is sent, so one that traps is retried later
+// rather than lost.
+// A run that starts while another is still waiting on its outcalls leaves the queue to
+// it; the earlier run re-arms the timer when it finishes.
+async fn deliver_due() {
+    if config::get().paused {
+        return;
+    }
+    let now = env::time();
+    let stale_after = STALE_RUN_SECS.saturating_mul(NANOS_PER_SEC);
+    if let Some(started_at) = DELIVERING.get() {
+        if now < started_at.saturating_add(stale_after) {
+            // Checked again in case the other run trapped and never re-arms.
+            schedule(started_at.saturating_add(stale_after));
+            return;
+        }
+    }
+    DELIVERING.set(Some(now));
+    let due: Vec<Delivery> = QUEUE.with(|q| {
+        q.borrow()
+            .iter()
+            .map(|(_, delivery)| delivery)
+            .filter(|delivery| delivery.next_attempt_at <= now)
+            .take(MAX_SENT_PER_RUN)
+            .collect()
+    });
+    for mut delivery in due {
+        delivery.attempts += 1;
+        delivery.next_attempt_at = now.saturating_add(backoff(delivery.attempts));
+        QUEUE.with(|q| q.borrow_mut().insert(delivery.id, delivery.clone()));
+        match send(&delivery).await {
+            Ok(()) => {
+                QUEUE.with(|q| q.borrow_mut().remove(&delivery.id));
+            }
+            Err(mut error) => {
+                error.truncate(MAX_ERROR_BYTES);
+                delivery.last_error = Some(error);
+                if delivery.attempts >= MAX_ATTEMPTS {
+                    QUEUE.with(|q| q.borrow_mut().remove(&delivery.id));
+                    bury(delivery);
+                } else {
+                    QUEUE.with(|q| q.borrow_mut().insert(delivery.id, delivery));
+                }
+            }
+        }
+    }
+    DELIVERING.set(None);
+    reschedule();
+}
+
+// Replicas see different headers and bodies, which would keep them from agreeing on the
+// response; only the status is kept. Left out of the .did, since only the management
+// canister calls it.
+#[ic_cdk::query]
+fn webhook_transform(args: TransformArgs) -> OutcallResponse {
+    OutcallResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: Vec::new(),
+    }
+}
+
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn register_webhook(url: String, events: Vec<WebhookEvent>) -> Result<u64, VoteError> {
+    roles::require_admin()?;
+    validate_url(&url)?;
+    validation::item_count("events", events.len(), 3)?;
+    let (count, next_id) = WEBHOOKS.with(|w| {
+        let webhooks = w.borrow();
+        let next_id = webhooks.iter().last().map_or(0, |(id, _)| id + 1);
+        (webhooks.len(), next_id)
+    });
+    if count >= MAX_WEBHOOKS {
+        return Err(ErrorCode::InvalidItemCount.with_message(format!(
+            "at most {} webhooks can be registered",
+            MAX_WEBHOOKS
+        )));
+    }
+    let mut unique = Vec::new();
+    for event in events {
+        if !unique.contains(&event) {
+            unique.push(event);
+        }
+    }
+    let webhook = Webhook {
+        url,
+        events: unique,
+        registered_at: env::time(),
+    };
+    WEBHOOKS.with(|w| w.borrow_mut().insert(next_id, webhook));
+    Ok(next_id)
+}
+
+// Queued deliveries for it fail on their next attempt and end up with the dead letters.
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn unregister_webhook(id: u64) -> Result<(), VoteError> {
+    roles::require_admin()?;
+    WEBHOOKS.with(|w| w.borrow_mut().remove(&id));
+    Ok(())
+}
+
+// Webhook URLs often embed a secret token, so they are not public.
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_webhooks() -> Result<Vec<(u64, Webhook)>, VoteError> {
+    roles::require_admin()?;
+    Ok(WEBHOOKS.with(|w| w.borrow().iter().collect()))
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DeliveryPage {
+    pub deliveries: Vec<Delivery>,
+    pub next: Option<u64>,
+}
+
+pub fn dead_letter_page(after: Option<u64>, page: usize) -> Result<DeliveryPage, VoteError> {
+    roles::require_admin()?;
+    let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+    let (deliveries, next) = DEAD_LETTERS.with(|d| {
+        config::scan_page(
+            d.borrow().range((start, Bound::Unbounded)),
+            page,
+            |_, delivery| Some(delivery),
+        )
+    });
+    Ok(DeliveryPage { deliveries, next })
+}
+
+#[ic_cdk::query]
+#[candid::candid_method(query)]
+fn list_dead_letters_v2(after: Option<u64>, limit: u64) -> Result<DeliveryPage, VoteError> {
+    dead_letter_page(after, config::page_limit(limit))
+}
+
+// Starts the delivery over with a fresh set of attempts.
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn retry_dead_letter(id: u64) -> Result<(), VoteError> {
+    roles::require_admin()?;
+    let Some(delivery) = DEAD_LETTERS.with(|d| d.borrow_mut().remove(&id)) else {
+        return Err(ErrorCode::NoSuchDelivery.into());
+    };
+    let now = env::time();
+    let delivery = Delivery {
+        attempts: 0,
+        next_attempt_at: now,
+        ..delivery
+    };
+    QUEUE.with(|q| q.borrow_mut().insert(delivery.id, delivery));
+    schedule(now);
+    Ok(())
+}
@@ -0,0 +1,208 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::icrc1::{self, Account};
+use crate::{
+    ballots, config, env, identity, load_proposal, memory, principal_key, secret, store_proposal,
+    validation, Choice, ErrorCode, Memory, PrincipalKey, Proposal, ProposalKind, VoteError,
+    ASSIGNED_WEIGHTS_MEMORY_ID, VOTE_WEIGHTS_MEMORY_ID,
+};
+
+const MAX_WEIGHTS_PER_CALL: usize = 500;
+// Voters left out of an owner's table still count once.
+const DEFAULT_WEIGHT: u64 = 1;
+
+// Tallies of token-weighted proposals, next to the one-per-voter counts.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Default)]
+pub struct WeightedTally {
+    pub approve: u128,
+    pub reject: u128,
+    pub pass: u128,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Default)]
+struct VoteWeight(u128);
+
+candid_storable!(VoteWeight, 32);
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Default)]
+struct AssignedWeight(u64);
+
+candid_storable!(AssignedWeight, 24);
+
+thread_local! {
+    static WEIGHTS: RefCell<StableBTreeMap<(u64, PrincipalKey), VoteWeight, Memory>> = RefCell::new(StableBTreeMap::init(memory(VOTE_WEIGHTS_MEMORY_ID)));
+
+    // Weights uploaded by the owner, for proposals without a ledger.
+    static ASSIGNED: RefCell<StableBTreeMap<(u64, PrincipalKey), AssignedWeight, Memory>> = RefCell::new(StableBTreeMap::init(memory(ASSIGNED_WEIGHTS_MEMORY_ID)));
+}
+
+// The balance at the time of the vote; tokens moved afterwards can be voted again
+// from another account, so weighted proposals should run on a locked or snapshot ledger.
+pub async fn balance_of(ledger: Principal, voter: Principal) -> Result<u128, VoteError> {
+    let account = Account {
+        owner: voter,
+        subaccount: None,
+    };
+    let balance = icrc1::balance_of(ledger, account)
+        .await
+        .map_err(|message| ErrorCode::LedgerCallFailed.with_message(message))?;
+    Ok(u128::try_from(balance.0).unwrap_or(u128::MAX))
+}
+
+fn weight_of<'a>(tally: &'a mut WeightedTally, choice: &Choice) -> &'a mut u128 {
+    match choice {
+        Choice::Approve => &mut tally.approve,
+        Choice::Reject => &mut tally.reject,
+        Choice::Pass => &mut tally.pass,
+    }
+}
+
+pub fn add(proposal: &mut Proposal, choice: &Choice, weight: u128) {
+    let tally = proposal.weights.get_or_insert_with(WeightedTally::default);
+    let total = weight_of(tally, choice);
+    *total = total.saturating_add(weight);
+}
+
+pub fn subtract(proposal: &mut Proposal, choice: &Choice, weight: u128) {
+    if let Some(tally) = proposal.weights.as_mut() {
+        let total = weight_of(tally, choice);
+        *total = total.saturating_sub(weight);
+    }
+}
+
+pub fn record(key: u64, voter: &Principal, weight: u128) {
+    WEIGHTS.with(|w| {
+        w.borrow_mut()
+            .insert((key, principal_key(voter)), VoteWeight(weight))
+    });
+}
+
+pub fn of(key: u64, voter: &Principal) -> Option<u128> {
+    WEIGHTS.with(|w| w.borrow().get(&(key, principal_key(voter))).map(|w| w.0))
+}
+
+pub fn remove(key: u64, voter: &Principal) {
+    WEIGHTS.with(|w| w.borrow_mut().remove(&(key, principal_key(voter))));
+}
+
+// The weights votes were counted with; the owner's or snapshot's table stays, so a
+// reopened proposal is counted against it again.
+pub fn clear_cast(key: u64) {
+    WEIGHTS.with(|w| {
+        let mut weights = w.borrow_mut();
+        let voters: Vec<PrincipalKey> = weights
+            .range((key, PrincipalKey::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|((_, voter), _)| voter)
+            .collect();
+        for voter in voters {
+            weights.remove(&(key, voter));
+        }
+    });
+}
+
+pub fn clear(key: u64) {
+    clear_cast(key);
+    ASSIGNED.with(|a| {
+        let mut assigned = a.borrow_mut();
+        let voters: Vec<PrincipalKey> = assigned
+            .range((key, PrincipalKey::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|((_, voter), _)| voter)
+            .collect();
+        for voter in voters {
+            assigned.remove(&(key, voter));
+        }
+    });
+}
+
+// The weight a vote adds on a proposal with an owner-assigned table; ledger-weighted
+// proposals ask the ledger instead.
+pub fn assigned(key: u64, voter: &Principal) -> u128 {
+    let weight = ASSIGNED
+        .with(|a| a.borrow().get(&(key, principal_key(voter))))
+        .map_or(DEFAULT_WEIGHT, |w| w.0);
+    weight as u128
+}
+
+// Can be called several times to upload a large table, but only until the first vote,
+// so every ballot is counted against the same table.
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn set_voter_weights(key: u64, table: Vec<(Principal, u64)>) -> Result<(), VoteError> {
+    config::ensure_not_paused()?;
+    let caller = env::authenticated_caller()?;
+    let mut proposal = load_proposal(key)?;
+    if proposal.owner != caller {
+        return Err(ErrorCode::AccessRejected.into());
+    }
+    if !proposal.is_active {
+        return Err(ErrorCode::ProposalIsNotActive.into());
+    }
+    if ballots::has_voters(key) {
+        return Err(ErrorCode::InvalidProposal
+            .with_message("voter weights cannot change once voting has started"));
+    }
+    if proposal.weight_ledger.is_some() || proposal.snapshot.is_some() {
+        return Err(ErrorCode::InvalidProposal
+            .with_message("this proposal is already weighted by a ledger or snapshot"));
+    }
+    if proposal.options.is_some()
+        || secret::is_secret(&proposal)
+        || matches!(
+            proposal.kind,
+            Some(ProposalKind::Election(_) | ProposalKind::Signaling)
+        )
+    {
+        return Err(ErrorCode::InvalidProposal
+            .with_message("only approve, reject or pass proposals can be weighted"));
+    }
+    validation::item_count("table", table.len(), MAX_WEIGHTS_PER_CALL)?;
+    if proposal.weights.is_none() {
+        proposal.weights = Some(WeightedTally::default());
+        store_proposal(key, &proposal)?;
+    }
+    assign_all(key, &table);
+    Ok(())
+}
+
+pub fn assign_all(key: u64, table: &[(Principal, u64)]) {
+    ASSIGNED.with(|a| {
+        let mut assigned = a.borrow_mut();
+        for (voter, weight) in table {
+            assigned.insert(
+                (key, principal_key(&identity::canonical(voter))),
+                AssignedWeight(*weight),
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{as_caller, principal};
+
+    #[test]
+    fn clearing_cast_weights_keeps_the_table() {
+        let voter = principal(2);
+        assign_all(1, &[(voter, 7)]);
+        record(1, &voter, 7);
+        clear_cast(1);
+        assert_eq!(of(1, &voter), None);
+        assert_eq!(assigned(1, &voter), 7);
+        clear(1);
+        assert_eq!(assigned(1, &voter), DEFAULT_WEIGHT as u128);
+    }
+
+    #[test]
+    fn a_table_naming_a_linked_principal_weights_its_canonical_identity() {
+        as_caller(1);
+        let canonical = principal(2);
+        identity::link(&principal(3), &canonical);
+        assign_all(1, &[(principal(3), 5)]);
+        assert_eq!(assigned(1, &canonical), 5);
+    }
+}
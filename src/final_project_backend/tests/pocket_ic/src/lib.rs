@@ -0,0 +1,197 @@
+// Installs the backend wasm in a fresh PocketIC instance and wraps the calls the
+// lifecycle tests make. The wasm must be built with `--features test-mode`.
+use candid::utils::ArgumentEncoder;
+use candid::{decode_one, encode_args, encode_one, CandidType, Deserialize, Principal};
+use pocket_ic::{PocketIc, WasmResult};
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+const CYCLES: u128 = 2_000_000_000_000;
+
+pub fn principal(id: u8) -> Principal {
+    Principal::from_slice(&[id; 29])
+}
+
+// The controller, which alone may mock the clock.
+pub fn controller() -> Principal {
+    principal(100)
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum Choice {
+    Approve,
+    Reject,
+    Pass,
+}
+
+// Only the fields the suite sets; the backend reads the rest as absent.
+#[derive(CandidType, Clone, Debug, Default)]
+pub struct CreateProposal {
+    pub description: String,
+    pub is_active: bool,
+    pub deadline_ns: Option<u64>,
+}
+
+impl CreateProposal {
+    pub fn open(description: &str) -> Self {
+        CreateProposal {
+            description: description.to_string(),
+            is_active: true,
+            deadline_ns: None,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct Proposal {
+    pub description: String,
+    pub owner: Principal,
+    pub approve: u32,
+    pub reject: u32,
+    pub pass: u32,
+    pub is_active: bool,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct CreatedProposal {
+    key: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct ErrorInfo {
+    pub code: u16,
+    pub message: String,
+}
+
+// The errors the suite expects; any other one fails to decode, which fails the test.
+#[derive(CandidType, Deserialize, Debug)]
+pub enum VoteError {
+    AccessRejected(ErrorInfo),
+    AlreadyVoted(ErrorInfo),
+    ProposalClosed(ErrorInfo),
+    ProposalIsNotActive(ErrorInfo),
+}
+
+pub struct Backend {
+    pic: PocketIc,
+    id: Principal,
+}
+
+fn wasm() -> Vec<u8> {
+    let path = std::env::var_os("BACKEND_WASM")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(
+                "../../../../target/wasm32-unknown-unknown/release/final_project_backend.wasm",
+            )
+        });
+    std::fs::read(&path).unwrap_or_else(|error| {
+        panic!(
+            "cannot read the backend wasm at {} ({}); build it with --features test-mode or set BACKEND_WASM",
+            path.display(),
+            error
+        )
+    })
+}
+
+impl Backend {
+    pub fn install() -> Self {
+        let pic = PocketIc::new();
+        let id = pic.create_canister_with_settings(Some(controller()), None);
+        pic.add_cycles(id, CYCLES);
+        pic.install_canister(
+            id,
+            wasm(),
+            encode_one(None::<()>).unwrap(),
+            Some(controller()),
+        );
+        Backend { pic, id }
+    }
+
+    fn update<T: for<'de> Deserialize<'de> + CandidType>(
+        &self,
+        sender: Principal,
+        method: &str,
+        args: impl ArgumentEncoder,
+    ) -> T {
+        let reply = self
+            .pic
+            .update_call(self.id, sender, method, encode_args(args).unwrap())
+            .unwrap_or_else(|error| panic!("{} failed: {:?}", method, error));
+        match reply {
+            WasmResult::Reply(bytes) => decode_one(&bytes).unwrap(),
+            WasmResult::Reject(message) => panic!("{} was rejected: {}", method, message),
+        }
+    }
+
+    fn query<T: for<'de> Deserialize<'de> + CandidType>(
+        &self,
+        method: &str,
+        args: impl ArgumentEncoder,
+    ) -> T {
+        let reply = self
+            .pic
+            .query_call(
+                self.id,
+                Principal::anonymous(),
+                method,
+                encode_args(args).unwrap(),
+            )
+            .unwrap_or_else(|error| panic!("{} failed: {:?}", method, error));
+        match reply {
+            WasmResult::Reply(bytes) => decode_one(&bytes).unwrap(),
+            WasmResult::Reject(message) => panic!("{} was rejected: {}", method, message),
+        }
+    }
+
+    pub fn create(&self, owner: Principal, proposal: CreateProposal) -> Result<u64, VoteError> {
+        self.update::<Result<CreatedProposal, VoteError>>(owner, "create_proposal_v3", (proposal,))
+            .map(|created| created.key)
+    }
+
+    pub fn edit(
+        &self,
+        caller: Principal,
+        key: u64,
+        proposal: CreateProposal,
+    ) -> Result<(), VoteError> {
+        self.update(caller, "edit_proposal_v2", (key, proposal))
+    }
+
+    pub fn vote(&self, voter: Principal, key: u64, choice: Choice) -> Result<(), VoteError> {
+        self.update(voter, "vote_v2", (key, choice))
+    }
+
+    pub fn end(&self, caller: Principal, key: u64) -> Result<(), VoteError> {
+        self.update(caller, "end_proposal_v2", (key,))
+    }
+
+    pub fn proposal(&self, key: u64) -> Proposal {
+        self.query::<Option<Proposal>>("get_proposal_v2", (key,))
+            .expect("the proposal exists")
+    }
+
+    pub fn set_mock_time(&self, caller: Principal, nanos: Option<u64>) -> Result<(), VoteError> {
+        self.update(caller, "set_mock_time", (nanos,))
+    }
+
+    // The replica's clock, which the backend reads unless it is mocked.
+    pub fn now(&self) -> u64 {
+        self.pic
+            .get_time()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64
+    }
+
+    pub fn advance_time(&self, by: Duration) {
+        self.pic.advance_time(by);
+    }
+
+    // Gives due timers a few rounds to run.
+    pub fn tick(&self) {
+        for _ in 0..3 {
+            self.pic.tick();
+        }
+    }
+}
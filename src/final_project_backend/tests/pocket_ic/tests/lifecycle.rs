@@ -0,0 +1,97 @@
+use final_project_backend_pocket_ic::{
+    controller, principal, Backend, Choice, CreateProposal, VoteError,
+};
+use std::time::Duration;
+
+const HOUR: Duration = Duration::from_secs(3_600);
+
+#[test]
+fn only_the_owner_edits_and_ends_a_proposal() {
+    let backend = Backend::install();
+    let (owner, other) = (principal(1), principal(2));
+    let key = backend
+        .create(owner, CreateProposal::open("original"))
+        .unwrap();
+
+    assert!(matches!(
+        backend.edit(other, key, CreateProposal::open("hijacked")),
+        Err(VoteError::AccessRejected(_))
+    ));
+    backend
+        .edit(owner, key, CreateProposal::open("edited"))
+        .unwrap();
+    assert_eq!(backend.proposal(key).description, "edited");
+    assert_eq!(backend.proposal(key).owner, owner);
+
+    assert!(matches!(
+        backend.end(other, key),
+        Err(VoteError::AccessRejected(_))
+    ));
+    backend.end(owner, key).unwrap();
+    assert!(!backend.proposal(key).is_active);
+}
+
+#[test]
+fn each_identity_votes_once_and_the_tally_adds_up() {
+    let backend = Backend::install();
+    let key = backend
+        .create(principal(1), CreateProposal::open("tally"))
+        .unwrap();
+
+    backend.vote(principal(2), key, Choice::Approve).unwrap();
+    backend.vote(principal(3), key, Choice::Approve).unwrap();
+    backend.vote(principal(4), key, Choice::Reject).unwrap();
+    backend.vote(principal(5), key, Choice::Pass).unwrap();
+    assert!(matches!(
+        backend.vote(principal(2), key, Choice::Reject),
+        Err(VoteError::AlreadyVoted(_))
+    ));
+
+    let proposal = backend.proposal(key);
+    assert_eq!(
+        (proposal.approve, proposal.reject, proposal.pass),
+        (2, 1, 1)
+    );
+
+    backend.end(principal(1), key).unwrap();
+    assert!(matches!(
+        backend.vote(principal(6), key, Choice::Approve),
+        Err(VoteError::ProposalClosed(_))
+    ));
+}
+
+#[test]
+fn a_proposal_closes_itself_at_its_deadline() {
+    let backend = Backend::install();
+    let mut with_deadline = CreateProposal::open("deadline");
+    with_deadline.deadline_ns = Some(backend.now() + HOUR.as_nanos() as u64);
+    let key = backend.create(principal(1), with_deadline).unwrap();
+    backend.vote(principal(2), key, Choice::Approve).unwrap();
+
+    backend.advance_time(HOUR + Duration::from_secs(1));
+    backend.tick();
+    assert!(!backend.proposal(key).is_active);
+    assert!(matches!(
+        backend.vote(principal(3), key, Choice::Approve),
+        Err(VoteError::ProposalClosed(_))
+    ));
+}
+
+#[test]
+fn the_mocked_clock_runs_deadlines_without_waiting() {
+    let backend = Backend::install();
+    let deadline = backend.now() + HOUR.as_nanos() as u64;
+    let mut with_deadline = CreateProposal::open("mocked");
+    with_deadline.deadline_ns = Some(deadline);
+    let key = backend.create(principal(1), with_deadline).unwrap();
+
+    assert!(matches!(
+        backend.set_mock_time(principal(1), Some(deadline + 1)),
+        Err(VoteError::AccessRejected(_))
+    ));
+    backend
+        .set_mock_time(controller(), Some(deadline + 1))
+        .unwrap();
+    backend.tick();
+    assert!(!backend.proposal(key).is_active);
+}